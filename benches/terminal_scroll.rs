@@ -0,0 +1,46 @@
+//! Scrolling the terminal window contents with the function `Console::scroll_pixels` uses, on a window of 1024x768
+//! pixels with rows of 16.
+//!
+//! Run with `cargo bench --bench terminal_scroll` on a nightly toolchain.
+
+#![feature(test)]
+
+extern crate test;
+
+use test::{black_box, Bencher};
+
+#[path = "../src/terminal/scroll.rs"]
+mod scroll;
+
+const WIDTH: usize = 1024;
+const HEIGHT: usize = 768;
+const ROW: usize = 16;
+
+fn window() -> Vec<u32> {
+    (0..WIDTH * HEIGHT).map(|i| i as u32).collect()
+}
+
+/// One row at a time, as a stream of lines scrolled before every redraw
+#[bench]
+fn scroll_row(b: &mut Bencher) {
+    let mut data = window();
+    b.bytes = (WIDTH * HEIGHT * 4) as u64;
+    b.iter(|| scroll::scroll_pixels(black_box(&mut data), WIDTH, HEIGHT, ROW, 0xFF000000));
+}
+
+/// Most of the screen at once, as when a frame's worth of output is scrolled in a single redraw
+#[bench]
+fn scroll_page(b: &mut Bencher) {
+    let mut data = window();
+    b.bytes = (WIDTH * HEIGHT * 4) as u64;
+    b.iter(|| scroll::scroll_pixels(black_box(&mut data), WIDTH, HEIGHT, HEIGHT - ROW, 0xFF000000));
+}
+
+/// A thousand lines of output, scrolled a row at a time like a large `cat`
+#[bench]
+fn scroll_lines(b: &mut Bencher) {
+    let mut data = window();
+    b.iter(|| for _ in 0..1000 {
+        scroll::scroll_pixels(black_box(&mut data), WIDTH, HEIGHT, ROW, 0xFF000000);
+    });
+}
@@ -0,0 +1,178 @@
+/// What a server certificate says about itself, shown before the user trusts it
+#[derive(Debug, PartialEq)]
+pub struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    /// SHA-256 of the whole certificate, as pairs of hex digits separated by colons
+    pub fingerprint: String,
+}
+
+/// Read the subject and issuer of a DER encoded X.509 certificate
+pub fn parse(der: &[u8]) -> Option<Certificate> {
+    let (_, certificate, _) = tlv(der)?;
+    let (_, tbs, _) = tlv(certificate)?;
+
+    // The version is only there when it is tagged [0]
+    let mut rest = tbs;
+    if rest.first() == Some(&0xA0) {
+        rest = tlv(rest)?.2;
+    }
+    // Serial number and signature algorithm
+    rest = tlv(rest)?.2;
+    rest = tlv(rest)?.2;
+    let (_, issuer, rest) = tlv(rest)?;
+    // Validity
+    let rest = tlv(rest)?.2;
+    let (_, subject, _) = tlv(rest)?;
+
+    Some(Certificate {
+        subject: name(subject)?,
+        issuer: name(issuer)?,
+        fingerprint: sha256(der).iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(":"),
+    })
+}
+
+/// Split off the first DER value, returning its tag, its contents and what follows it
+fn tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.get(0)?;
+    let first = *data.get(1)? as usize;
+    let (len, start) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let bytes = first & 0x7F;
+        if bytes == 0 || bytes > 4 || data.len() < 2 + bytes {
+            return None;
+        }
+        (data[2..2 + bytes].iter().fold(0, |len, &b| len << 8 | b as usize), 2 + bytes)
+    };
+    if data.len() - start < len {
+        return None;
+    }
+    Some((tag, &data[start..start + len], &data[start + len..]))
+}
+
+/// A distinguished name as `CN=..., O=...`, with the attributes people read
+fn name(mut rdns: &[u8]) -> Option<String> {
+    let mut parts = Vec::new();
+    while ! rdns.is_empty() {
+        let (_, set, rest) = tlv(rdns)?;
+        rdns = rest;
+
+        let mut attributes = set;
+        while ! attributes.is_empty() {
+            let (_, attribute, rest) = tlv(attributes)?;
+            attributes = rest;
+
+            let (_, oid, value) = tlv(attribute)?;
+            let (tag, value, _) = tlv(value)?;
+            // Attribute types under 2.5.4
+            if oid.len() != 3 || oid[0] != 0x55 || oid[1] != 0x04 {
+                continue;
+            }
+            let key = match oid[2] {
+                0x03 => "CN",
+                0x06 => "C",
+                0x07 => "L",
+                0x08 => "ST",
+                0x0A => "O",
+                0x0B => "OU",
+                _ => continue
+            };
+            // UTF8String, PrintableString, T61String and IA5String, which are read as UTF-8
+            match tag {
+                0x0C | 0x13 | 0x14 | 0x16 => parts.push(format!("{}={}", key, String::from_utf8_lossy(value))),
+                _ => ()
+            }
+        }
+    }
+    Some(parts.join(", "))
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    for i in 0..8 {
+        message.push((bits >> (56 - i * 8)) as u8);
+    }
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (chunk[i * 4] as u32) << 24 | (chunk[i * 4 + 1] as u32) << 16 | (chunk[i * 4 + 2] as u32) << 8 | chunk[i * 4 + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let mut v = h;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (! v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+        for i in 0..8 {
+            h[i] = h[i].wrapping_add(v[i]);
+        }
+    }
+
+    let mut out = [0; 32];
+    for i in 0..8 {
+        for j in 0..4 {
+            out[i * 4 + j] = (h[i] >> (24 - j * 8)) as u8;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use super::{parse, sha256, Certificate};
+
+    #[test]
+    fn digest() {
+        let hex = |digest: [u8; 32]| digest.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        assert_eq!(hex(sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(sha256(&[b'a'; 100])), "2816597888e4a0d3a36b82b83316ab32680eb8f00f8cd3b904d681246d285a0e");
+    }
+
+    #[test]
+    fn certificate() {
+        let mut der = Vec::new();
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tst").join("browser").join("certificate.der");
+        File::open(path).unwrap().read_to_end(&mut der).unwrap();
+
+        assert_eq!(parse(&der), Some(Certificate {
+            subject: "CN=example.com, O=Example".to_string(),
+            issuer: "CN=Example Root, O=Example".to_string(),
+            fingerprint: "45:0D:1D:A2:60:7E:68:57:4D:C3:92:C0:BF:9D:DB:E9:6B:C6:C0:70:B1:94:44:86:A6:89:81:2C:0C:F0:76:8D".to_string(),
+        }));
+        assert_eq!(parse(&der[..der.len() / 2]), None);
+    }
+}
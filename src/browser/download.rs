@@ -0,0 +1,167 @@
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use hyper::client::Response;
+use hyper::header::{ContentDisposition, ContentLength, DispositionParam, Headers};
+use url::Url;
+
+#[derive(Clone)]
+pub enum Status {
+    /// Still receiving, with the number of bytes written so far
+    Running(u64),
+    /// Completed, with the total number of bytes written
+    Finished(u64),
+    /// Stopped because of an error
+    Failed(String),
+}
+
+/// A response body being saved to disk on a background thread
+pub struct Download {
+    pub url: Url,
+    pub path: PathBuf,
+    pub total: Option<u64>,
+    status: Arc<Mutex<Status>>,
+}
+
+impl Download {
+    pub fn new(url: Url, mut response: Response, path: PathBuf) -> Download {
+        let total = response.headers.get::<ContentLength>().map(|length| length.0);
+        let status = Arc::new(Mutex::new(Status::Running(0)));
+
+        {
+            let status = status.clone();
+            let path = path.clone();
+            thread::spawn(move || {
+                let result = save(&mut response, &path, &status);
+                *status.lock().unwrap() = match result {
+                    Ok(count) => Status::Finished(count),
+                    Err(err) => Status::Failed(err)
+                };
+            });
+        }
+
+        Download {
+            url: url,
+            path: path,
+            total: total,
+            status: status,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn running(&self) -> bool {
+        match self.status() {
+            Status::Running(_) => true,
+            _ => false
+        }
+    }
+
+    /// One line summary for the downloads page
+    pub fn description(&self) -> String {
+        let name = self.path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or(String::new());
+        match self.status() {
+            Status::Running(count) => match self.total {
+                Some(total) if total > 0 => format!("{}: {}% ({} of {})", name, count * 100 / total, format_size(count), format_size(total)),
+                _ => format!("{}: {}", name, format_size(count))
+            },
+            Status::Finished(count) => format!("{}: finished, {}", name, format_size(count)),
+            Status::Failed(err) => format!("{}: failed, {}", name, err)
+        }
+    }
+}
+
+fn save<R: Read>(r: &mut R, path: &Path, status: &Mutex<Status>) -> Result<u64, String> {
+    let mut file = File::create(path).map_err(|err| format!("Failed to create {}: {}", path.display(), err))?;
+
+    let mut count = 0;
+    let mut buf = [0; 65536];
+    loop {
+        let read = r.read(&mut buf).map_err(|err| format!("Failed to read response: {}", err))?;
+        if read == 0 {
+            break;
+        }
+
+        file.write_all(&buf[..read]).map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+
+        count += read as u64;
+        *status.lock().unwrap() = Status::Running(count);
+    }
+
+    Ok(count)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 * 1024 {
+        format!("{:.1} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+    } else if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Only the last component of a suggested name is used, so a server cannot pick the directory
+fn sanitize(name: &str) -> Option<String> {
+    match name.rsplit(|c| c == '/' || c == '\\').next() {
+        Some("") | Some(".") | Some("..") | None => None,
+        Some(part) => Some(part.to_string())
+    }
+}
+
+/// Pick a file name from the Content-Disposition header, falling back to the last URL segment
+pub fn file_name(headers: &Headers, url: &Url) -> String {
+    if let Some(disposition) = headers.get::<ContentDisposition>() {
+        for param in disposition.parameters.iter() {
+            if let DispositionParam::Filename(_, _, ref bytes) = *param {
+                if let Some(name) = sanitize(&String::from_utf8_lossy(bytes)) {
+                    return name;
+                }
+            }
+        }
+    }
+
+    if let Some(segments) = url.path_segments() {
+        if let Some(name) = segments.last().and_then(sanitize) {
+            return name;
+        }
+    }
+
+    "download".to_string()
+}
+
+/// Location in the downloads directory for a new file, not overwriting existing ones
+pub fn download_path(name: &str) -> PathBuf {
+    let dir = match env::var("HOME") {
+        Ok(home) => {
+            let downloads = Path::new(&home).join("Downloads");
+            if downloads.is_dir() {
+                downloads
+            } else {
+                PathBuf::from(home)
+            }
+        },
+        Err(_) => PathBuf::from(".")
+    };
+
+    let (stem, extension) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name, "")
+    };
+
+    let mut path = dir.join(name);
+    let mut i = 1;
+    while path.exists() {
+        path = dir.join(format!("{} ({}){}", stem, i, extension));
+        i += 1;
+    }
+    path
+}
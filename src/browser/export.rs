@@ -0,0 +1,558 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use orbclient::{Color, Renderer};
+use orbfont::Font;
+use orbimage::Image;
+use url::Url;
+
+use super::Block;
+
+/// Size of the whole document, not just what fits in the window
+fn document_size(blocks: &[Block], width: i32) -> (i32, i32) {
+    let mut size = (width, 1);
+    for block in blocks.iter() {
+        if block.x + block.w > size.0 {
+            size.0 = block.x + block.w;
+        }
+        if block.y + block.h > size.1 {
+            size.1 = block.y + block.h;
+        }
+    }
+    size
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for &byte in data.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1;
+    let mut b = 0;
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Lengths of the deflate length codes 257 to 285, and the extra bits after each
+const LENGTH_BASE: [usize; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u32; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+/// Distances of the deflate distance codes, and the extra bits after each
+const DIST_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193,
+    12289, 16385, 24577
+];
+const DIST_EXTRA: [u32; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// How far back matches are looked for, the most deflate allows
+const WINDOW: usize = 32768;
+/// Earlier positions tried for each match, trading a little size for speed on long pages
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: usize = 15;
+
+/// Bits packed into bytes starting from the lowest, as deflate writes them
+struct Bits {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl Bits {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which goes out starting from its highest bit
+    fn code(&mut self, code: u32, count: u32) {
+        let mut reversed = 0;
+        for i in 0..count {
+            reversed |= ((code >> i) & 1) << (count - 1 - i);
+        }
+        self.write(reversed, count);
+    }
+
+    /// A symbol of the fixed literal and length code
+    fn symbol(&mut self, symbol: u32) {
+        match symbol {
+            0 ... 143 => self.code(0x30 + symbol, 8),
+            144 ... 255 => self.code(0x190 + symbol - 144, 9),
+            256 ... 279 => self.code(symbol - 256, 7),
+            _ => self.code(0xC0 + symbol - 280, 8)
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// Compress data with deflate in a single block of the fixed Huffman codes, with matches found through chains of
+/// earlier positions that start with the same three bytes
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = Bits { out: Vec::with_capacity(data.len() / 4), bits: 0, count: 0 };
+    // Last block, fixed codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let hash = |i: usize| -> usize {
+        (((data[i] as usize) << 10) ^ ((data[i + 1] as usize) << 5) ^ data[i + 2] as usize) & ((1 << HASH_BITS) - 1)
+    };
+    // Latest position for each hash, and the one before it with the same hash for each position in the window, plus one
+    let mut head = vec![0usize; 1 << HASH_BITS];
+    let mut prev = vec![0usize; WINDOW];
+    let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize| {
+        if i + 2 < data.len() {
+            let h = hash(i);
+            prev[i % WINDOW] = head[h];
+            head[h] = i + 1;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + 2 < data.len() {
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate > 0 && chain < MAX_CHAIN {
+                let start = candidate - 1;
+                if i - start > WINDOW - 1 {
+                    break;
+                }
+                let max = cmp::min(258, data.len() - i);
+                let mut len = 0;
+                while len < max && data[start + len] == data[i + len] {
+                    len += 1;
+                }
+                if len > best.0 {
+                    best = (len, i - start);
+                    if len == max {
+                        break;
+                    }
+                }
+                candidate = prev[start % WINDOW];
+                chain += 1;
+            }
+        }
+
+        let (len, dist) = best;
+        if len >= 3 {
+            let code = LENGTH_BASE.iter().rposition(|&base| base <= len).unwrap();
+            bits.symbol(257 + code as u32);
+            bits.write((len - LENGTH_BASE[code]) as u32, LENGTH_EXTRA[code]);
+            let code = DIST_BASE.iter().rposition(|&base| base <= dist).unwrap();
+            bits.code(code as u32, 5);
+            bits.write((dist - DIST_BASE[code]) as u32, DIST_EXTRA[code]);
+            for j in i..i + len {
+                insert(&mut head, &mut prev, j);
+            }
+            i += len;
+        } else {
+            bits.symbol(data[i] as u32);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+
+    bits.symbol(256);
+    bits.finish()
+}
+
+/// Data in stored deflate blocks, for what does not get any smaller
+fn stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 5);
+    let mut chunks = data.chunks(65535).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(if last { 1 } else { 0 });
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+/// Wrap data in a zlib stream, which both PNG and PDF readers accept, compressed unless that makes it larger
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let compressed = deflate(data);
+    if compressed.len() < data.len() {
+        out.extend_from_slice(&compressed);
+    } else {
+        out.extend_from_slice(&stored(data));
+    }
+
+    let adler = adler32(data);
+    out.extend_from_slice(&[(adler >> 24) as u8, (adler >> 16) as u8, (adler >> 8) as u8, adler as u8]);
+    out
+}
+
+fn be32(value: u32) -> [u8; 4] {
+    [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    out.extend_from_slice(&be32(data.len() as u32));
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&be32(crc));
+}
+
+fn rgb(color: Color) -> [u8; 3] {
+    [(color.data >> 16) as u8, (color.data >> 8) as u8, color.data as u8]
+}
+
+/// Render the whole document into a single image
+pub fn render(blocks: &[Block], width: i32) -> Image {
+    let (w, h) = document_size(blocks, width);
+    let mut image = Image::new(w as u32, h as u32);
+    image.set(Color::rgb(255, 255, 255));
+    for block in blocks.iter() {
+        block.draw(&mut image, (0, 0));
+    }
+    image
+}
+
+/// Export the document as one tall PNG
+pub fn png(blocks: &[Block], width: i32, path: &Path) -> Result<(), String> {
+    let image = render(blocks, width);
+    let (w, h) = (image.width(), image.height());
+
+    let mut raw = Vec::with_capacity((w as usize * 3 + 1) * h as usize);
+    for row in image.data().chunks(w as usize) {
+        raw.push(0);
+        for &color in row.iter() {
+            raw.extend_from_slice(&rgb(color));
+        }
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&be32(w));
+    header.extend_from_slice(&be32(h));
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut out = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    png_chunk(&mut out, b"IHDR", &header);
+    png_chunk(&mut out, b"IDAT", &zlib(&raw));
+    png_chunk(&mut out, b"IEND", &[]);
+
+    let mut file = File::create(path).map_err(|err| format!("Failed to create {}: {}", path.display(), err))?;
+    file.write_all(&out).map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+/// Objects of a PDF file, numbered from one in the order they are added
+struct Pdf {
+    objects: Vec<Vec<u8>>,
+}
+
+impl Pdf {
+    fn reserve(&mut self) -> usize {
+        self.objects.push(Vec::new());
+        self.objects.len()
+    }
+
+    fn set(&mut self, id: usize, object: Vec<u8>) {
+        self.objects[id - 1] = object;
+    }
+
+    fn add(&mut self, object: Vec<u8>) -> usize {
+        let id = self.reserve();
+        self.set(id, object);
+        id
+    }
+
+    fn add_stream(&mut self, dict: &str, data: &[u8]) -> usize {
+        let mut object = format!("<< {} /Length {} >>\nstream\n", dict, data.len()).into_bytes();
+        object.extend_from_slice(data);
+        object.extend_from_slice(b"\nendstream");
+        self.add(object)
+    }
+
+    fn write(&self, root: usize) -> Vec<u8> {
+        let mut out = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(object);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+
+        let xref = out.len();
+        out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1).as_bytes());
+        for offset in offsets.iter() {
+            out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        out.extend_from_slice(format!("trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n", self.objects.len() + 1, root, xref).as_bytes());
+        out
+    }
+}
+
+/// Literal string in the PDF text encoding, replacing what WinAnsi cannot show
+fn pdf_string(string: &str) -> String {
+    let mut out = String::from("(");
+    for c in string.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            },
+            ' ' ... '~' => out.push(c),
+            '\u{A0}' ... '\u{FF}' => out.push_str(&format!("\\{:03o}", c as u32)),
+            _ => out.push('?')
+        }
+    }
+    out.push(')');
+    out
+}
+
+/// TrueType files the fonts were loaded from, which PDF exports embed
+pub struct FontFiles {
+    pub regular: Option<PathBuf>,
+    pub bold: Option<PathBuf>
+}
+
+/// Add a font, embedding the TrueType file it was loaded from
+fn pdf_font(pdf: &mut Pdf, font: &Font, file: &Option<PathBuf>, style: &str) -> Result<usize, String> {
+    let path = match *file {
+        Some(ref path) => path,
+        None => return Err(format!("Failed to embed the {} font: the file it was loaded from is unknown", style))
+    };
+    let mut data = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|err| format!("Failed to embed font {}: {}", path.display(), err))?;
+
+    let name = format!("Browser{}", style);
+
+    let file = pdf.add_stream(&format!("/Length1 {} /Filter /FlateDecode", data.len()), &zlib(&data));
+    let descriptor = pdf.add(format!(
+        "<< /Type /FontDescriptor /FontName /{} /Flags 32 /FontBBox [0 -250 1000 1000] /ItalicAngle 0 /Ascent 800 /Descent -200 /CapHeight 700 /StemV 80 /FontFile2 {} 0 R >>",
+        name, file
+    ).into_bytes());
+
+    let mut widths = String::new();
+    for code in 32..256 {
+        let string = (code as u8 as char).to_string();
+        let width = if code == 32 {
+            500
+        } else {
+            font.render(&string, 1000.0).width()
+        };
+        widths.push_str(&format!("{} ", width));
+    }
+
+    Ok(pdf.add(format!(
+        "<< /Type /Font /Subtype /TrueType /BaseFont /{} /FirstChar 32 /LastChar 255 /Widths [{}] /Encoding /WinAnsiEncoding /FontDescriptor {} 0 R >>",
+        name, widths, descriptor
+    ).into_bytes()))
+}
+
+/// How far down each block goes on the pages, so that none that fits on a page is cut by the end of one.
+///
+/// Rows of blocks that would cross the end of a page start the next one instead, moving everything after them down as
+/// well. Blocks taller than a page stay where they are and are drawn on every page they cross.
+fn page_offsets(blocks: &[Block], page_h: i32) -> Vec<i32> {
+    // The tallest block of each row, in order down the document
+    let mut rows = BTreeMap::new();
+    for block in blocks.iter() {
+        let h = rows.entry(block.y).or_insert(0);
+        if block.h <= page_h && block.h > *h {
+            *h = block.h;
+        }
+    }
+
+    let mut shifts = BTreeMap::new();
+    let mut shift = 0;
+    for (&y, &h) in rows.iter() {
+        let top = y + shift;
+        let page_end = (top / page_h + 1) * page_h;
+        if top + h > page_end {
+            shift += page_end - top;
+        }
+        shifts.insert(y, shift);
+    }
+
+    blocks.iter().map(|block| shifts.get(&block.y).cloned().unwrap_or(0)).collect()
+}
+
+/// Export the document as a PDF, split into pages with the proportions of A4
+pub fn pdf(blocks: &[Block], url: &Url, font: &Font, font_bold: &Font, font_files: &FontFiles, width: i32, path: &Path) -> Result<(), String> {
+    let (w, _) = document_size(blocks, width);
+    let page_h = w * 297 / 210;
+    let offsets = page_offsets(blocks, page_h);
+    let h = blocks.iter().zip(offsets.iter()).map(|(block, offset)| block.y + offset + block.h).max().unwrap_or(1);
+    let pages = (h + page_h - 1) / page_h;
+
+    let mut pdf = Pdf { objects: Vec::new() };
+    let catalog = pdf.reserve();
+    let pages_id = pdf.reserve();
+    let font_regular = pdf_font(&mut pdf, font, &font_files.regular, "Regular")?;
+    let font_bold_id = pdf_font(&mut pdf, font_bold, &font_files.bold, "Bold")?;
+
+    let mut kids = Vec::new();
+    for page in 0..pages {
+        let top = page * page_h;
+
+        let mut contents = String::new();
+        let mut xobjects = String::new();
+        let mut annots = Vec::new();
+
+        for (block, offset) in blocks.iter().zip(offsets.iter()) {
+            let y = block.y + offset;
+            if y + block.h <= top || y >= top + page_h {
+                continue;
+            }
+            let x = block.x;
+            // PDF coordinates start at the bottom of the page, and what is past its edges is cut off
+            let bottom = page_h - (y - top) - block.h;
+
+            if let Some(ref image) = block.image {
+                let mut data = Vec::with_capacity(image.data().len() * 3);
+                for &color in image.data().iter() {
+                    data.extend_from_slice(&rgb(color));
+                }
+                let id = pdf.add_stream(&format!(
+                    "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /FlateDecode",
+                    image.width(), image.height()
+                ), &zlib(&data));
+                xobjects.push_str(&format!("/Im{} {} 0 R ", id, id));
+                contents.push_str(&format!("q {} 0 0 {} {} {} cm /Im{} Do Q\n", block.w, block.h, x, bottom, id));
+            }
+
+            if block.text.is_some() {
+                let color = rgb(block.color);
+                let baseline = page_h - (y - top) - (block.size * 0.8) as i32;
+                contents.push_str(&format!(
+                    "BT /F{} {} Tf {:.3} {:.3} {:.3} rg {} {} Td {} Tj ET\n",
+                    if block.bold { 2 } else { 1 }, block.size,
+                    color[0] as f32 / 255.0, color[1] as f32 / 255.0, color[2] as f32 / 255.0,
+                    x, baseline, pdf_string(&block.string)
+                ));
+            }
+
+            if let Some(ref link) = block.link {
+                if let Ok(link_url) = url.join(link) {
+                    annots.push(pdf.add(format!(
+                        "<< /Type /Annot /Subtype /Link /Rect [{} {} {} {}] /Border [0 0 0] /A << /S /URI /URI {} >> >>",
+                        x, bottom, x + block.w, bottom + block.h, pdf_string(link_url.as_str())
+                    ).into_bytes()));
+                }
+            }
+        }
+
+        let contents_id = pdf.add_stream("", contents.as_bytes());
+        let annots = annots.iter().map(|id| format!("{} 0 R", id)).collect::<Vec<String>>().join(" ");
+        kids.push(pdf.add(format!(
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 {} 0 R /F2 {} 0 R >> /XObject << {}>> >> /Contents {} 0 R /Annots [{}] >>",
+            pages_id, w, page_h, font_regular, font_bold_id, xobjects, contents_id, annots
+        ).into_bytes()));
+    }
+
+    let kids = kids.iter().map(|id| format!("{} 0 R", id)).collect::<Vec<String>>().join(" ");
+    pdf.set(pages_id, format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids, pages).into_bytes());
+    pdf.set(catalog, format!("<< /Type /Catalog /Pages {} 0 R >>", pages_id).into_bytes());
+
+    let mut file = File::create(path).map_err(|err| format!("Failed to create {}: {}", path.display(), err))?;
+    file.write_all(&pdf.write(catalog)).map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+/// Export to PNG or PDF depending on the file extension
+pub fn export(blocks: &[Block], url: &Url, font: &Font, font_bold: &Font, font_files: &FontFiles, width: i32, path: &Path) -> Result<(), String> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("png") => png(blocks, width, path),
+        Some("pdf") => pdf(blocks, url, font, font_bold, font_files, width, path),
+        _ => Err(format!("Unknown export format for {}, use .png or .pdf", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use orbclient::Color;
+    use orbfont::Font;
+    use orbimage;
+    use url::Url;
+
+    use super::{page_offsets, pdf, png, FontFiles};
+    use Block;
+
+    fn block(y: i32, h: i32) -> Block<'static> {
+        Block {
+            x: 0, y: y, w: 10, h: h, color: Color::rgb(0, 0, 0), size: 16.0, bold: false, string: String::new(),
+            link: None, image: None, text: None, node: 0, download: None, trust: false,
+        }
+    }
+
+    #[test]
+    fn png_compressed() {
+        let path = env::temp_dir().join("browser-export-test.png");
+        png(&[], 640, &path).unwrap();
+
+        // A blank page of 640 pixels by one takes a few bytes once compressed, and reads back as it was
+        let mut data = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+        assert!(data.len() < 100);
+        let image = orbimage::parse_png(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (640, 1));
+        assert!(image.data().iter().all(|color| color.data == 0xFFFFFFFF));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn page_break() {
+        // The second line would cross the end of the first page, so it starts the next one along with the rest of its
+        // line and everything after it, while the image taller than a page stays where it is
+        let blocks = [block(0, 20), block(90, 20), block(90, 10), block(110, 20), block(200, 300)];
+        assert_eq!(page_offsets(&blocks, 100), vec![0, 10, 10, 10, 10]);
+    }
+
+    #[test]
+    fn pdf_font_unknown() {
+        // Without any system font there is nothing to export with
+        let font = match Font::find(None, None, None) {
+            Ok(font) => font,
+            Err(_) => return
+        };
+        let url = Url::parse("about:blank").unwrap();
+        let path = env::temp_dir().join("browser-export-test.pdf");
+        let _ = fs::remove_file(&path);
+
+        // Without the file behind a font there is nothing to embed, so the export fails instead of substituting another font
+        let font_files = FontFiles { regular: None, bold: None };
+        assert!(pdf(&[], &url, &font, &font, &font_files, 640, &path).is_err());
+        assert!(File::open(&path).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper;
+use hyper::client::{Client, ProxyConfig, Response};
+use hyper::net::{HttpConnector, HttpStream, HttpsConnector, SslClient};
+use hyper_rustls::TlsClient;
+use rustls::{ClientConfig, ClientSession, Session, TLSError};
+use url::Url;
+
+use certificate::{self, Certificate};
+
+/// Network settings, read from the browser config file with the proxy environment variables as defaults
+pub struct Config {
+    /// Proxy used for http URLs
+    pub http_proxy: Option<Url>,
+    /// Proxy used for https URLs
+    pub https_proxy: Option<Url>,
+    /// Hosts and domains that are always connected to directly
+    pub no_proxy: Vec<String>,
+    /// PEM bundle of extra certificate authorities
+    pub ca_file: Option<PathBuf>,
+    /// PEM certificates trusted for a single host
+    pub exceptions: BTreeMap<String, PathBuf>,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).or_else(|_| env::var(name.to_uppercase())).ok().and_then(|value| {
+        if value.is_empty() {
+            None
+        } else {
+            Some(value)
+        }
+    })
+}
+
+fn proxy_url(value: &str) -> Option<Url> {
+    match Url::parse(value) {
+        Ok(url) => Some(url),
+        Err(_) => match Url::parse(&format!("http://{}", value)) {
+            Ok(url) => Some(url),
+            Err(err) => {
+                println!("Invalid proxy {}: {}", value, err);
+                None
+            }
+        }
+    }
+}
+
+fn no_proxy_list(value: &str) -> Vec<String> {
+    value.split(',').map(|host| host.trim().to_lowercase()).filter(|host| !host.is_empty()).collect()
+}
+
+impl Config {
+    /// The defaults, with the proxies taken from the environment
+    pub fn new() -> Config {
+        let mut config = Config::empty();
+        config.http_proxy = env_var("http_proxy").and_then(|value| proxy_url(&value));
+        config.https_proxy = env_var("https_proxy").and_then(|value| proxy_url(&value));
+        config.no_proxy = env_var("no_proxy").map(|value| no_proxy_list(&value)).unwrap_or(Vec::new());
+        config
+    }
+
+    /// The defaults, without looking at the environment
+    fn empty() -> Config {
+        Config {
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: Vec::new(),
+            ca_file: None,
+            exceptions: BTreeMap::new(),
+            read_timeout: Duration::new(5, 0),
+            write_timeout: Duration::new(5, 0),
+        }
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        env::var("HOME").ok().map(|home| Path::new(&home).join(".config").join("browser.conf"))
+    }
+
+    /// Load the config file on top of the environment, where lines are `key=value` like package info files
+    pub fn load() -> Config {
+        let mut config = Config::new();
+        config.read();
+        config
+    }
+
+    /// Load only what the config file sets, to change and `save` it without writing the environment into it
+    pub fn file() -> Config {
+        let mut config = Config::empty();
+        config.read();
+        config
+    }
+
+    fn read(&mut self) {
+        let mut info = String::new();
+        if let Some(path) = Config::path() {
+            if let Ok(mut file) = File::open(path) {
+                let _ = file.read_to_string(&mut info);
+            }
+        }
+
+        for line in info.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().unwrap_or("").trim();
+            match key {
+                "http_proxy" => self.http_proxy = proxy_url(value),
+                "https_proxy" => self.https_proxy = proxy_url(value),
+                "no_proxy" => self.no_proxy = no_proxy_list(value),
+                "ca_file" => self.ca_file = Some(PathBuf::from(value)),
+                "exception" => {
+                    let mut parts = value.splitn(2, ' ');
+                    if let (Some(host), Some(path)) = (parts.next(), parts.next()) {
+                        self.exceptions.insert(host.to_lowercase(), PathBuf::from(path.trim()));
+                    }
+                },
+                "read_timeout" => match value.parse() {
+                    Ok(secs) => self.read_timeout = Duration::new(secs, 0),
+                    Err(err) => println!("Invalid read_timeout {}: {}", value, err)
+                },
+                "write_timeout" => match value.parse() {
+                    Ok(secs) => self.write_timeout = Duration::new(secs, 0),
+                    Err(err) => println!("Invalid write_timeout {}: {}", value, err)
+                },
+                _ => println!("Unknown browser config: {}", line)
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Config::path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME not set"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = File::create(path)?;
+        if let Some(ref proxy) = self.http_proxy {
+            write!(file, "http_proxy={}\n", proxy)?;
+        }
+        if let Some(ref proxy) = self.https_proxy {
+            write!(file, "https_proxy={}\n", proxy)?;
+        }
+        if ! self.no_proxy.is_empty() {
+            write!(file, "no_proxy={}\n", self.no_proxy.join(","))?;
+        }
+        if let Some(ref ca_file) = self.ca_file {
+            write!(file, "ca_file={}\n", ca_file.display())?;
+        }
+        for (host, path) in self.exceptions.iter() {
+            write!(file, "exception={} {}\n", host, path.display())?;
+        }
+        write!(file, "read_timeout={}\n", self.read_timeout.as_secs())?;
+        write!(file, "write_timeout={}\n", self.write_timeout.as_secs())?;
+        Ok(())
+    }
+
+    fn bypass_proxy(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.no_proxy.iter().any(|entry| {
+            let domain = entry.trim_left_matches('.');
+            entry == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+        })
+    }
+}
+
+/// Add the certificates of a PEM file to the trust roots of a client
+fn add_pem_file(tls: &mut TlsClient, path: &Path) -> Result<(), String> {
+    let cfg = Arc::get_mut(&mut tls.cfg).ok_or_else(|| "TLS config is shared".to_string())?;
+    let file = File::open(path).map_err(|err| format!("Failed to open {}: {}", path.display(), err))?;
+    let (valid, _invalid) = cfg.root_store.add_pem_file(&mut BufReader::new(file)).map_err(|_| format!("Failed to parse {}", path.display()))?;
+    if valid == 0 {
+        return Err(format!("No certificates found in {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Check that a PEM file has certificates that can be trusted
+pub fn check_pem_file(path: &Path) -> Result<(), String> {
+    add_pem_file(&mut TlsClient::new(), path)
+}
+
+/// TLS client that adds the certificate exceptions for each host on top of the shared trust roots
+#[derive(Clone)]
+pub struct Tls {
+    default: Arc<TlsClient>,
+    hosts: Arc<BTreeMap<String, TlsClient>>,
+}
+
+impl Tls {
+    /// Build the clients, skipping the certificate files that cannot be loaded so a bad entry does not stop the browser
+    fn new(config: &Config) -> Tls {
+        let mut default = TlsClient::new();
+        let mut ca_file = None;
+        if let Some(ref path) = config.ca_file {
+            match add_pem_file(&mut default, path) {
+                Ok(()) => ca_file = Some(path.as_path()),
+                Err(err) => println!("Skipping ca_file: {}", err)
+            }
+        }
+
+        let mut hosts = BTreeMap::new();
+        for (host, path) in config.exceptions.iter() {
+            let mut tls = TlsClient::new();
+            let result = match ca_file {
+                Some(ca_file) => add_pem_file(&mut tls, ca_file),
+                None => Ok(())
+            }.and_then(|()| add_pem_file(&mut tls, path));
+            match result {
+                Ok(()) => {
+                    hosts.insert(host.clone(), tls);
+                },
+                Err(err) => println!("Skipping certificate exception for {}: {}", host, err)
+            }
+        }
+
+        Tls {
+            default: Arc::new(default),
+            hosts: Arc::new(hosts),
+        }
+    }
+}
+
+impl SslClient for Tls {
+    type Stream = <TlsClient as SslClient>::Stream;
+
+    fn wrap_client(&self, stream: HttpStream, host: &str) -> hyper::Result<Self::Stream> {
+        self.hosts.get(&host.to_lowercase()).unwrap_or(&*self.default).wrap_client(stream, host)
+    }
+}
+
+/// Clients shared by every request, built once from the config
+pub struct Http {
+    pub config: Config,
+    direct: Client,
+    http_proxy: Option<Client>,
+    https_proxy: Option<Client>,
+}
+
+impl Http {
+    pub fn new(config: Config) -> Http {
+        let tls = Tls::new(&config);
+
+        let proxy_client = |proxy: &Option<Url>| -> Option<Client> {
+            proxy.as_ref().and_then(|proxy| proxy.host_str().map(|host| {
+                let port = proxy.port_or_known_default().unwrap_or(80);
+                let mut client = Client::with_proxy_config(ProxyConfig::new("http", host.to_string(), port, HttpConnector, tls.clone()));
+                client.set_read_timeout(Some(config.read_timeout));
+                client.set_write_timeout(Some(config.write_timeout));
+                client
+            }))
+        };
+
+        let http_proxy = proxy_client(&config.http_proxy);
+        let https_proxy = proxy_client(&config.https_proxy);
+
+        let mut direct = Client::with_connector(HttpsConnector::new(tls.clone()));
+        direct.set_read_timeout(Some(config.read_timeout));
+        direct.set_write_timeout(Some(config.write_timeout));
+
+        Http {
+            config: config,
+            direct: direct,
+            http_proxy: http_proxy,
+            https_proxy: https_proxy,
+        }
+    }
+
+    fn client(&self, url: &Url) -> &Client {
+        let proxy = match url.scheme() {
+            "http" => self.http_proxy.as_ref(),
+            "https" => self.https_proxy.as_ref(),
+            _ => None
+        };
+
+        match proxy {
+            Some(client) if ! self.config.bypass_proxy(url.host_str().unwrap_or("")) => client,
+            _ => &self.direct
+        }
+    }
+
+    pub fn get(&self, url: &Url) -> hyper::Result<Response> {
+        self.client(url).get(url.clone()).send()
+    }
+}
+
+/// The certificate a host presents, read from a handshake with a client that trusts nothing, which goes on until the
+/// certificate is rejected
+pub fn peer_certificate(url: &Url, config: &Config) -> Result<Certificate, String> {
+    let host = url.host_str().ok_or_else(|| "No host".to_string())?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let mut stream = TcpStream::connect((host, port)).map_err(|err| format!("Failed to connect to {}: {}", host, err))?;
+    let _ = stream.set_read_timeout(Some(config.read_timeout));
+    let _ = stream.set_write_timeout(Some(config.write_timeout));
+
+    let mut session = ClientSession::new(&Arc::new(ClientConfig::new()), host);
+    while session.is_handshaking() && session.get_peer_certificates().map_or(true, |certificates| certificates.is_empty()) {
+        while session.wants_write() {
+            session.write_tls(&mut stream).map_err(|err| format!("Failed to write to {}: {}", host, err))?;
+        }
+        match session.read_tls(&mut stream) {
+            Ok(0) => break,
+            Ok(_) => if session.process_new_packets().is_err() {
+                break;
+            },
+            Err(err) => return Err(format!("Failed to read from {}: {}", host, err))
+        }
+    }
+
+    let der = session.get_peer_certificates().and_then(|certificates| certificates.into_iter().next())
+        .ok_or_else(|| format!("{} presented no certificate", host))?;
+    certificate::parse(&der.0).ok_or_else(|| format!("Failed to parse the certificate of {}", host))
+}
+
+/// Whether an error from rustls means the server certificate was not accepted
+fn certificate_error(err: &(Error + Send + Sync + 'static)) -> bool {
+    match err.downcast_ref::<TLSError>() {
+        Some(&TLSError::WebPKIError(_)) | Some(&TLSError::NoCertificatesPresented) => true,
+        _ => false
+    }
+}
+
+/// Whether a request failed because the server certificate was not accepted
+pub fn is_certificate_error(err: &hyper::Error) -> bool {
+    match *err {
+        hyper::Error::Ssl(ref err) => certificate_error(&**err),
+        hyper::Error::Io(ref err) => err.get_ref().map_or(false, certificate_error),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io;
+    use std::path::PathBuf;
+
+    use hyper;
+    use rustls::TLSError;
+
+    use super::{check_pem_file, is_certificate_error, Config, Tls};
+
+    #[test]
+    fn certificate_errors() {
+        let untrusted = io::Error::new(io::ErrorKind::InvalidData, TLSError::NoCertificatesPresented);
+        assert!(is_certificate_error(&hyper::Error::Io(untrusted)));
+
+        // Other TLS failures and invalid data in general are not about the certificate
+        let decrypt = io::Error::new(io::ErrorKind::InvalidData, TLSError::DecryptError);
+        assert!(!is_certificate_error(&hyper::Error::Io(decrypt)));
+        let invalid = io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8");
+        assert!(!is_certificate_error(&hyper::Error::Io(invalid)));
+    }
+
+    #[test]
+    fn bad_certificate_files_skipped() {
+        let missing = env::temp_dir().join("browser-http-test-missing.pem");
+        assert!(check_pem_file(&missing).is_err());
+
+        let mut config = Config::new();
+        config.ca_file = Some(missing.clone());
+        config.exceptions.insert("example.com".to_string(), PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"));
+        // Neither file holds a certificate, so both are left out rather than failing
+        let tls = Tls::new(&config);
+        assert!(tls.hosts.is_empty());
+    }
+}
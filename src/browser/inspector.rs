@@ -0,0 +1,199 @@
+use std::cmp;
+
+use html5ever::rcdom::{Document, Doctype, Text, Comment, Element, Handle, RcDom};
+use orbclient::{Color, EventOption, Renderer, Window, WindowFlag, K_DOWN, K_ESC, K_UP};
+
+/// One DOM node as listed in the inspector, numbered in the same order `walk` visits them
+pub struct Node {
+    pub depth: usize,
+    pub description: String,
+    /// One past the number of the last node in this subtree
+    pub end: usize,
+}
+
+fn describe(handle: &Handle) -> Option<String> {
+    let node = handle.borrow();
+    match node.node {
+        Document => Some("#document".to_string()),
+        Doctype(ref name, _, _) => Some(format!("<!DOCTYPE {}>", name)),
+        Text(ref text) => {
+            let trimmed = text.trim();
+            if trimmed.is_empty() {
+                None
+            } else if trimmed.chars().count() > 60 {
+                Some(format!("\"{}...\"", trimmed.chars().take(60).collect::<String>()))
+            } else {
+                Some(format!("\"{}\"", trimmed))
+            }
+        },
+        Comment(ref text) => Some(format!("<!--{}-->", text)),
+        Element(ref name, _, ref attrs) => {
+            let mut string = format!("<{}", name.local);
+            for attr in attrs.iter() {
+                string.push_str(&format!(" {}=\"{}\"", attr.name.local, attr.value));
+            }
+            string.push('>');
+            Some(string)
+        }
+    }
+}
+
+fn collect(handle: Handle, depth: usize, nodes: &mut Vec<Option<Node>>) {
+    let i = nodes.len();
+    nodes.push(describe(&handle).map(|description| Node {
+        depth: depth,
+        description: description,
+        end: 0
+    }));
+
+    for child in handle.borrow().children.iter() {
+        collect(child.clone(), depth + 1, nodes);
+    }
+
+    let end = nodes.len();
+    if let Some(ref mut node) = nodes[i] {
+        node.end = end;
+    }
+}
+
+/// Window listing the DOM tree, used to pick a node to highlight in the page
+pub struct Inspector {
+    window: Window,
+    /// Every node in visiting order, `None` for whitespace that is not shown
+    nodes: Vec<Option<Node>>,
+    /// Indexes into `nodes` of the rows shown
+    rows: Vec<usize>,
+    selected: Option<usize>,
+    scroll: i32,
+    mouse_y: i32,
+}
+
+impl Inspector {
+    pub fn new(dom: &RcDom, title: &str) -> Inspector {
+        let mut nodes = Vec::new();
+        collect(dom.document.clone(), 0, &mut nodes);
+
+        let rows = nodes.iter().enumerate().filter_map(|(i, node)| node.as_ref().map(|_| i)).collect();
+
+        let window = Window::new_flags(-1, -1, 480, 600, &format!("{} - Inspector", title), &[WindowFlag::Async, WindowFlag::Resizable]).unwrap();
+
+        let mut inspector = Inspector {
+            window: window,
+            nodes: nodes,
+            rows: rows,
+            selected: None,
+            scroll: 0,
+            mouse_y: 0,
+        };
+        inspector.draw();
+        inspector
+    }
+
+    /// Range of node numbers covered by the selected node and its descendants
+    pub fn selected(&self) -> Option<(usize, usize)> {
+        self.selected.and_then(|i| self.nodes[i].as_ref().map(|node| (i, node.end)))
+    }
+
+    fn draw(&mut self) {
+        self.window.set(Color::rgb(255, 255, 255));
+
+        let width = self.window.width();
+        let height = self.window.height() as i32;
+        for (row, &i) in self.rows.iter().enumerate() {
+            let y = row as i32 * 16 - self.scroll;
+            if y + 16 <= 0 || y >= height {
+                continue;
+            }
+
+            if let Some(ref node) = self.nodes[i] {
+                let color = if self.selected == Some(i) {
+                    self.window.rect(0, y, width, 16, Color::rgb(56, 117, 215));
+                    Color::rgb(255, 255, 255)
+                } else {
+                    Color::rgb(0, 0, 0)
+                };
+
+                let mut x = node.depth as i32 * 16;
+                for c in node.description.chars() {
+                    self.window.char(x, y, c, color);
+                    x += 8;
+                }
+            }
+        }
+
+        self.window.sync();
+    }
+
+    fn scroll_by(&mut self, amount: i32) {
+        let max_scroll = cmp::max(0, self.rows.len() as i32 * 16 - self.window.height() as i32);
+        self.scroll = cmp::max(0, cmp::min(max_scroll, self.scroll + amount));
+    }
+
+    fn select_row(&mut self, row: usize) {
+        if let Some(&i) = self.rows.get(row) {
+            self.selected = Some(i);
+
+            let y = row as i32 * 16;
+            if y < self.scroll {
+                self.scroll = y;
+            } else if y + 16 > self.scroll + self.window.height() as i32 {
+                self.scroll = y + 16 - self.window.height() as i32;
+            }
+        }
+    }
+
+    /// Handle pending events, returning whether the selection changed and whether the window closed
+    pub fn events(&mut self) -> (bool, bool) {
+        let mut changed = false;
+        let mut closed = false;
+        let mut redraw = false;
+
+        let selected_row = self.selected.and_then(|i| self.rows.iter().position(|&row| row == i));
+
+        for event in self.window.events() {
+            match event.to_option() {
+                EventOption::Key(key_event) => if key_event.pressed {
+                    match key_event.scancode {
+                        K_ESC => closed = true,
+                        K_UP => if let Some(row) = selected_row {
+                            if row > 0 {
+                                self.select_row(row - 1);
+                                changed = true;
+                            }
+                        },
+                        K_DOWN => {
+                            self.select_row(selected_row.map(|row| row + 1).unwrap_or(0));
+                            changed = true;
+                        },
+                        _ => ()
+                    }
+                },
+                EventOption::Mouse(mouse_event) => {
+                    self.mouse_y = mouse_event.y;
+                },
+                EventOption::Button(button_event) => if button_event.left {
+                    let row = (self.mouse_y + self.scroll) / 16;
+                    if row >= 0 {
+                        self.select_row(row as usize);
+                        changed = true;
+                    }
+                },
+                EventOption::Scroll(scroll_event) => {
+                    self.scroll_by(-scroll_event.y * 48);
+                    redraw = true;
+                },
+                EventOption::Resize(_) => {
+                    redraw = true;
+                },
+                EventOption::Quit(_) => closed = true,
+                _ => ()
+            }
+        }
+
+        if changed || redraw {
+            self.draw();
+        }
+
+        (changed, closed)
+    }
+}
@@ -11,6 +11,7 @@ extern crate tendril;
 extern crate url;
 extern crate hyper;
 extern crate hyper_rustls;
+extern crate rustls;
 
 
 use std::{cmp, env, str};
@@ -19,18 +20,57 @@ use std::default::Default;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{stderr, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
 use std::string::String;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use html5ever::parse_document;
 use html5ever::rcdom::{Document, Doctype, Text, Comment, Element, RcDom, Handle};
-use orbclient::{Color, EventOption, Renderer, Window, WindowFlag, K_BKSP, K_ESC, K_LEFT, K_RIGHT, K_DOWN, K_PGDN, K_UP, K_PGUP, K_ENTER};
+use orbclient::{Color, EventOption, Renderer, Window, WindowFlag, K_BKSP, K_D, K_ESC, K_I, K_P, K_U, K_LEFT, K_RIGHT, K_DOWN, K_PGDN, K_UP, K_PGUP, K_ENTER};
 use orbfont::Font;
 use tendril::TendrilSink;
 use url::Url;
 use hyper::header::{self, Headers};
-use hyper::Client;
-use hyper::net::HttpsConnector;
+use hyper::client::Response;
+
+use certificate::Certificate;
+use download::Download;
+use export::FontFiles;
+use http::Http;
+use inspector::Inspector;
+
+mod certificate;
+mod download;
+mod export;
+mod http;
+mod inspector;
+mod source;
+
+static LAUNCH_COMMAND: &'static str = "/ui/bin/launcher";
+static DOWNLOADS_URL: &'static str = "about:downloads";
+
+#[derive(Clone, Copy)]
+enum Action {
+    Back,
+    Open,
+    ViewSource,
+    Inspect,
+    Downloads,
+    Export,
+}
+
+/// Entries of the menu opened with the right mouse button
+const MENU: [(&'static str, Action); 6] = [
+    ("Back", Action::Back),
+    ("Open...", Action::Open),
+    ("View source", Action::ViewSource),
+    ("Inspect", Action::Inspect),
+    ("Downloads", Action::Downloads),
+    ("Export...", Action::Export),
+];
+const MENU_WIDTH: i32 = 128;
 
 struct Block<'a> {
     x: i32,
@@ -38,10 +78,20 @@ struct Block<'a> {
     w: i32,
     h: i32,
     color: Color,
+    size: f32,
+    bold: bool,
     string: String,
     link: Option<String>,
     image: Option<orbimage::Image>,
     text: Option<orbfont::Text<'a>>,
+    /// Number of the DOM node this block was created for, in the order `walk` visits them
+    node: usize,
+    /// Finished download the block opens, as an index into the downloads, which only the downloads page sets so no
+    /// page can have a file opened
+    download: Option<usize>,
+    /// Whether the block asks to trust the certificate of the host, which only the page for an untrusted certificate
+    /// sets
+    trust: bool,
 }
 
 impl<'a> Block<'a> {
@@ -52,22 +102,57 @@ impl<'a> Block<'a> {
         m_x >= x && m_x < x + self.w && m_y >= y && m_y < y + self.h
     }
 
-    fn draw(&self, window: &mut Window, offset: (i32, i32)) {
+    fn draw<R: Renderer>(&self, renderer: &mut R, offset: (i32, i32)) {
         let x = self.x - offset.0;
         let y = self.y - offset.1;
-        if x + self.w > 0 && x < window.width() as i32 && y + self.h > 0 && y < window.height() as i32 {
+        if x + self.w > 0 && x < renderer.width() as i32 && y + self.h > 0 && y < renderer.height() as i32 {
             if let Some(ref image) = self.image {
-                image.draw(window, x, y);
+                image.draw(renderer, x, y);
             }
 
             if let Some(ref text) = self.text {
-                text.draw(window, x, y, self.color);
+                text.draw(renderer, x, y, self.color);
+            }
+        }
+    }
+}
+
+/// What clicking a block does
+#[derive(Debug, PartialEq)]
+enum Click {
+    /// Open the finished download with this index in the launcher
+    Launch(usize),
+    /// Scroll to the anchor with this name
+    Anchor(String),
+    /// Follow the link
+    Navigate(String),
+    /// Ask for a certificate to trust for the host of the page
+    Trust,
+}
+
+/// What clicking at `m_x`, `m_y` does, if anything
+fn click<'a>(blocks: &[Block<'a>], m_x: i32, m_y: i32, offset: (i32, i32)) -> Option<Click> {
+    for block in blocks.iter() {
+        if block.contains(m_x, m_y, offset) {
+            println!("Click {}", block.string);
+            if let Some(i) = block.download {
+                return Some(Click::Launch(i));
+            }
+            if block.trust {
+                return Some(Click::Trust);
+            }
+            if let Some(ref link) = block.link {
+                if link.starts_with('#') {
+                    return Some(Click::Anchor(link[1..].to_string()));
+                }
+                return Some(Click::Navigate(link.clone()));
             }
         }
     }
+    None
 }
 
-fn text_block<'a>(string: &str, x: &mut i32, y: &mut i32, size: f32, bold: bool, color: Color, link: Option<String>, font: &'a Font, font_bold: &'a Font, window: &Window, blocks: &mut Vec<Block<'a>>) {
+fn text_block<'a>(string: &str, x: &mut i32, y: &mut i32, size: f32, bold: bool, color: Color, link: Option<String>, font: &'a Font, font_bold: &'a Font, width: i32, blocks: &mut Vec<Block<'a>>) {
     let trimmed_left = string.trim_left();
     let left_margin = string.len() as i32 - trimmed_left.len() as i32;
     let trimmed_right = trimmed_left.trim_right();
@@ -92,7 +177,7 @@ fn text_block<'a>(string: &str, x: &mut i32, y: &mut i32, size: f32, bold: bool,
         let w = text.width() as i32;
         let h = text.height() as i32;
 
-        if *x + w >= window.width() as i32 && *x > 0 {
+        if *x + w >= width && *x > 0 {
             *x = 0;
             *y += size.ceil() as i32;
         }
@@ -103,10 +188,15 @@ fn text_block<'a>(string: &str, x: &mut i32, y: &mut i32, size: f32, bold: bool,
             w: w,
             h: h,
             color: color,
+            size: size,
+            bold: bold,
             string: word.to_string(),
             link: link.clone(),
             image: None,
-            text: Some(text)
+            text: Some(text),
+            node: 0,
+            download: None,
+            trust: false
         });
 
         *x += w;
@@ -115,9 +205,13 @@ fn text_block<'a>(string: &str, x: &mut i32, y: &mut i32, size: f32, bold: bool,
     *x += right_margin * 8;
 }
 
-fn walk<'a>(handle: Handle, indent: usize, x: &mut i32, y: &mut i32, mut size: f32, mut bold: bool, mut color: Color, mut ignore: bool, whitespace: &mut bool, mut link: Option<String>, url: &Url, font: &'a Font, font_bold: &'a Font, window: &Window, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
+fn walk<'a>(handle: Handle, indent: usize, x: &mut i32, y: &mut i32, mut size: f32, mut bold: bool, mut color: Color, mut ignore: bool, whitespace: &mut bool, mut link: Option<String>, url: &Url, http: &Http, font: &'a Font, font_bold: &'a Font, width: i32, node_id: &mut usize, anchors: &mut BTreeMap<String, i32>, images: &mut BTreeMap<String, Option<orbimage::Image>>, blocks: &mut Vec<Block<'a>>) {
     let node = handle.borrow();
 
+    let id = *node_id;
+    *node_id += 1;
+    let first_block = blocks.len();
+
     let mut new_line = false;
 
     //print!("{}", repeat(" ").take(indent).collect::<String>());
@@ -158,7 +252,7 @@ fn walk<'a>(handle: Handle, indent: usize, x: &mut i32, y: &mut i32, mut size: f
                     if ignore {
                         //println!("#text: ignored");
                     } else {
-                        text_block(&string, x, y, size, bold, color, link.clone(), font, font_bold, window, blocks);
+                        text_block(&string, x, y, size, bold, color, link.clone(), font, font_bold, width, blocks);
                     }
                 } else {
                     //println!("#text: empty");
@@ -253,60 +347,39 @@ fn walk<'a>(handle: Handle, indent: usize, x: &mut i32, y: &mut i32, mut size: f
 
                         let mut use_alt = true;
                         if let Some(src) = src_opt {
-                            if src.ends_with(".jpg") || src.ends_with(".jpeg") {
-                                let img_url = url.join(&src).unwrap();
-                                if let Ok((_img_headers, img_data)) = http_download(&img_url) {
-                                    if let Ok(img) = orbimage::parse_jpg(&img_data) {
-                                        use_alt = false;
-
-                                        let w = img.width() as i32;
-                                        let h = img.height() as i32;
-
-                                        blocks.push(Block {
-                                            x: *x,
-                                            y: *y,
-                                            w: w,
-                                            h: h,
-                                            color: color,
-                                            string: String::new(),
-                                            link: link.clone(),
-                                            image: Some(img),
-                                            text: None
-                                        });
-
-                                        *y += h;
-                                    }
-                                }
-                            } else if src.ends_with(".png") {
-                                let img_url = url.join(&src).unwrap();
-                                if let Ok((_img_headers, img_data)) = http_download(&img_url) {
-                                    if let Ok(img) = orbimage::parse_png(&img_data) {
-                                        use_alt = false;
-
-                                        let w = img.width() as i32;
-                                        let h = img.height() as i32;
-
-                                        blocks.push(Block {
-                                            x: *x,
-                                            y: *y,
-                                            w: w,
-                                            h: h,
-                                            color: color,
-                                            string: String::new(),
-                                            link: link.clone(),
-                                            image: Some(img),
-                                            text: None
-                                        });
-
-                                        *y += h;
-                                    }
+                            if let Ok(img_url) = url.join(&src) {
+                                let img_opt = images.entry(img_url.to_string()).or_insert_with(|| image_download(http, &src, &img_url));
+                                if let Some(ref img) = *img_opt {
+                                    use_alt = false;
+
+                                    let w = img.width() as i32;
+                                    let h = img.height() as i32;
+
+                                    blocks.push(Block {
+                                        x: *x,
+                                        y: *y,
+                                        w: w,
+                                        h: h,
+                                        color: color,
+                                        size: size,
+                                        bold: bold,
+                                        string: String::new(),
+                                        link: link.clone(),
+                                        image: Some(img.clone()),
+                                        text: None,
+                                        node: 0,
+                                        download: None,
+                                        trust: false
+                                    });
+
+                                    *y += h;
                                 }
                             }
                         }
 
                         if use_alt {
                             if let Some(alt) = alt_opt {
-                                text_block(&alt, x, y, size, bold, color, link.clone(), font, font_bold, window, blocks);
+                                text_block(&alt, x, y, size, bold, color, link.clone(), font, font_bold, width, blocks);
                             }
                         }
                     }
@@ -335,8 +408,12 @@ fn walk<'a>(handle: Handle, indent: usize, x: &mut i32, y: &mut i32, mut size: f
         }
     }
 
+    for block in blocks[first_block..].iter_mut() {
+        block.node = id;
+    }
+
     for child in node.children.iter() {
-        walk(child.clone(), indent + 4, x, y, size, bold, color, ignore, whitespace, link.clone(), url, font, font_bold, window, anchors, blocks);
+        walk(child.clone(), indent + 4, x, y, size, bold, color, ignore, whitespace, link.clone(), url, http, font, font_bold, width, node_id, anchors, images, blocks);
     }
 
     if new_line {
@@ -351,13 +428,14 @@ pub fn escape_default(s: &str) -> String {
     s.chars().flat_map(|c| c.escape_default()).collect()
 }
 
-fn http_download(url: &Url) -> Result<(Headers, Vec<u8>), String> {
-    write!(stderr(), "* Requesting {}\n", url).map_err(|err| format!("{}", err))?;
+fn http_request(http: &Http, url: &Url) -> hyper::Result<Response> {
+    let _ = write!(stderr(), "* Requesting {}\n", url);
+
+    http.get(url)
+}
 
-    let mut client = Client::with_connector(HttpsConnector::new(hyper_rustls::TlsClient::new()));
-    client.set_read_timeout(Some(Duration::new(5, 0)));
-    client.set_write_timeout(Some(Duration::new(5, 0)));
-    let mut res = client.get(url.clone()).send().map_err(|err| format!("Failed to send request: {}", err))?;
+fn http_download(http: &Http, url: &Url) -> Result<(Headers, Vec<u8>), String> {
+    let mut res = http_request(http, url).map_err(|err| format!("Failed to send request: {}", err))?;
     let mut data = Vec::new();
     res.read_to_end(&mut data).map_err(|err| format!("Failed to read response: {}", err))?;
 
@@ -366,152 +444,117 @@ fn http_download(url: &Url) -> Result<(Headers, Vec<u8>), String> {
     Ok((res.headers.clone(), data))
 }
 
-fn read_parse<'a, R: Read>(headers: Headers, r: &mut R, url: &Url, font: &'a Font, font_bold: &'a Font, window: &Window, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
+/// Downloaded and parsed page, kept around so it can be laid out again without refetching
+enum Content {
+    Text(String),
+    /// Parsed document and the markup it came from
+    Html(RcDom, String),
+    /// Source of a page with whether it is HTML, and the parse errors
+    Source(String, bool, Vec<String>),
+    Image(orbimage::Image),
+    /// Content type that cannot be shown, with the response left unread so it can be saved
+    Unsupported(String, Option<Response>),
+    Downloads,
+    /// Certificate for the host was rejected, with the TLS error and the certificate it presented
+    Untrusted(String, String, Result<Certificate, String>),
+    Error(String),
+}
+
+/// Media type of the content, without its parameters, which decides how the content is read
+fn media_type(headers: &Headers) -> String {
     let content_type = headers.get_raw("content-type").and_then(|x| str::from_utf8(x[0].as_slice()).ok()).unwrap_or("text/plain");
-    let media_type = content_type.split(";").next().unwrap_or("");
+    content_type.split(";").next().unwrap_or("").trim().to_string()
+}
 
+fn supported(media_type: &str) -> bool {
     match media_type {
+        "text/plain" | "text/html" | "image/jpeg" | "image/png" | "image/x-ms-bmp" => true,
+        _ => false
+    }
+}
+
+fn image_download(http: &Http, src: &str, url: &Url) -> Option<orbimage::Image> {
+    if src.ends_with(".jpg") || src.ends_with(".jpeg") {
+        http_download(http, url).ok().and_then(|(_img_headers, img_data)| orbimage::parse_jpg(&img_data).ok())
+    } else if src.ends_with(".png") {
+        http_download(http, url).ok().and_then(|(_img_headers, img_data)| orbimage::parse_png(&img_data).ok())
+    } else {
+        None
+    }
+}
+
+fn read_parse<R: Read>(headers: Headers, r: &mut R) -> Content {
+    let media_type = media_type(&headers);
+
+    match media_type.as_str() {
         "text/plain" => {
             let mut string = String::new();
             match r.read_to_string(&mut string) {
-                Ok(_) => {
-                    let mut y = 0;
-                    for line in string.lines() {
-                        text_block(line, &mut 0, &mut y, 12.0, false, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-                        y += 12;
-                    }
-                },
-                Err(err) => {
-                    let error = format!("Text data not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-                }
+                Ok(_) => Content::Text(string),
+                Err(err) => Content::Error(format!("Text data not readable: {}", err))
             }
         },
         "text/html" => {
-            match parse_document(RcDom::default(), Default::default()).from_utf8().read_from(r) {
-                Ok(dom) => {
-                    let mut x = 0;
-                    let mut y = 0;
-                    let mut whitespace = false;
-                    walk(dom.document, 0, &mut x, &mut y, 16.0, false, Color::rgb(0, 0, 0), false, &mut whitespace, None, url, font, font_bold, window, anchors, blocks);
-
-                    if !dom.errors.is_empty() {
-                        /*
-                        println!("\nParse errors:");
-                        for err in dom.errors.into_iter() {
-                            println!("    {}", err);
-                        }
-                        */
-                    }
+            let mut data = Vec::new();
+            match r.read_to_end(&mut data) {
+                Ok(_) => match parse_document(RcDom::default(), Default::default()).from_utf8().read_from(&mut data.as_slice()) {
+                    Ok(dom) => Content::Html(dom, String::from_utf8_lossy(&data).into_owned()),
+                    Err(err) => Content::Error(format!("HTML data not readable: {}", err))
                 },
-                Err(err) => {
-                    let error = format!("HTML data not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-                }
+                Err(err) => Content::Error(format!("HTML stream not readable: {}", err))
             }
         },
         "image/jpeg" => {
             let mut data = Vec::new();
             match r.read_to_end(&mut data) {
                 Ok(_) => match orbimage::parse_jpg(&data) {
-                    Ok(img) => {
-                        blocks.push(Block {
-                            x: 0,
-                            y: 0,
-                            w: img.width() as i32,
-                            h: img.height() as i32,
-                            color: Color::rgb(0, 0, 0),
-                            string: String::new(),
-                            link: None,
-                            image: Some(img),
-                            text: None
-                        });
-                    },
-                    Err(err) => {
-                        let error = format!("JPG data not readable: {}", err);
-                        text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-                    }
+                    Ok(img) => Content::Image(img),
+                    Err(err) => Content::Error(format!("JPG data not readable: {}", err))
                 },
-                Err(err) => {
-                    let error = format!("JPG stream not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-                }
+                Err(err) => Content::Error(format!("JPG stream not readable: {}", err))
             }
         },
         "image/png" => {
             let mut data = Vec::new();
             match r.read_to_end(&mut data){
                 Ok(_) => match orbimage::parse_png(&data) {
-                    Ok(img) => {
-                        blocks.push(Block {
-                            x: 0,
-                            y: 0,
-                            w: img.width() as i32,
-                            h: img.height() as i32,
-                            color: Color::rgb(0, 0, 0),
-                            string: String::new(),
-                            link: None,
-                            image: Some(img),
-                            text: None
-                        });
-                    },
-                    Err(err) => {
-                        let error = format!("PNG data not readable: {}", err);
-                        text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-                    }
+                    Ok(img) => Content::Image(img),
+                    Err(err) => Content::Error(format!("PNG data not readable: {}", err))
                 },
-                Err(err) => {
-                    let error = format!("PNG stream not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-                }
+                Err(err) => Content::Error(format!("PNG stream not readable: {}", err))
             }
         },
         "image/x-ms-bmp" => {
             let mut data = Vec::new();
             match r.read_to_end(&mut data) {
                 Ok(_) => match orbimage::parse_bmp(&data) {
-                    Ok(img) => {
-                        blocks.push(Block {
-                            x: 0,
-                            y: 0,
-                            w: img.width() as i32,
-                            h: img.height() as i32,
-                            color: Color::rgb(0, 0, 0),
-                            string: String::new(),
-                            link: None,
-                            image: Some(img),
-                            text: None
-                        });
-                    },
-                    Err(err) => {
-                        let error = format!("BMP data not readable: {}", err);
-                        text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-                    }
+                    Ok(img) => Content::Image(img),
+                    Err(err) => Content::Error(format!("BMP data not readable: {}", err))
                 },
-                Err(err) => {
-                    let error = format!("BMP stream not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-                }
+                Err(err) => Content::Error(format!("BMP stream not readable: {}", err))
             }
         },
-        _ => {
-            let error = format!("Unsupported content type: {}", content_type);
-            text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window, blocks);
-        }
+        _ => Content::Error(format!("Unsupported content type: {}", media_type))
     }
 }
 
-fn file_parse<'a>(url: &Url, font: &'a Font, font_bold: &'a Font, window: &Window, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
+fn file_parse(url: &Url) -> Content {
     if let Ok(path) = url.to_file_path() {
         if let Ok(mut file) = File::open(&path) {
+            let mut data = Vec::new();
+            if let Err(err) = file.read_to_end(&mut data) {
+                return Content::Error(format!("Failed to read {}: {}", path.display(), err));
+            }
+
             let mut headers = Headers::new();
 
+            // Files with other extensions are shown as text when they are text
             let mime_type = match path.extension().unwrap_or(OsStr::new("")).to_str().unwrap_or("") {
                 "html" => "text/html",
                 "jpg" | "jpeg" => "image/jpeg",
                 "png" => "image/png",
                 "bmp" => "image/x-ms-bmp",
-                _ => "text/plain",
+                _ => if str::from_utf8(&data).is_ok() { "text/plain" } else { "application/octet-stream" },
             };
 
             /* TODO {
@@ -522,34 +565,195 @@ fn file_parse<'a>(url: &Url, font: &'a Font, font_bold: &'a Font, window: &Windo
 
             headers.set(header::ContentType(mime_type.parse().unwrap()));
 
-            read_parse(headers, &mut file, url, font, font_bold, window, anchors, blocks);
+            if supported(mime_type) {
+                read_parse(headers, &mut data.as_slice())
+            } else {
+                Content::Unsupported(mime_type.to_string(), None)
+            }
         } else {
             println!("{} not found", path.display());
+            Content::Error(format!("{} not found", path.display()))
         }
+    } else {
+        Content::Error(format!("{} is not a valid path", url))
     }
 }
 
-fn http_parse<'a>(url: &Url, font: &'a Font, font_bold: &'a Font, window: &Window, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
-    match http_download(url) {
-        Ok((headers, response)) => {
-            read_parse(headers, &mut response.as_slice(), url, font, font_bold, window, anchors, blocks);
+fn http_parse(http: &Http, url: &Url) -> Content {
+    match http_request(http, url) {
+        Ok(mut response) => {
+            let media_type = media_type(&response.headers);
+            if supported(&media_type) {
+                let mut data = Vec::new();
+                match response.read_to_end(&mut data) {
+                    Ok(_) => {
+                        let _ = write!(stderr(), "* Received {} bytes\n", data.len());
+                        read_parse(response.headers.clone(), &mut data.as_slice())
+                    },
+                    Err(err) => Content::Error(format!("Failed to read response: {}", err))
+                }
+            } else {
+                Content::Unsupported(media_type, Some(response))
+            }
+        },
+        Err(ref err) if url.scheme() == "https" && http::is_certificate_error(err) => {
+            Content::Untrusted(url.host_str().unwrap_or("").to_string(), format!("{}", err), http::peer_certificate(url, &http.config))
         },
         Err(err) => {
             let mut headers = Headers::new();
             headers.set(header::ContentType("text/plain".parse().unwrap()));
-            let response = format!("{}", err).into_bytes();
-            read_parse(headers, &mut response.as_slice(), url, font, font_bold, window, anchors, blocks);
+            let response = format!("Failed to send request: {}", err).into_bytes();
+            read_parse(headers, &mut response.as_slice())
         }
     }
 }
 
-fn url_parse<'a>(url: &Url, font: &'a Font, font_bold: &'a Font, window: &Window, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
+fn url_parse(http: &Http, url: &Url) -> Content {
     if url.scheme() == "http" || url.scheme() == "https" {
-        http_parse(url, font, font_bold, window, anchors, blocks)
+        http_parse(http, url)
     } else if url.scheme() == "file" {
-        file_parse(url, font, font_bold, window, anchors, blocks)
+        file_parse(url)
     } else {
         println!("{} scheme not found", url.scheme());
+        Content::Error(format!("{} scheme not found", url.scheme()))
+    }
+}
+
+fn layout<'a>(content: &Content, url: &Url, http: &Http, downloads: &[Download], font: &'a Font, font_bold: &'a Font, width: i32, anchors: &mut BTreeMap<String, i32>, images: &mut BTreeMap<String, Option<orbimage::Image>>, blocks: &mut Vec<Block<'a>>) {
+    match *content {
+        Content::Text(ref string) => {
+            let mut y = 0;
+            for line in string.lines() {
+                text_block(line, &mut 0, &mut y, 12.0, false, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+                y += 12;
+            }
+        },
+        Content::Html(ref dom, _) => {
+            let mut x = 0;
+            let mut y = 0;
+            let mut whitespace = false;
+            let mut node_id = 0;
+            walk(dom.document.clone(), 0, &mut x, &mut y, 16.0, false, Color::rgb(0, 0, 0), false, &mut whitespace, None, url, http, font, font_bold, width, &mut node_id, anchors, images, blocks);
+        },
+        Content::Image(ref img) => {
+            blocks.push(Block {
+                x: 0,
+                y: 0,
+                w: img.width() as i32,
+                h: img.height() as i32,
+                color: Color::rgb(0, 0, 0),
+                size: 0.0,
+                bold: false,
+                string: String::new(),
+                link: None,
+                image: Some(img.clone()),
+                text: None,
+                node: 0,
+                download: None,
+                trust: false
+            });
+        },
+        Content::Unsupported(ref media_type, _) => {
+            let mut y = 0;
+            let error = format!("Unsupported content type: {}", media_type);
+            text_block(&error, &mut 0, &mut y, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+            // Only responses are offered to be saved, a file is already on disk where it can be copied from
+            if url.scheme() == "file" {
+                y += 16;
+                text_block("Files opened from disk cannot be saved again, copy the file from where it is instead", &mut 0, &mut y, 16.0, false, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+            }
+        },
+        Content::Source(ref string, html, ref errors) => {
+            let mut y = 0;
+
+            if ! errors.is_empty() {
+                text_block(&format!("{} parse errors:", errors.len()), &mut 0, &mut y, 12.0, true, Color::rgb(192, 0, 0), None, font, font_bold, width, blocks);
+                y += 12;
+                for error in errors.iter() {
+                    text_block(error, &mut 0, &mut y, 12.0, false, Color::rgb(192, 0, 0), None, font, font_bold, width, blocks);
+                    y += 12;
+                }
+                y += 12;
+            }
+
+            let mut state = source::State::Text;
+            for (i, line) in string.lines().enumerate() {
+                let mut x = 0;
+                text_block(&format!("{:>5} ", i + 1), &mut x, &mut y, 12.0, false, Color::rgb(128, 128, 128), None, font, font_bold, width, blocks);
+                if html {
+                    for (segment, color) in source::highlight(line, &mut state) {
+                        text_block(&segment, &mut x, &mut y, 12.0, false, color, None, font, font_bold, width, blocks);
+                    }
+                } else {
+                    text_block(line, &mut x, &mut y, 12.0, false, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+                }
+                y += 12;
+            }
+        },
+        Content::Untrusted(ref host, ref error, ref certificate) => {
+            let mut y = 0;
+            text_block(&format!("Certificate for {} is not trusted", host), &mut 0, &mut y, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+            y += 16;
+            text_block(error, &mut 0, &mut y, 16.0, false, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+            y += 32;
+
+            match *certificate {
+                Ok(ref certificate) => {
+                    text_block("Certificate presented", &mut 0, &mut y, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+                    y += 16;
+                    for line in [
+                        format!("Subject: {}", certificate.subject),
+                        format!("Issuer: {}", certificate.issuer),
+                        format!("SHA-256 fingerprint: {}", certificate.fingerprint),
+                    ].iter() {
+                        text_block(line, &mut 0, &mut y, 16.0, false, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+                        y += 16;
+                    }
+                },
+                Err(ref err) => {
+                    text_block(&format!("Certificate not available: {}", err), &mut 0, &mut y, 16.0, false, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+                    y += 16;
+                }
+            }
+            y += 16;
+
+            // Only this page can ask to trust a certificate, from a block no document can make
+            let first_block = blocks.len();
+            text_block("Trust a certificate for this host...", &mut 0, &mut y, 16.0, false, Color::rgb(0, 0, 255), None, font, font_bold, width, blocks);
+            for block in blocks[first_block..].iter_mut() {
+                block.trust = true;
+            }
+        },
+        Content::Downloads => {
+            let mut y = 0;
+            text_block("Downloads", &mut 0, &mut y, 24.0, true, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+            y += 32;
+
+            if downloads.is_empty() {
+                text_block("No downloads", &mut 0, &mut y, 16.0, false, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+            }
+
+            for (i, download) in downloads.iter().enumerate().rev() {
+                let finished = match download.status() {
+                    download::Status::Finished(_) => true,
+                    _ => false
+                };
+                let color = if finished { Color::rgb(0, 0, 255) } else { Color::rgb(0, 0, 0) };
+                let first_block = blocks.len();
+                text_block(&download.description(), &mut 0, &mut y, 16.0, false, color, None, font, font_bold, width, blocks);
+                if finished {
+                    for block in blocks[first_block..].iter_mut() {
+                        block.download = Some(i);
+                    }
+                }
+                y += 16;
+                text_block(&format!("{}", download.url), &mut 0, &mut y, 12.0, false, Color::rgb(128, 128, 128), None, font, font_bold, width, blocks);
+                y += 20;
+            }
+        },
+        Content::Error(ref error) => {
+            text_block(error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, width, blocks);
+        }
     }
 }
 
@@ -614,18 +818,82 @@ fn open_dialog(url: &Url) -> Option<Url> {
     Rc::try_unwrap(ret).unwrap().into_inner()
 }
 
-fn main_window(arg: &str, font: &Font, font_bold: &Font) {
+fn path_dialog(title: &str, path: &PathBuf, action: &str) -> Option<PathBuf> {
+    use orbtk::{Button, Click, Enter, Place, Point, Rect, Text, TextBox, Window};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let ret = Rc::new(RefCell::new(None));
+
+    {
+        let w = 400;
+        let mut window = Window::new(Rect::new(-1, -1, w, 32), title);
+
+        let path_box = TextBox::new();
+        {
+            let ret_path = ret.clone();
+            let window_path = &mut window as *mut Window;
+            path_box.position(0, 0)
+                .size(w, 16)
+                .text(format!("{}", path.display()))
+                .on_enter(move |me: &TextBox| {
+                    *ret_path.borrow_mut() = Some(PathBuf::from(me.text.get()));
+                    unsafe { (&mut *window_path).close(); }
+                });
+                window.add(&path_box);
+        }
+
+        {
+            let window_cancel = &mut window as *mut Window;
+            let button = Button::new();
+            button.position(0, 16)
+                .size(w/2, 16)
+                .text("Cancel")
+                .on_click(move |_button: &Button, _point: Point| {
+                    unsafe { (&mut *window_cancel).close(); }
+                });
+            window.add(&button);
+        }
+
+        {
+            let ret_save = ret.clone();
+            let window_save = &mut window as *mut Window;
+            let button = Button::new();
+            button.position((w as i32)/2, 16)
+                .size(w/2, 16)
+                .text(action)
+                .on_click(move |_button: &Button, _point: Point| {
+                    *ret_save.borrow_mut() = Some(PathBuf::from(path_box.text.get()));
+                    unsafe { (&mut *window_save).close(); }
+                });
+            window.add(&button);
+        }
+
+        window.exec();
+    }
+
+    Rc::try_unwrap(ret).unwrap().into_inner()
+}
+
+fn main_window(arg: &str, font: &Font, font_bold: &Font, font_files: &FontFiles, mut http: Http) {
     let mut history = vec![];
 
     let mut url = Url::parse(arg).unwrap();
 
     let (display_width, display_height) = orbclient::get_display_size().expect("viewer: failed to get display size");
-    let (window_w, window_h) = (cmp::min(1024, display_width * 4/5) as i32, cmp::min(768, display_height * 4/5) as i32);
+    let (mut window_w, mut window_h) = (cmp::min(1024, display_width * 4/5) as i32, cmp::min(768, display_height * 4/5) as i32);
 
     let mut window = Window::new_flags(
         -1, -1, window_w as u32, window_h as u32,  "Browser", &[WindowFlag::Resizable]
     ).unwrap();
 
+    let mut downloads: Vec<Download> = Vec::new();
+    let mut downloads_running = false;
+    let mut downloads_updated = Instant::now();
+
+    let mut content = Content::Error(String::new());
+    let mut inspector: Option<Inspector> = None;
+    let mut images = BTreeMap::new();
     let mut anchors = BTreeMap::new();
     let mut blocks = Vec::new();
 
@@ -635,8 +903,12 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
     let mut mouse_x = 0;
     let mut mouse_y = 0;
     let mut mouse_down = false;
+    let mut mouse_right = false;
+    let mut menu = None;
+    let mut ctrl = false;
 
     let mut reload = true;
+    let mut relayout = false;
     let mut redraw = true;
     loop {
         if reload {
@@ -646,7 +918,7 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
 
             anchors.clear();
             blocks.clear();
-            text_block("Loading...", &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, &window, &mut blocks);
+            text_block("Loading...", &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, window_w, &mut blocks);
 
             {
                 window.set(Color::rgb(255, 255, 255));
@@ -660,9 +932,64 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
 
             anchors.clear();
             blocks.clear();
-            url_parse(&url, &font, &font_bold, &window, &mut anchors, &mut blocks);
+            images.clear();
+            inspector = None;
+            content = if url.as_str() == DOWNLOADS_URL {
+                Content::Downloads
+            } else if url.scheme() == "view-source" {
+                // Everything after the scheme, since the query of the page would otherwise be split off
+                let source = &url.as_str()[url.scheme().len() + 1..];
+                match Url::parse(source) {
+                    Ok(source_url) => match url_parse(&http, &source_url) {
+                        Content::Html(dom, string) => Content::Source(string, true, dom.errors.iter().map(|err| err.to_string()).collect()),
+                        Content::Text(string) => Content::Source(string, false, Vec::new()),
+                        _ => Content::Error(format!("No source available for {}", source_url))
+                    },
+                    Err(err) => Content::Error(format!("Invalid URL {}: {}", source, err))
+                }
+            } else {
+                url_parse(&http, &url)
+            };
+
+            if let Content::Unsupported(_, ref mut response_opt) = content {
+                if let Some(response) = response_opt.take() {
+                    let path = download::download_path(&download::file_name(&response.headers, &url));
+                    if let Some(path) = path_dialog("Save", &path, "Save") {
+                        downloads.push(Download::new(url.clone(), response, path));
+
+                        history.push(url.clone());
+                        url = Url::parse(DOWNLOADS_URL).unwrap();
+                        reload = true;
+                        continue;
+                    }
+                }
+            }
 
             offset = (0, 0);
+            relayout = true;
+        }
+
+        // Refresh the progress shown on the downloads page, including once after the last one stops
+        if let Content::Downloads = content {
+            let running = downloads.iter().any(|download| download.running());
+            if (running || downloads_running) && downloads_updated.elapsed() >= Duration::from_millis(250) {
+                downloads_running = running;
+                downloads_updated = Instant::now();
+                relayout = true;
+            }
+        }
+
+        if relayout {
+            relayout = false;
+
+            // The same content always produces the same sequence of blocks, so the index of the
+            // first visible block can be used to keep it at the top after reflowing
+            let top_block = blocks.iter().position(|block| block.y + block.h > offset.1);
+
+            anchors.clear();
+            blocks.clear();
+            layout(&content, &url, &http, &downloads, &font, &font_bold, window_w, &mut anchors, &mut images, &mut blocks);
+
             max_offset = (0, 0);
             for block in blocks.iter() {
                 if block.x + block.w > max_offset.0 {
@@ -673,6 +1000,12 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
                 }
             }
 
+            if let Some(block) = top_block.and_then(|i| blocks.get(i)) {
+                offset.1 = block.y;
+            }
+            offset.0 = cmp::max(0, cmp::min(max_offset.0 - window_w, offset.0));
+            offset.1 = cmp::max(0, cmp::min(max_offset.1 - window_h, offset.1));
+
             redraw = true;
         }
 
@@ -681,18 +1014,56 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
 
             window.set(Color::rgb(255, 255, 255));
 
+            if let Some((start, end)) = inspector.as_ref().and_then(|inspector| inspector.selected()) {
+                for block in blocks.iter() {
+                    if block.node >= start && block.node < end {
+                        window.rect(block.x - offset.0, block.y - offset.1, block.w as u32, block.h as u32, Color::rgb(255, 240, 160));
+                    }
+                }
+            }
+
             for block in blocks.iter() {
                 block.draw(&mut window, offset);
             }
 
+            if let Some((menu_x, menu_y)) = menu {
+                window.rect(menu_x, menu_y, MENU_WIDTH as u32, MENU.len() as u32 * 16, Color::rgb(224, 224, 224));
+                for (i, &(name, _)) in MENU.iter().enumerate() {
+                    let mut x = menu_x + 8;
+                    for c in name.chars() {
+                        window.char(x, menu_y + i as i32 * 16, c, Color::rgb(0, 0, 0));
+                        x += 8;
+                    }
+                }
+            }
+
             window.sync();
         }
 
+        // Wait for window events, unless a download is running, or has just stopped, and its progress is to be shown,
+        // or the inspector's window has to be looked at too
+        let shown = match content {
+            Content::Downloads => downloads_running,
+            _ => false
+        };
+        let running = shown || downloads.iter().any(|download| download.running());
+        window.set_async(running || inspector.is_some());
+
+        let mut events = false;
+        let mut action = None;
         for event in window.events() {
+            events = true;
             match event.to_option() {
-                EventOption::Key(key_event) => if key_event.pressed {
+                EventOption::Key(key_event) => if key_event.scancode == 0x1D {
+                    ctrl = key_event.pressed;
+                } else if key_event.pressed {
                     match key_event.scancode {
-                        K_ESC => return,
+                        K_ESC => if menu.is_some() {
+                            menu = None;
+                            redraw = true;
+                        } else {
+                            return;
+                        },
                         K_LEFT => {
                             redraw = true;
                             offset.0 = cmp::max(0, offset.0 - 60);
@@ -717,16 +1088,13 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
                             redraw = true;
                             offset.1 = cmp::min(cmp::max(0, max_offset.1 - window_h), offset.1 + 600);
                         },
-                        K_BKSP => if let Some(last_url) = history.pop() {
-                            url = last_url;
-                            reload = true;
-                        },
-                        K_ENTER => {
-                            if let Some(new_url) = open_dialog(&url) {
-                                url = new_url;
-                                reload = true;
-                            }
-                        },
+                        K_BKSP => action = Some(Action::Back),
+                        K_ENTER => action = Some(Action::Open),
+                        // Letters only act with Ctrl held, so they stay free for typing into pages
+                        K_I if ctrl => action = Some(Action::Inspect),
+                        K_U if ctrl => action = Some(Action::ViewSource),
+                        K_D if ctrl => action = Some(Action::Downloads),
+                        K_P if ctrl => action = Some(Action::Export),
                         _ => ()
                     }
                 },
@@ -735,33 +1103,65 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
                     mouse_y = mouse_event.y;
                 },
                 EventOption::Button(button_event) => {
+                    if button_event.right {
+                        mouse_right = true;
+                    } else if mouse_right {
+                        mouse_right = false;
+                        menu = Some((mouse_x, mouse_y));
+                        redraw = true;
+                    }
+
                     if button_event.left {
                         mouse_down = true;
                     } else if mouse_down {
                         mouse_down = false;
 
-                        let mut link_opt = None;
-                        for block in blocks.iter() {
-                            if block.contains(mouse_x, mouse_y, offset) {
-                                println!("Click {}", block.string);
-                                if let Some(ref link) = block.link {
-                                    link_opt = Some(link.clone());
-                                    break;
+                        if let Some((menu_x, menu_y)) = menu.take() {
+                            redraw = true;
+                            if mouse_x >= menu_x && mouse_x < menu_x + MENU_WIDTH && mouse_y >= menu_y {
+                                if let Some(&(_, menu_action)) = MENU.get(((mouse_y - menu_y) / 16) as usize) {
+                                    action = Some(menu_action);
                                 }
                             }
+                            continue;
                         }
 
-                        if let Some(link) = link_opt {
-                            if link.starts_with('#') {
-                                if let Some(anchor) = anchors.get(&link[1..]) {
-                                    println!("Anchor {}: {}", link, *anchor);
-                                    offset.0 = 0;
-                                    offset.1 = *anchor;
-                                    redraw = true;
-                                } else {
-                                    println!("Anchor {} not found", link);
+                        match click(&blocks, mouse_x, mouse_y, offset) {
+                            Some(Click::Launch(i)) => if let Some(download) = downloads.get(i) {
+                                if let Err(err) = Command::new(LAUNCH_COMMAND).arg(&download.path).spawn() {
+                                    println!("Failed to launch {}: {}", download.path.display(), err);
                                 }
+                            },
+                            Some(Click::Anchor(name)) => if let Some(anchor) = anchors.get(&name) {
+                                println!("Anchor #{}: {}", name, *anchor);
+                                offset.0 = 0;
+                                offset.1 = *anchor;
+                                redraw = true;
                             } else {
+                                println!("Anchor #{} not found", name);
+                            },
+                            Some(Click::Trust) => if let Content::Untrusted(ref host, _, _) = content {
+                                let title = format!("Trust certificate for {}", host);
+                                if let Some(path) = path_dialog(&title, &PathBuf::new(), "Trust") {
+                                    match http::check_pem_file(&path) {
+                                        Ok(()) => {
+                                            // Only what the config file already had is written back, not the environment
+                                            let mut config = http::Config::file();
+                                            config.exceptions.insert(host.to_lowercase(), path.clone());
+                                            if let Err(err) = config.save() {
+                                                println!("Failed to save browser config: {}", err);
+                                            }
+                                            // Kept for this session even if it could not be saved
+                                            let mut config = http::Config::load();
+                                            config.exceptions.insert(host.to_lowercase(), path);
+                                            http = Http::new(config);
+                                            reload = true;
+                                        },
+                                        Err(err) => println!("Failed to trust certificate: {}", err)
+                                    }
+                                }
+                            },
+                            Some(Click::Navigate(link)) => {
                                 history.push(url.clone());
 
                                 url = url.join(&link).unwrap();
@@ -769,7 +1169,8 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
                                 println!("Navigate {}: {:#?}", link, url);
 
                                 reload = true;
-                            }
+                            },
+                            None => ()
                         }
                     }
                 },
@@ -779,16 +1180,122 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
 
                     redraw = true;
                 },
-                EventOption::Resize(_) => {
-                    redraw = true;
+                EventOption::Resize(resize_event) => {
+                    window_w = resize_event.width as i32;
+                    window_h = resize_event.height as i32;
+                    relayout = true;
                 },
                 EventOption::Quit(_) => return,
                 _ => ()
             }
         }
+
+        match action {
+            Some(Action::Back) => if let Some(last_url) = history.pop() {
+                url = last_url;
+                reload = true;
+            },
+            Some(Action::Open) => if let Some(new_url) = open_dialog(&url) {
+                url = new_url;
+                reload = true;
+            },
+            Some(Action::ViewSource) => if url.scheme() != "view-source" {
+                if let Ok(source_url) = Url::parse(&format!("view-source:{}", url)) {
+                    history.push(url.clone());
+                    url = source_url;
+                    reload = true;
+                }
+            },
+            Some(Action::Inspect) => if let Content::Html(ref dom, _) = content {
+                inspector = Some(Inspector::new(dom, &format!("{}", url)));
+            },
+            Some(Action::Downloads) => if url.as_str() != DOWNLOADS_URL {
+                history.push(url.clone());
+                url = Url::parse(DOWNLOADS_URL).unwrap();
+                reload = true;
+            },
+            Some(Action::Export) => {
+                let path = download::download_path("page.pdf");
+                if let Some(path) = path_dialog("Export", &path, "Export") {
+                    if let Err(err) = export::export(&blocks, &url, font, font_bold, font_files, window_w, &path) {
+                        println!("{}", err);
+                    }
+                }
+            },
+            None => ()
+        }
+
+        let mut inspector_closed = false;
+        if let Some(ref mut inspector) = inspector {
+            let (changed, closed) = inspector.events();
+            if changed {
+                // Bring the first highlighted block into view
+                if let Some((start, end)) = inspector.selected() {
+                    if let Some(block) = blocks.iter().find(|block| block.node >= start && block.node < end) {
+                        if block.y < offset.1 || block.y + block.h > offset.1 + window_h {
+                            offset.1 = cmp::max(0, cmp::min(max_offset.1 - window_h, block.y));
+                        }
+                    }
+                }
+                redraw = true;
+            }
+            inspector_closed = closed;
+        }
+        if inspector_closed {
+            inspector = None;
+            redraw = true;
+        }
+
+        if ! events && ! reload && ! relayout && ! redraw {
+            thread::sleep(Duration::from_millis(16));
+        }
     }
 }
 
+/// Lay out a page without opening a window and write it to a PNG or PDF file
+fn export_main(arg: &str, font: &Font, font_bold: &Font, font_files: &FontFiles, http: &Http, width: i32, path: &Path) -> Result<(), String> {
+    let url = Url::parse(arg).map_err(|err| format!("Invalid URL {}: {}", arg, err))?;
+
+    let mut anchors = BTreeMap::new();
+    let mut images = BTreeMap::new();
+    let mut blocks = Vec::new();
+    let content = url_parse(http, &url);
+    layout(&content, &url, http, &[], font, font_bold, width, &mut anchors, &mut images, &mut blocks);
+
+    export::export(&blocks, &url, font, font_bold, font_files, width, path)
+}
+
+/// File of the font with the default typeface and family in a style, where orbfont looks for it on Redox
+#[cfg(target_os = "redox")]
+fn font_file(style: &str) -> Option<String> {
+    Some(format!("/ui/fonts/Mono/Fira/{}.ttf", style))
+}
+
+/// File of a monospace TrueType font in a style, as fontconfig picks the system's fonts
+#[cfg(not(target_os = "redox"))]
+fn font_file(style: &str) -> Option<String> {
+    let output = Command::new("fc-match").arg("-f").arg("%{file}").arg(format!("monospace:style={}:fontformat=TrueType", style)).output().ok()?;
+    let path = String::from_utf8(output.stdout).ok()?;
+    // PDF exports embed the file as TrueType, so anything else is no use
+    if output.status.success() && path.to_lowercase().ends_with(".ttf") {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Load a font from the file the lookup found, so exports embed the same font, or with `Font::find` and no file when
+/// there is none
+fn load_font(style: Option<&str>) -> Result<(Font, Option<PathBuf>), String> {
+    if let Some(path) = font_file(style.unwrap_or("Regular")) {
+        match Font::from_path(&path) {
+            Ok(font) => return Ok((font, Some(PathBuf::from(path)))),
+            Err(err) => println!("Failed to load font {}: {}", path, err)
+        }
+    }
+    Font::find(None, None, style).map(|font| (font, None)).map_err(|err| format!("{}", err))
+}
+
 fn main() {
     let err_window = |msg: &str| {
         let mut window = Window::new(-1, -1, 320, 32, "Browser").unwrap();
@@ -817,11 +1324,101 @@ fn main() {
         }
     };
 
-    match Font::find(None, None, None) {
-        Ok(font) => match Font::find(None, None, Some("Bold")) {
-            Ok(font_bold) => main_window(&env::args().nth(1).unwrap_or("https://www.redox-os.org".to_string()), &font, &font_bold),
-            Err(err) => err_window(&format!("{}", err))
+    let mut arg_opt = None;
+    let mut export_opt = None;
+    let mut export_width = 1024;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--export" => export_opt = args.next(),
+            "--width" => match args.next().map(|width| width.parse()) {
+                Some(Ok(width)) => export_width = width,
+                _ => println!("Invalid --width")
+            },
+            _ => arg_opt = Some(arg)
+        }
+    }
+    let arg = arg_opt.unwrap_or("https://www.redox-os.org".to_string());
+
+    match load_font(None) {
+        Ok((font, regular)) => match load_font(Some("Bold")) {
+            Ok((font_bold, bold)) => {
+                let font_files = FontFiles { regular: regular, bold: bold };
+                let http = Http::new(http::Config::load());
+                if let Some(export_path) = export_opt {
+                    if let Err(err) = export_main(&arg, &font, &font_bold, &font_files, &http, export_width, Path::new(&export_path)) {
+                        let _ = write!(stderr(), "browser: {}\n", err);
+                        process::exit(1);
+                    }
+                } else {
+                    main_window(&arg, &font, &font_bold, &font_files, http)
+                }
+            },
+            Err(err) => err_window(&err)
         },
-        Err(err) => err_window(&format!("{}", err))
+        Err(err) => err_window(&err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use hyper::header::{self, Headers};
+    use orbfont::Font;
+    use url::Url;
+
+    use super::{click, file_parse, layout, read_parse, Click, Content};
+    use http::{self, Http};
+
+    #[test]
+    fn content_type_parameters() {
+        // Spaces around the media type are no part of it, the same as when deciding whether the content is supported
+        let mut headers = Headers::new();
+        headers.set_raw("content-type", vec![b" text/plain ; charset=utf-8".to_vec()]);
+        match read_parse(headers, &mut &b"text"[..]) {
+            Content::Text(string) => assert_eq!(string, "text"),
+            _ => panic!("text/plain not read as text")
+        }
+    }
+
+    #[test]
+    fn file_unsupported() {
+        let path = env::temp_dir().join("browser-test-file");
+        File::create(&path).unwrap().write_all(&[0x00, 0xFF, 0xFE]).unwrap();
+
+        // A file that is not text is not shown as such, and has nothing to save it from
+        match file_parse(&Url::from_file_path(&path).unwrap()) {
+            Content::Unsupported(media_type, None) => assert_eq!(media_type, "application/octet-stream"),
+            _ => panic!("binary file not unsupported")
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn page_cannot_launch() {
+        // Without any system font there is nothing to lay the page out with
+        let font = match Font::find(None, None, None) {
+            Ok(font) => font,
+            Err(_) => return
+        };
+        let http = Http::new(http::Config::new());
+        let url = Url::parse("https://example.com/").unwrap();
+
+        let mut headers = Headers::new();
+        headers.set(header::ContentType("text/html".parse().unwrap()));
+        let content = read_parse(headers, &mut &b"<a href=\"launch:/bin/sh\">open</a>"[..]);
+
+        let mut anchors = BTreeMap::new();
+        let mut images = BTreeMap::new();
+        let mut blocks = Vec::new();
+        layout(&content, &url, &http, &[], &font, &font, 1024, &mut anchors, &mut images, &mut blocks);
+
+        // The link is followed like any other, which nothing answers, instead of opening the path
+        let block = blocks.iter().find(|block| block.link.is_some()).unwrap();
+        assert_eq!(click(&blocks, block.x, block.y, (0, 0)), Some(Click::Navigate("launch:/bin/sh".to_string())));
     }
 }
@@ -0,0 +1,92 @@
+use std::mem;
+
+use orbclient::Color;
+
+/// Where the highlighter is in the markup, carried from one line to the next
+#[derive(Clone, Copy, PartialEq)]
+pub enum State {
+    Text,
+    Tag,
+    Attribute,
+    Value(char),
+    Comment,
+}
+
+fn color(state: State) -> Color {
+    match state {
+        State::Text => Color::rgb(0, 0, 0),
+        State::Tag => Color::rgb(136, 18, 128),
+        State::Attribute => Color::rgb(153, 69, 0),
+        State::Value(_) => Color::rgb(26, 26, 166),
+        State::Comment => Color::rgb(35, 110, 37),
+    }
+}
+
+fn flush(segments: &mut Vec<(String, Color)>, current: &mut String, state: State) {
+    if ! current.is_empty() {
+        segments.push((mem::replace(current, String::new()), color(state)));
+    }
+}
+
+/// Split one line of HTML source into coloured segments
+pub fn highlight(line: &str, state: &mut State) -> Vec<(String, Color)> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+
+    for (i, c) in line.char_indices() {
+        match *state {
+            State::Text => if c == '<' {
+                flush(&mut segments, &mut current, *state);
+                *state = if line[i..].starts_with("<!--") {
+                    State::Comment
+                } else {
+                    State::Tag
+                };
+                current.push(c);
+            } else {
+                current.push(c);
+            },
+            State::Tag => if c == '>' {
+                current.push(c);
+                flush(&mut segments, &mut current, *state);
+                *state = State::Text;
+            } else if c.is_whitespace() {
+                flush(&mut segments, &mut current, *state);
+                *state = State::Attribute;
+                current.push(c);
+            } else {
+                current.push(c);
+            },
+            State::Attribute => if c == '>' {
+                flush(&mut segments, &mut current, *state);
+                current.push(c);
+                flush(&mut segments, &mut current, State::Tag);
+                *state = State::Text;
+            } else if c == '"' || c == '\'' {
+                flush(&mut segments, &mut current, *state);
+                *state = State::Value(c);
+                current.push(c);
+            } else {
+                current.push(c);
+            },
+            State::Value(quote) => {
+                current.push(c);
+                if c == quote {
+                    flush(&mut segments, &mut current, *state);
+                    *state = State::Attribute;
+                }
+            },
+            State::Comment => {
+                current.push(c);
+                if c == '>' && line[..i + 1].ends_with("-->") {
+                    flush(&mut segments, &mut current, *state);
+                    *state = State::Text;
+                }
+            }
+        }
+    }
+
+    flush(&mut segments, &mut current, *state);
+
+    segments
+}
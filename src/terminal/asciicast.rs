@@ -0,0 +1,260 @@
+//! Sessions recorded in the asciicast v2 format: a JSON header line, then one `[time, code, data]` line per event
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::str;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Writes the output of a shell to a recording as it arrives
+pub struct Recorder {
+    file: File,
+    start: Instant,
+    /// Start of a UTF-8 sequence split between reads, held back until the rest of it arrives
+    partial: Vec<u8>,
+}
+
+impl Recorder {
+    /// Start a recording of a console `width` by `height` cells
+    pub fn create(path: &str, width: usize, height: usize, term: &str) -> Result<Recorder, String> {
+        let mut file = File::create(path).map_err(|err| format!("failed to create {}: {}", path, err))?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        writeln!(file, "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}, \"env\": {{\"TERM\": {}}}}}",
+                 width, height, timestamp, escape(term))
+            .map_err(|err| format!("failed to write {}: {}", path, err))?;
+
+        Ok(Recorder {
+            file: file,
+            start: Instant::now(),
+            partial: Vec::new(),
+        })
+    }
+
+    fn event(&mut self, code: &str, data: &str) {
+        let elapsed = self.start.elapsed();
+        let time = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        if let Err(err) = writeln!(self.file, "[{:.6}, \"{}\", {}]", time, code, escape(data)) {
+            println!("terminal: failed to record: {}", err);
+        }
+    }
+
+    /// Record bytes written by the shell
+    pub fn output(&mut self, buf: &[u8]) {
+        let mut data = Vec::with_capacity(self.partial.len() + buf.len());
+        data.extend_from_slice(&self.partial);
+        data.extend_from_slice(buf);
+        self.partial.clear();
+
+        // Recordings hold text, so an incomplete sequence at the end waits for the next read and invalid bytes are replaced
+        let end = match str::from_utf8(&data) {
+            Ok(_) => data.len(),
+            Err(err) => match err.error_len() {
+                None => err.valid_up_to(),
+                Some(_) => data.len()
+            }
+        };
+        self.partial.extend_from_slice(&data[end..]);
+
+        if end > 0 {
+            let text = String::from_utf8_lossy(&data[..end]).into_owned();
+            self.event("o", &text);
+        }
+    }
+
+    /// Record the console changing size
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.event("r", &format!("{}x{}", width, height));
+    }
+}
+
+/// Quote a string for JSON
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\0' ... '\x1F' | '\x7F' => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Reads the JSON values found in recordings, just enough of JSON for the header and the event lines
+struct Parser<'a> {
+    bytes: &'a [u8],
+    i: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Parser<'a> {
+        Parser {
+            bytes: text.as_bytes(),
+            i: 0,
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while self.i < self.bytes.len() && (self.bytes[self.i] as char).is_whitespace() {
+            self.i += 1;
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Option<()> {
+        self.skip_space();
+        if self.bytes.get(self.i) == Some(&b) {
+            self.i += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        self.skip_space();
+        let start = self.i;
+        while let Some(&b) = self.bytes.get(self.i) {
+            match b {
+                b'0' ... b'9' | b'.' | b'-' | b'+' | b'e' | b'E' => self.i += 1,
+                _ => break
+            }
+        }
+        str::from_utf8(&self.bytes[start..self.i]).ok().and_then(|number| number.parse().ok())
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let b = *self.bytes.get(self.i)?;
+            self.i += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self.bytes.get(self.i)?;
+                    self.i += 1;
+                    match escaped {
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0C),
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // Characters outside the BMP come as a surrogate pair
+                            if code >= 0xD800 && code < 0xDC00 && self.bytes.get(self.i..self.i + 2) == Some(&b"\\u"[..]) {
+                                self.i += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            let c = ::std::char::from_u32(code).unwrap_or('\u{FFFD}');
+                            let mut buf = [0; 4];
+                            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        },
+                        other => bytes.push(other)
+                    }
+                },
+                _ => bytes.push(b)
+            }
+        }
+        String::from_utf8(bytes).ok()
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.bytes.get(self.i..self.i + 4)?;
+        self.i += 4;
+        u32::from_str_radix(str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
+
+/// The number following `"key":` in the header, which is all of it that replay needs
+fn header_field(header: &str, key: &str) -> Option<usize> {
+    let pattern = format!("\"{}\"", key);
+    let start = header.find(&pattern)? + pattern.len();
+    let mut parser = Parser::new(&header[start..]);
+    parser.expect(b':')?;
+    parser.number().map(|number| number as usize)
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Event {
+    Output(String),
+    /// New size in cells
+    Resize(usize, usize),
+}
+
+/// A recording read back in full, with events at times in seconds from its start
+pub struct Recording {
+    pub width: usize,
+    pub height: usize,
+    pub events: Vec<(f64, Event)>,
+}
+
+impl Recording {
+    pub fn open(path: &str) -> Result<Recording, String> {
+        let file = File::open(path).map_err(|err| format!("failed to open {}: {}", path, err))?;
+        let mut lines = BufReader::new(file).lines();
+
+        let header = match lines.next() {
+            Some(line) => line.map_err(|err| format!("failed to read {}: {}", path, err))?,
+            None => return Err(format!("{} is empty", path))
+        };
+        if header_field(&header, "version") != Some(2) {
+            return Err(format!("{} is not an asciicast v2 recording", path));
+        }
+        let width = header_field(&header, "width").ok_or_else(|| format!("{} has no width", path))?;
+        let height = header_field(&header, "height").ok_or_else(|| format!("{} has no height", path))?;
+
+        let mut events = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line.map_err(|err| format!("failed to read {}: {}", path, err))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut parser = Parser::new(&line);
+            let event = parser.expect(b'[').and_then(|_| {
+                let time = parser.number()?;
+                parser.expect(b',')?;
+                let code = parser.string()?;
+                parser.expect(b',')?;
+                let data = parser.string()?;
+                Some((time, code, data))
+            });
+
+            match event {
+                Some((time, code, data)) => match code.as_str() {
+                    "o" => events.push((time, Event::Output(data))),
+                    "r" => {
+                        let mut parts = data.splitn(2, 'x');
+                        let w = parts.next().and_then(|w| w.parse().ok());
+                        let h = parts.next().and_then(|h| h.parse().ok());
+                        if let (Some(w), Some(h)) = (w, h) {
+                            events.push((time, Event::Resize(w, h)));
+                        }
+                    },
+                    // Input and markers are not shown
+                    _ => ()
+                },
+                None => println!("terminal: {}:{}: invalid event", path, i + 2)
+            }
+        }
+
+        Ok(Recording {
+            width: width,
+            height: height,
+            events: events,
+        })
+    }
+
+    /// Time of the last event
+    pub fn duration(&self) -> f64 {
+        self.events.last().map_or(0.0, |&(time, _)| time)
+    }
+}
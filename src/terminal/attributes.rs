@@ -0,0 +1,377 @@
+use theme::Theme;
+
+// Cell attributes other than bold, as bits of `Block::attrs`
+pub const DIM: u8 = 1;
+pub const ITALIC: u8 = 2;
+pub const UNDERLINE: u8 = 4;
+pub const BLINK: u8 = 8;
+pub const REVERSE: u8 = 16;
+pub const HIDDEN: u8 = 32;
+pub const STRIKETHROUGH: u8 = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Colour {
+    Default,
+    /// One of the 256 xterm colours, the first 16 taken from the theme
+    Indexed(u8),
+    Rgb(u32),
+}
+
+impl Colour {
+    fn resolve(&self, theme: &Theme, default: u32) -> u32 {
+        match *self {
+            Colour::Default => default,
+            Colour::Indexed(i) => indexed(theme, i),
+            Colour::Rgb(rgb) => rgb | 0xFF000000,
+        }
+    }
+}
+
+/// RGB value of one of the 256 xterm colours
+pub fn indexed(theme: &Theme, i: u8) -> u32 {
+    match i {
+        0 ... 15 => theme.palette[i as usize],
+        16 ... 231 => {
+            let level = |value: u8| -> u32 {
+                if value == 0 { 0 } else { value as u32 * 40 + 55 }
+            };
+            let i = i - 16;
+            0xFF000000 | level(i / 36) << 16 | level((i / 6) % 6) << 8 | level(i % 6)
+        },
+        _ => {
+            let gray = (i - 232) as u32 * 10 + 8;
+            0xFF000000 | gray << 16 | gray << 8 | gray
+        }
+    }
+}
+
+/// Graphic rendition set by the program with SGR sequences
+#[derive(Clone, Copy)]
+pub struct Attributes {
+    pub bold: bool,
+    pub attrs: u8,
+    pub foreground: Colour,
+    pub background: Colour,
+}
+
+impl Attributes {
+    pub fn new() -> Attributes {
+        Attributes {
+            bold: false,
+            attrs: 0,
+            foreground: Colour::Default,
+            background: Colour::Default,
+        }
+    }
+
+    /// Foreground and background as stored in a cell, before reverse video and the other attributes apply
+    pub fn colors(&self, theme: &Theme) -> (u32, u32) {
+        (self.foreground.resolve(theme, theme.foreground), self.background.resolve(theme, theme.background))
+    }
+
+    fn set(&mut self, attr: u8, on: bool) {
+        if on {
+            self.attrs |= attr;
+        } else {
+            self.attrs &= !attr;
+        }
+    }
+
+    /// Apply the parameters of one SGR sequence, each a list of colon separated parts
+    pub fn apply(&mut self, params: &[Vec<u32>]) {
+        if params.is_empty() {
+            *self = Attributes::new();
+            return;
+        }
+
+        let mut i = 0;
+        while i < params.len() {
+            let param = &params[i];
+            let code = param.get(0).cloned().unwrap_or(0);
+            match code {
+                0 => *self = Attributes::new(),
+                1 => self.bold = true,
+                2 => self.set(DIM, true),
+                3 => self.set(ITALIC, true),
+                // 4:0 turns underlining off, the other styles are all drawn as a single line
+                4 => self.set(UNDERLINE, param.get(1).cloned().unwrap_or(1) != 0),
+                5 | 6 => self.set(BLINK, true),
+                7 => self.set(REVERSE, true),
+                8 => self.set(HIDDEN, true),
+                9 => self.set(STRIKETHROUGH, true),
+                21 => self.set(UNDERLINE, true),
+                22 => {
+                    self.bold = false;
+                    self.set(DIM, false);
+                },
+                23 => self.set(ITALIC, false),
+                24 => self.set(UNDERLINE, false),
+                25 => self.set(BLINK, false),
+                27 => self.set(REVERSE, false),
+                28 => self.set(HIDDEN, false),
+                29 => self.set(STRIKETHROUGH, false),
+                30 ... 37 => self.foreground = Colour::Indexed((code - 30) as u8),
+                39 => self.foreground = Colour::Default,
+                40 ... 47 => self.background = Colour::Indexed((code - 40) as u8),
+                49 => self.background = Colour::Default,
+                90 ... 97 => self.foreground = Colour::Indexed((code - 90 + 8) as u8),
+                100 ... 107 => self.background = Colour::Indexed((code - 100 + 8) as u8),
+                38 | 48 | 58 => {
+                    // Either 38:5:n and 38:2:[colourspace:]r:g:b, or the same split over separate parameters
+                    let parts: Vec<u32> = if param.len() > 1 {
+                        param[1..].to_vec()
+                    } else {
+                        params[i + 1..].iter().map(|param| param.get(0).cloned().unwrap_or(0)).collect()
+                    };
+
+                    let (colour, used) = match parts.get(0).cloned() {
+                        Some(5) if parts.len() >= 2 => (Some(Colour::Indexed(parts[1] as u8)), 2),
+                        Some(2) if param.len() > 5 => (Some(rgb(parts[2], parts[3], parts[4])), 5),
+                        Some(2) if parts.len() >= 4 => (Some(rgb(parts[1], parts[2], parts[3])), 4),
+                        _ => (None, parts.len())
+                    };
+
+                    if let Some(colour) = colour {
+                        match code {
+                            38 => self.foreground = colour,
+                            48 => self.background = colour,
+                            // Underline colour is not drawn separately
+                            _ => ()
+                        }
+                    }
+
+                    if param.len() == 1 {
+                        i += used;
+                    }
+                },
+                _ => ()
+            }
+            i += 1;
+        }
+    }
+}
+
+fn rgb(r: u32, g: u32, b: u32) -> Colour {
+    Colour::Rgb((r & 0xFF) << 16 | (g & 0xFF) << 8 | (b & 0xFF))
+}
+
+/// Longest OSC string kept, longer ones are dropped
+const OSC_MAX: usize = 1 << 20;
+
+/// Longest DCS or APC string kept, large enough for the images sent in them
+const STRING_MAX: usize = 1 << 24;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    /// Inside a CSI sequence that is not handled, skipped up to its final byte
+    Ignore,
+    /// After the space in a CSI sequence, which ends with `q` for DECSCUSR
+    CsiSpace,
+    Osc,
+    /// An escape inside an OSC string, the start of the `ESC \` terminator
+    OscEscape,
+    Dcs,
+    DcsEscape,
+    Apc,
+    ApcEscape,
+}
+
+/// A sequence the terminal handles itself instead of leaving it to ransid
+pub enum Sequence {
+    /// Parameters of an SGR sequence, each a list of colon separated parts
+    Sgr(Vec<Vec<u32>>),
+    /// Text of an OSC sequence, between `ESC ]` and its BEL or `ESC \` terminator
+    Osc(Vec<u8>),
+    /// Parameter of DECSCUSR, `CSI Ps SP q`, which sets the cursor shape and whether it blinks
+    CursorStyle(u32),
+    /// Contents of a DCS string, between `ESC P` and `ESC \`
+    Dcs(Vec<u8>),
+    /// Contents of an APC string, between `ESC _` and `ESC \`
+    Apc(Vec<u8>),
+    /// Modes set with `CSI Pm h` or reset with `CSI Pm l`, and whether they are set
+    Mode(Vec<u32>, bool),
+    /// DEC private modes set with `CSI ? Pm h` or reset with `CSI ? Pm l`, and whether they are set
+    PrivateMode(Vec<u32>, bool),
+    /// Whether the number pad is switched to application mode with `ESC =` (DECKPAM) or back with `ESC >` (DECKPNM)
+    Keypad(bool),
+    /// Full reset with `ESC c`
+    Reset,
+}
+
+/// Picks SGR, DECSCUSR, mode, keypad, OSC, DCS and APC sequences and resets out of the output, one byte at a time so
+/// sequences may be split between writes
+pub struct SequenceParser {
+    state: State,
+    params: Vec<u8>,
+    /// Whether the CSI sequence being read started with `?`
+    private: bool,
+    osc: Vec<u8>,
+    /// Contents of the DCS or APC string being read
+    string: Vec<u8>,
+}
+
+impl SequenceParser {
+    pub fn new() -> SequenceParser {
+        SequenceParser {
+            state: State::Ground,
+            params: Vec::new(),
+            private: false,
+            osc: Vec::new(),
+            string: Vec::new(),
+        }
+    }
+
+    /// Whether the parser is outside any sequence, so the next byte is text or the start of a sequence
+    pub fn ground(&self) -> bool {
+        self.state == State::Ground
+    }
+
+    /// Whether the parser is inside a DCS or APC string, whose bytes ransid must not see
+    pub fn hidden(&self) -> bool {
+        match self.state {
+            State::Dcs | State::DcsEscape | State::Apc | State::ApcEscape => true,
+            _ => false
+        }
+    }
+
+    /// Feed one byte, returning the sequence it completes
+    pub fn advance(&mut self, b: u8) -> Option<Sequence> {
+        match self.state {
+            State::Ground => if b == 0x1B {
+                self.state = State::Escape;
+            },
+            State::Escape => match b {
+                b'[' => {
+                    self.params.clear();
+                    self.private = false;
+                    self.state = State::Csi;
+                },
+                b']' => {
+                    self.osc.clear();
+                    self.state = State::Osc;
+                },
+                b'P' => {
+                    self.string.clear();
+                    self.state = State::Dcs;
+                },
+                b'_' => {
+                    self.string.clear();
+                    self.state = State::Apc;
+                },
+                b'c' => {
+                    self.state = State::Ground;
+                    return Some(Sequence::Reset);
+                },
+                b'=' | b'>' => {
+                    self.state = State::Ground;
+                    return Some(Sequence::Keypad(b == b'='));
+                },
+                0x1B => (),
+                _ => self.state = State::Ground
+            },
+            State::Csi => match b {
+                b'0' ... b'9' | b';' | b':' => self.params.push(b),
+                b'?' if self.params.is_empty() && ! self.private => self.private = true,
+                b'm' if ! self.private => {
+                    self.state = State::Ground;
+                    return Some(Sequence::Sgr(self.parse()));
+                },
+                b'h' | b'l' => {
+                    self.state = State::Ground;
+                    let modes = self.parse().iter().map(|param| param.first().cloned().unwrap_or(0)).collect();
+                    return Some(if self.private { Sequence::PrivateMode(modes, b == b'h') } else { Sequence::Mode(modes, b == b'h') });
+                },
+                b' ' if ! self.private => self.state = State::CsiSpace,
+                0x1B => self.state = State::Escape,
+                0x18 | 0x1A => self.state = State::Ground,
+                0x40 ... 0x7E => self.state = State::Ground,
+                _ => self.state = State::Ignore
+            },
+            State::CsiSpace => match b {
+                b'q' => {
+                    self.state = State::Ground;
+                    let style = self.parse().first().and_then(|param| param.first().cloned()).unwrap_or(0);
+                    return Some(Sequence::CursorStyle(style));
+                },
+                0x1B => self.state = State::Escape,
+                0x18 | 0x1A | 0x40 ... 0x7E => self.state = State::Ground,
+                _ => self.state = State::Ignore
+            },
+            State::Ignore => match b {
+                0x1B => self.state = State::Escape,
+                0x18 | 0x1A | 0x40 ... 0x7E => self.state = State::Ground,
+                _ => ()
+            },
+            State::Osc => match b {
+                0x07 => {
+                    self.state = State::Ground;
+                    return self.finish_osc();
+                },
+                0x1B => self.state = State::OscEscape,
+                0x18 | 0x1A => self.state = State::Ground,
+                _ => if self.osc.len() < OSC_MAX {
+                    self.osc.push(b);
+                }
+            },
+            State::OscEscape => match b {
+                b'\\' => {
+                    self.state = State::Ground;
+                    return self.finish_osc();
+                },
+                // Any other escape ends the string and starts a new sequence
+                b'[' => {
+                    self.params.clear();
+                    self.private = false;
+                    self.state = State::Csi;
+                },
+                b']' => {
+                    self.osc.clear();
+                    self.state = State::Osc;
+                },
+                _ => self.state = State::Ground
+            },
+            State::Dcs | State::Apc => match b {
+                0x1B => self.state = if self.state == State::Dcs { State::DcsEscape } else { State::ApcEscape },
+                0x18 | 0x1A => self.state = State::Ground,
+                _ => if self.string.len() < STRING_MAX {
+                    self.string.push(b);
+                }
+            },
+            State::DcsEscape | State::ApcEscape => {
+                let dcs = self.state == State::DcsEscape;
+                self.state = State::Ground;
+                // Anything but the terminator cancels the string
+                if b == b'\\' && self.string.len() < STRING_MAX {
+                    let string = ::std::mem::replace(&mut self.string, Vec::new());
+                    return Some(if dcs { Sequence::Dcs(string) } else { Sequence::Apc(string) });
+                }
+                self.string.clear();
+            }
+        }
+        None
+    }
+
+    fn finish_osc(&mut self) -> Option<Sequence> {
+        if self.osc.len() < OSC_MAX {
+            Some(Sequence::Osc(::std::mem::replace(&mut self.osc, Vec::new())))
+        } else {
+            self.osc.clear();
+            None
+        }
+    }
+
+    fn parse(&self) -> Vec<Vec<u32>> {
+        if self.params.is_empty() {
+            return Vec::new();
+        }
+
+        self.params.split(|&b| b == b';').map(|param| {
+            param.split(|&b| b == b':').map(|part| {
+                part.iter().fold(0u32, |value, &digit| value.saturating_mul(10).saturating_add((digit - b'0') as u32))
+            }).collect()
+        }).collect()
+    }
+}
@@ -0,0 +1,261 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use theme::{parse_color, Theme};
+
+/// How the cursor is drawn over its cell while the terminal has the keyboard
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
+/// What programs may do with the clipboard through OSC 52
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipboardAccess {
+    None,
+    /// Only copy to it, as editors over SSH do
+    Write,
+    /// Also read it, which lets any program that can write to the terminal see what was copied
+    ReadWrite,
+}
+
+/// Terminal settings, read from `key=value` lines in the config file.
+///
+/// Lines after a `[name]` line belong to the profile of that name, and are only applied, after the lines above the
+/// first profile, when it is picked with `--profile`.
+pub struct Config {
+    /// Number of rows kept after they scroll off the top of the screen
+    pub scrollback: usize,
+    /// Family searched for with `Font::find`, such as `Fira`
+    pub font_family: Option<String>,
+    /// TrueType file to use instead of searching by family
+    pub font_path: Option<String>,
+    pub font_bold_path: Option<String>,
+    /// Fonts tried in turn for characters the main font has no glyph for, as families or TrueType files, one for
+    /// each `font_fallback` line
+    pub font_fallback: Vec<String>,
+    /// Font height in pixels, from which the cell size is measured
+    pub font_size: f32,
+    /// Cursor shape until the program sets another with DECSCUSR, and again after it resets it
+    pub cursor_shape: CursorShape,
+    pub cursor_blink: bool,
+    /// Time the cursor stays shown, and then hidden, when it blinks
+    pub cursor_blink_ms: u64,
+    pub theme: Theme,
+    pub clipboard_access: ClipboardAccess,
+    /// Program run with a title and a body to show the notifications programs send with OSC 9 and OSC 777, if any
+    pub notify_command: Option<String>,
+    /// Shell and arguments, split at spaces, used when none are given on the command line
+    pub command: Vec<String>,
+    /// Directory the shell is started in, instead of the terminal's own
+    pub working_directory: Option<String>,
+    /// Variables added to the shell's environment, one for each `env=NAME=value` line
+    pub env: Vec<(String, String)>,
+    /// `TERM` given to the shell, instead of our terminfo entry or xterm's
+    pub term: Option<String>,
+    /// Size of the window in cells, instead of a share of the display
+    pub columns: Option<usize>,
+    pub rows: Option<usize>,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            scrollback: 1000,
+            font_family: None,
+            font_path: None,
+            font_bold_path: None,
+            font_fallback: Vec::new(),
+            font_size: 16.0,
+            cursor_shape: CursorShape::Block,
+            cursor_blink: false,
+            cursor_blink_ms: 500,
+            theme: Theme::named("default").unwrap(),
+            clipboard_access: ClipboardAccess::Write,
+            notify_command: Some("notify-send".to_string()),
+            command: Vec::new(),
+            working_directory: None,
+            env: Vec::new(),
+            term: None,
+            columns: None,
+            rows: None,
+        }
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        env::var("HOME").ok().map(|home| Path::new(&home).join(".config").join("terminal.conf"))
+    }
+
+    /// Read the config file, with the lines of `profile` applied over the rest
+    pub fn load(profile: Option<&str>) -> Config {
+        let mut info = String::new();
+        if let Some(path) = Config::path() {
+            if let Ok(mut file) = File::open(path) {
+                let _ = file.read_to_string(&mut info);
+            }
+        }
+
+        Config::parse(&info, profile)
+    }
+
+    pub fn parse(info: &str, profile: Option<&str>) -> Config {
+        let mut config = Config::new();
+
+        // Lines of the picked profile are kept until the rest are applied, so the profile wins wherever it is in the file
+        let mut section = None;
+        let mut found = false;
+        let mut lines = Vec::new();
+        for line in info.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].trim();
+                found |= Some(name) == profile;
+                section = Some(name);
+                continue;
+            }
+
+            match section {
+                None => config.set_line(line),
+                Some(name) => if Some(name) == profile {
+                    lines.push(line);
+                }
+            }
+        }
+
+        if let Some(profile) = profile {
+            if ! found {
+                println!("terminal: Unknown profile {}", profile);
+            }
+        }
+        for line in lines {
+            config.set_line(line);
+        }
+
+        config
+    }
+
+    fn set_line(&mut self, line: &str) {
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        if let Err(err) = self.set(key, value) {
+            println!("{}", err);
+        }
+    }
+
+    /// Apply one setting, from a line of the config file or a `--key value` option.
+    ///
+    /// A theme replaces every colour, so it should come before any single colour it is meant to keep.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "scrollback" => match value.parse() {
+                Ok(rows) => self.scrollback = rows,
+                Err(err) => return Err(format!("Invalid scrollback {}: {}", value, err))
+            },
+            "font_family" => self.font_family = Some(value.to_string()),
+            "font_path" => self.font_path = Some(value.to_string()),
+            "font_bold_path" => self.font_bold_path = Some(value.to_string()),
+            "font_fallback" => self.font_fallback.push(value.to_string()),
+            "font_size" => match value.parse::<f32>() {
+                Ok(size) if size >= 4.0 => self.font_size = size,
+                _ => return Err(format!("Invalid font_size {}", value))
+            },
+            "cursor_shape" => self.cursor_shape = match value {
+                "block" => CursorShape::Block,
+                "underline" => CursorShape::Underline,
+                "bar" => CursorShape::Bar,
+                _ => return Err(format!("Invalid cursor_shape {}, expected block, underline or bar", value))
+            },
+            "cursor_blink" => match value.parse() {
+                Ok(blink) => self.cursor_blink = blink,
+                Err(err) => return Err(format!("Invalid cursor_blink {}: {}", value, err))
+            },
+            "cursor_blink_ms" => match value.parse::<u64>() {
+                Ok(ms) if ms > 0 => self.cursor_blink_ms = ms,
+                _ => return Err(format!("Invalid cursor_blink_ms {}", value))
+            },
+            "clipboard_access" => self.clipboard_access = match value {
+                "none" => ClipboardAccess::None,
+                "write" => ClipboardAccess::Write,
+                "read_write" => ClipboardAccess::ReadWrite,
+                _ => return Err(format!("Invalid clipboard_access {}, expected none, write or read_write", value))
+            },
+            "notify_command" => self.notify_command = if value.is_empty() { None } else { Some(value.to_string()) },
+            "command" => self.command = value.split_whitespace().map(|arg| arg.to_string()).collect(),
+            "working_directory" => self.working_directory = Some(value.to_string()),
+            "env" => match value.find('=') {
+                Some(i) if i > 0 => self.env.push((value[..i].to_string(), value[i + 1..].to_string())),
+                _ => return Err(format!("Invalid env {}, expected NAME=value", value))
+            },
+            "term" => self.term = Some(value.to_string()),
+            "columns" => match value.parse::<usize>() {
+                Ok(columns) if columns > 0 => self.columns = Some(columns),
+                _ => return Err(format!("Invalid columns {}", value))
+            },
+            "rows" => match value.parse::<usize>() {
+                Ok(rows) if rows > 0 => self.rows = Some(rows),
+                _ => return Err(format!("Invalid rows {}", value))
+            },
+            "theme" => match Theme::named(value) {
+                Some(theme) => self.theme = theme,
+                None => return Err(format!("Unknown theme {}, expected one of {}", value, Theme::names().join(", ")))
+            },
+            "foreground" => self.theme.foreground = parse_color(value).map_err(|err| format!("Invalid foreground {}", err))?,
+            "background" => self.theme.background = parse_color(value).map_err(|err| format!("Invalid background {}", err))?,
+            "cursor" => self.theme.cursor = parse_color(value).map_err(|err| format!("Invalid cursor {}", err))?,
+            _ => if key.starts_with("color") {
+                match key[5..].parse::<usize>() {
+                    Ok(i) if i < 16 => self.theme.palette[i] = parse_color(value).map_err(|err| format!("Invalid {} {}", key, err))?,
+                    _ => return Err(format!("Unknown terminal config: {}={}", key, value))
+                }
+            } else {
+                return Err(format!("Unknown terminal config: {}={}", key, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    const INFO: &'static str = "\
+font_size=14
+term=xterm
+
+[work]
+command=ssh -t host
+working_directory=/home/user/work
+env=EDITOR=vi
+columns=120
+
+[other]
+font_size=20
+";
+
+    #[test]
+    fn profile() {
+        let config = Config::parse(INFO, Some("work"));
+        assert_eq!(config.font_size, 14.0);
+        assert_eq!(config.term, Some("xterm".to_string()));
+        assert_eq!(config.command, vec!["ssh", "-t", "host"]);
+        assert_eq!(config.working_directory, Some("/home/user/work".to_string()));
+        assert_eq!(config.env, vec![("EDITOR".to_string(), "vi".to_string())]);
+        assert_eq!((config.columns, config.rows), (Some(120), None));
+
+        // Without a profile only the lines above the first one count
+        let config = Config::parse(INFO, None);
+        assert_eq!(config.font_size, 14.0);
+        assert!(config.command.is_empty());
+        assert_eq!(config.columns, None);
+    }
+}
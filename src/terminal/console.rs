@@ -1,280 +1,577 @@
-extern crate ransid;
-
 use std::{cmp, mem};
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeSet, HashMap};
 use std::io::Result;
+use std::time::{Duration, Instant};
 
-use orbclient::{Color, EventOption, Renderer, Window, WindowFlag};
+use orbclient::{Color, EventOption, Renderer};
 use orbfont::Font;
+use orbimage::Image;
+
+use attributes::{BLINK, DIM, HIDDEN, ITALIC, REVERSE, STRIKETHROUGH, UNDERLINE};
+use config::{Config, CursorShape};
+use graphics::Placement;
+use links::{self, Target};
+use scroll;
+use search::Search;
+use state::{Block, State};
+
+/// How much of each pixel of an area `w` by `h` is covered when `text` is drawn in it
+fn coverage(font: &Font, text: &str, size: f32, w: usize, h: usize) -> Vec<u8> {
+    // Drawn white on black, the red channel is the coverage
+    let mut image = Image::from_color(w as u32, h as u32, Color::rgb(0, 0, 0));
+    font.render(text, size).draw(&mut image, 0, 0, Color::rgb(255, 255, 255));
+    image.data().iter().map(|pixel| (pixel.data >> 16) as u8).collect()
+}
+
+/// Whether a font has a glyph for `c`, going by whether it draws it differently from a noncharacter
+fn has_glyph(font: &Font, c: char, size: f32, w: usize, h: usize) -> bool {
+    let mut str_buf = [0; 4];
+    c.is_whitespace() || coverage(font, c.encode_utf8(&mut str_buf), size, w, h) != coverage(font, "\u{FFFF}", size, w, h)
+}
+
+/// Regular and bold fonts, with the cell size measured from them
+pub struct Fonts {
+    pub regular: Font,
+    pub bold: Font,
+    /// Tried in turn for characters the regular or bold font has no glyph for
+    pub fallback: Vec<Font>,
+    pub size: f32,
+    pub cell_w: usize,
+    pub cell_h: usize,
+    /// Rendered glyphs by text, width in cells, boldness and colour, with coverage in the alpha channel
+    glyphs: HashMap<(String, usize, bool, u32), Box<[u32]>>,
+}
+
+impl Fonts {
+    pub fn new(config: &Config) -> ::std::result::Result<Fonts, String> {
+        let family = config.font_family.as_ref().map(|family| family.as_str());
+
+        let regular = match config.font_path {
+            Some(ref path) => Font::from_path(path),
+            None => Font::find(None, family, None)
+        }.map_err(|err| format!("failed to open font: {}", err))?;
+
+        // A font given by path is also used for bold, unless a bold one is given too
+        let bold = match (config.font_bold_path.as_ref(), config.font_path.as_ref()) {
+            (Some(path), _) | (None, Some(path)) => Font::from_path(path),
+            (None, None) => Font::find(None, family, Some("Bold"))
+        }.map_err(|err| format!("failed to open bold font: {}", err))?;
+
+        let mut fallback = Vec::new();
+        for name in config.font_fallback.iter() {
+            // A path has a slash in it, anything else is a family
+            let font = if name.contains('/') {
+                Font::from_path(name)
+            } else {
+                Font::find(None, Some(name), None)
+            };
+            match font {
+                Ok(font) => fallback.push(font),
+                Err(err) => println!("terminal: failed to open fallback font {}: {}", name, err)
+            }
+        }
+
+        // The font is monospaced, so any one glyph gives the cell size
+        let (cell_w, cell_h) = {
+            let text = regular.render("M", config.font_size);
+            (cmp::max(1, text.width() as usize), cmp::max(1, text.height() as usize))
+        };
+
+        Ok(Fonts {
+            regular: regular,
+            bold: bold,
+            fallback: fallback,
+            size: config.font_size,
+            cell_w: cell_w,
+            cell_h: cell_h,
+            glyphs: HashMap::new(),
+        })
+    }
 
-#[cfg(target_arch = "x86_64")]
-#[inline(always)]
-#[cold]
-pub unsafe fn fast_copy(dst: *mut u8, src: *const u8, len: usize) {
-    asm!("cld
-        rep movsb"
-        :
-        : "{rdi}"(dst as usize), "{rsi}"(src as usize), "{rcx}"(len)
-        : "cc", "memory", "rdi", "rsi", "rcx"
-        : "intel", "volatile");
+    /// `text` drawn `cells` wide, in the first font from regular or bold then the fallbacks that has its first character
+    fn glyph(&mut self, text: &str, cells: usize, bold: bool, color: u32) -> &[u32] {
+        let key = (text.to_string(), cells, bold, color);
+        if self.glyphs.len() >= 4096 && ! self.glyphs.contains_key(&key) {
+            self.glyphs.clear();
+        }
+
+        let (w, h, size) = (self.cell_w * cells, self.cell_h, self.size);
+        let main = if bold { &self.bold } else { &self.regular };
+        let fallback = &self.fallback;
+        self.glyphs.entry(key).or_insert_with(|| {
+            let c = text.chars().next().unwrap_or(' ');
+            let font = if has_glyph(main, c, size, w, h) {
+                main
+            } else {
+                fallback.iter().find(|font| has_glyph(font, c, size, w, h)).unwrap_or(main)
+            };
+            coverage(font, text, size, w, h).iter().map(|&alpha| (alpha as u32) << 24 | (color & 0xFFFFFF)).collect::<Vec<u32>>().into_boxed_slice()
+        })
+    }
+
+    /// Draw cell `part` of `text` drawn `cells` wide into the cell at `x`, `y`, so each half of a wide character can be
+    /// drawn on its own
+    pub fn draw_text(&mut self, image: &mut Image, x: usize, y: usize, text: &str, cells: usize, part: usize, bold: bool, color: u32) {
+        let cell_w = self.cell_w;
+        let cell_h = self.cell_h;
+        let width = image.width() as usize;
+        let height = image.height() as usize;
+        if (x + 1) * cell_w > width || (y + 1) * cell_h > height {
+            return;
+        }
+
+        let glyph = self.glyph(text, cells, bold, color);
+        let glyph_w = cell_w * cells;
+        let data = image.data_mut();
+        for row in 0..cell_h {
+            let start = (y * cell_h + row) * width + x * cell_w;
+            for col in 0..cell_w {
+                let pixel = glyph[row * glyph_w + part * cell_w + col];
+                let alpha = pixel >> 24;
+                if alpha == 255 {
+                    data[start + col].data = pixel;
+                } else if alpha > 0 {
+                    let under = data[start + col].data;
+                    let channel = |shift: u32| -> u32 {
+                        ((((pixel >> shift) & 0xFF) * alpha + ((under >> shift) & 0xFF) * (255 - alpha)) / 255) << shift
+                    };
+                    data[start + col].data = 0xFF000000 | channel(16) | channel(8) | channel(0);
+                }
+            }
+        }
+    }
 }
 
-#[cfg(target_arch = "x86_64")]
-#[inline(always)]
-#[cold]
-pub unsafe fn fast_set32(dst: *mut u32, src: u32, len: usize) {
-    asm!("cld
-        rep stosd"
-        :
-        : "{rdi}"(dst as usize), "{eax}"(src), "{rcx}"(len)
-        : "cc", "memory", "rdi", "rcx"
-        : "intel", "volatile");
+/// Halfway between two colours, for dim text
+fn blend(a: u32, b: u32) -> u32 {
+    let channel = |shift: u32| -> u32 {
+        (((a >> shift) & 0xFF) + ((b >> shift) & 0xFF)) / 2 << shift
+    };
+    0xFF000000 | channel(16) | channel(8) | channel(0)
 }
 
-#[derive(Clone, Copy)]
-pub struct Block {
-    c: char,
-    fg: u32,
-    bg: u32,
-    bold: bool,
+/// Lean a cell drawn in the image to the right, for fonts without an italic style
+fn slant(image: &mut Image, x: usize, y: usize, w: usize, h: usize, bg: u32) {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    if x + w > width || y + h > height {
+        return;
+    }
+
+    let data = image.data_mut();
+    for row in 0..h {
+        // The top row moves a quarter of the cell, the bottom row stays
+        let shift = (h - 1 - row) * w / (4 * h);
+        if shift > 0 {
+            let start = (y + row) * width + x;
+            for col in (shift..w).rev() {
+                data[start + col] = data[start + col - shift];
+            }
+            for col in 0..shift {
+                data[start + col] = Color { data: bg };
+            }
+        }
+    }
 }
 
+/// Draw a cell with its attributes, leaving blinking text out when `blink_on` is false
+fn draw_block(image: &mut Image, fonts: &mut Fonts, x: usize, y: usize, block: &Block, clusters: &[String], blink_on: bool) {
+    let mut fg = block.fg;
+    let mut bg = block.bg;
+    if block.attrs & REVERSE == REVERSE {
+        mem::swap(&mut fg, &mut bg);
+    }
+    if block.attrs & DIM == DIM {
+        fg = blend(fg, bg);
+    }
+
+    let cell_w = fonts.cell_w;
+    let cell_h = fonts.cell_h;
+    image.rect((x * cell_w) as i32, (y * cell_h) as i32, cell_w as u32, cell_h as u32, Color { data: bg });
+
+    if block.attrs & HIDDEN == HIDDEN || (block.attrs & BLINK == BLINK && ! blink_on) {
+        return;
+    }
+
+    if block.c != '\0' {
+        let (cells, part) = match block.width {
+            0 => (2, 1),
+            2 => (2, 0),
+            _ => (1, 0)
+        };
+        let mut str_buf = [0; 4];
+        let text = match clusters.get((block.cluster as usize).wrapping_sub(1)) {
+            Some(cluster) => cluster.as_str(),
+            None => &*block.c.encode_utf8(&mut str_buf)
+        };
+        fonts.draw_text(image, x, y, text, cells, part, block.bold, fg);
+        if block.attrs & ITALIC == ITALIC {
+            slant(image, x * cell_w, y * cell_h, cell_w, cell_h, bg);
+        }
+    }
+
+    let line = cmp::max(1, cell_h / 16);
+    if block.attrs & UNDERLINE == UNDERLINE {
+        image.rect((x * cell_w) as i32, (y * cell_h + cell_h - line * 2) as i32, cell_w as u32, line as u32, Color { data: fg });
+    }
+    if block.attrs & STRIKETHROUGH == STRIKETHROUGH {
+        image.rect((x * cell_w) as i32, (y * cell_h + cell_h / 2) as i32, cell_w as u32, line as u32, Color { data: fg });
+    }
+}
+
+/// Draw the part of an image that falls in a cell over it, blending in pixels that are not opaque
+fn draw_tile(image: &mut Image, cell_w: usize, cell_h: usize, x: usize, y: usize, block: &Block, graphics: &[Option<Placement>]) {
+    let graphic = match graphics.get((block.image as usize).wrapping_sub(1)) {
+        Some(&Some(ref placement)) => &placement.graphic,
+        _ => return
+    };
+
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    if (x + 1) * cell_w > width || (y + 1) * cell_h > height {
+        return;
+    }
+
+    let src_x = block.tile.0 as usize * cell_w;
+    let src_y = block.tile.1 as usize * cell_h;
+    let data = image.data_mut();
+    for row in 0..cmp::min(cell_h, graphic.height.saturating_sub(src_y)) {
+        let start = (y * cell_h + row) * width + x * cell_w;
+        let src_start = (src_y + row) * graphic.width + src_x;
+        for col in 0..cmp::min(cell_w, graphic.width.saturating_sub(src_x)) {
+            let pixel = graphic.data[src_start + col];
+            let alpha = pixel >> 24;
+            if alpha == 255 {
+                data[start + col].data = pixel;
+            } else if alpha > 0 {
+                let under = data[start + col].data;
+                let channel = |shift: u32| -> u32 {
+                    ((((pixel >> shift) & 0xFF) * alpha + ((under >> shift) & 0xFF) * (255 - alpha)) / 255) << shift
+                };
+                data[start + col].data = 0xFF000000 | channel(16) | channel(8) | channel(0);
+            }
+        }
+    }
+}
+
+/// How far a selection extends from the cells the mouse was dragged over
+#[derive(Clone, Copy, PartialEq)]
+pub enum SelectionMode {
+    Char,
+    Word,
+    Line,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || "_-.~/".contains(c)
+}
+
+/// Draws a `State` into an image for the frontend to place in the window, and handles the mouse and keyboard
 pub struct Console {
-    pub console: ransid::Console,
-    pub window: Window,
-    pub alternate: bool,
-    pub grid: Box<[Block]>,
-    pub alt_grid: Box<[Block]>,
-    pub font: Font,
-    pub font_bold: Font,
+    pub state: State,
+    /// What the console looks like, placed in the window by the frontend
+    pub image: Image,
+    pub fonts: Fonts,
+    /// Rows of the image changed since the frontend last copied them to the window
     pub changed: BTreeSet<usize>,
+    /// Whether output has been written that `redraw` has not drawn yet
+    pub pending: bool,
+    pub redraw_time: Instant,
     pub mouse_x: u16,
     pub mouse_y: u16,
     pub mouse_left: bool,
-    pub ctrl: bool,
-    pub input: Vec<u8>,
-    pub end_of_input: bool,
-    pub cooked: VecDeque<u8>,
-    pub requested: usize,
+    /// Selected cells in view coordinates, as the cell the drag started at and the cell under the mouse
+    pub selection: Option<((usize, usize), (usize, usize))>,
+    pub selection_mode: SelectionMode,
+    pub selection_anchor: (usize, usize),
+    pub selecting: bool,
+    /// Time and cell of the last click, used to count double and triple clicks
+    pub last_click: Option<(Instant, (usize, usize))>,
+    pub clicks: usize,
+    /// Whether blinking text is shown, flipped by `tick`
+    pub blink_on: bool,
+    blink_time: Instant,
+    /// Whether the pane has the keyboard, the cursor is drawn hollow and does not blink when it does not
+    pub focused: bool,
+    /// Whether a blinking cursor is shown, flipped by `tick` and turned back on by output and typing
+    cursor_on: bool,
+    cursor_time: Instant,
+    cursor_blink: Duration,
+    /// Cells of the link under the mouse, which are underlined, and where it leads
+    hover: Option<(Vec<(usize, usize)>, Target)>,
+    /// A link that was Ctrl+clicked, for the frontend to open
+    pub open: Option<Target>,
+    /// Whether the button held down was a Ctrl+click on a link, so its release is not reported or selected with
+    link_click: bool,
+    /// The open search, which takes the keyboard until it is closed
+    pub search: Option<Search>,
 }
 
 impl Console {
-    pub fn new(width: u32, height: u32) -> Console {
-        let mut window = Window::new_flags(-1, -1, width, height, "Terminal", &[WindowFlag::Async, WindowFlag::Resizable]).unwrap();
-        window.sync();
-
-        let ransid = ransid::Console::new(width as usize / 8, height as usize / 16);
-        let grid = vec![Block {
-            c: '\0', fg: 0, bg: 0, bold: false
-        }; ransid.w * ransid.h].into_boxed_slice();
+    pub fn new(width: u32, height: u32, fonts: Fonts, config: &Config) -> Console {
+        let image = Image::from_color(width, height, Color { data: config.theme.background });
+        let mut state = State::new(width as usize / fonts.cell_w, height as usize / fonts.cell_h, config);
+        state.cell_size = (fonts.cell_w, fonts.cell_h);
 
         Console {
-            console: ransid,
-            alternate: false,
-            grid: grid.clone(),
-            alt_grid: grid,
-            window: window,
-            font: Font::find(None, None, None).unwrap(),
-            font_bold: Font::find(None, None, Some("Bold")).unwrap(),
+            state: state,
+            image: image,
+            fonts: fonts,
             changed: BTreeSet::new(),
+            pending: false,
+            redraw_time: Instant::now(),
             mouse_x: 0,
             mouse_y: 0,
             mouse_left: false,
-            ctrl: false,
-            input: Vec::new(),
-            end_of_input: false,
-            cooked: VecDeque::new(),
-            requested: 0
+            selection: None,
+            selection_mode: SelectionMode::Char,
+            selection_anchor: (0, 0),
+            selecting: false,
+            last_click: None,
+            clicks: 0,
+            blink_on: true,
+            blink_time: Instant::now(),
+            focused: true,
+            cursor_on: true,
+            cursor_time: Instant::now(),
+            cursor_blink: Duration::from_millis(config.cursor_blink_ms),
+            hover: None,
+            open: None,
+            link_click: false,
+            search: None,
         }
     }
 
     pub fn input(&mut self, event_option: EventOption) {
+        // Selections and the scrolled view are drawn over the image as it is, so bring it up to date first
+        if self.pending {
+            self.redraw();
+        }
+
         match event_option {
             EventOption::Key(key_event) => {
                 let mut buf = vec![];
 
                 if key_event.scancode == 0x1D {
-                    self.ctrl = key_event.pressed;
-                } else if key_event.pressed {
-                    match key_event.scancode {
-                        0x0E => { // Backspace
-                            buf.extend_from_slice(b"\x7F");
-                        },
-                        0x47 => { // Home
-                            buf.extend_from_slice(b"\x1B[H");
-                        },
-                        0x48 => { // Up
-                            buf.extend_from_slice(b"\x1B[A");
-                        },
-                        0x49 => { // Page up
-                            buf.extend_from_slice(b"\x1B[5~");
-                        },
-                        0x4B => { // Left
-                            buf.extend_from_slice(b"\x1B[D");
-                        },
-                        0x4D => { // Right
-                            buf.extend_from_slice(b"\x1B[C");
-                        },
-                        0x4F => { // End
-                            buf.extend_from_slice(b"\x1B[F");
-                        },
-                        0x50 => { // Down
-                            buf.extend_from_slice(b"\x1B[B");
-                        },
-                        0x51 => { // Page down
-                            buf.extend_from_slice(b"\x1B[6~");
-                        },
-                        0x52 => { // Insert
-                            buf.extend_from_slice(b"\x1B[2~");
-                        },
-                        0x53 => { // Delete
-                            buf.extend_from_slice(b"\x1B[3~");
-                        },
-                        _ => {
-                            let c = match key_event.character {
-                                c @ 'A' ... 'Z' if self.ctrl => ((c as u8 - b'A') + b'\x01') as char,
-                                c @ 'a' ... 'z' if self.ctrl => ((c as u8 - b'a') + b'\x01') as char,
-                                c => c
-                            };
-
-                            if c != '\0' {
-                                buf.extend_from_slice(&[c as u8]);
-                            }
-                        }
+                    self.state.ctrl = key_event.pressed;
+                } else if key_event.scancode == 0x2A || key_event.scancode == 0x36 {
+                    self.state.shift = key_event.pressed;
+                } else if key_event.pressed && self.state.shift && (key_event.scancode == 0x49 || key_event.scancode == 0x51) {
+                    let page = self.state.console.h;
+                    if key_event.scancode == 0x49 {
+                        self.scroll_view(page as isize);
+                    } else {
+                        self.scroll_view(-(page as isize));
                     }
+                } else if key_event.scancode == 0x38 {
+                    self.state.alt = key_event.pressed;
+                } else if key_event.pressed && self.search.is_some() {
+                    self.search_key(key_event.scancode, key_event.character);
+                } else if key_event.pressed {
+                    buf = self.state.encode_key(key_event.scancode, key_event.character);
                 }
 
-                if self.console.raw_mode {
-                    self.input.extend(buf);
-                } else {
-                    for &b in buf.iter() {
-                        match b {
-                            b'\x03' => {
-                                self.end_of_input = true;
-                                let _ = self.write(b"^C\n", true);
-                            },
-                            b'\x08' | b'\x7F' => {
-                                if let Some(_c) = self.cooked.pop_back() {
-                                    let _ = self.write(b"\x08", true);
-                                }
-                            },
-                            b'\x1B' => {
-                                let _ = self.write(b"^[", true);
-                            },
-                            b'\n' | b'\r' => {
-                                self.cooked.push_back(b);
-                                while let Some(c) = self.cooked.pop_front() {
-                                    self.input.push(c);
-                                }
-                                let _ = self.write(b"\n", true);
-                            },
-                            _ => {
-                                self.cooked.push_back(b);
-                                let _ = self.write(&[b], true);
-                            }
-                        }
-                    }
-                }
+                self.send(buf);
             },
             EventOption::Mouse(mouse_event) => {
-                let x = (mouse_event.x/8) as u16 + 1;
-                let y = (mouse_event.y/16) as u16 + 1;
-                if self.console.mouse_rxvt && self.console.mouse_btn {
+                let x = (cmp::max(0, mouse_event.x) as usize/self.fonts.cell_w) as u16 + 1;
+                let y = (cmp::max(0, mouse_event.y) as usize/self.fonts.cell_h) as u16 + 1;
+                if self.selecting {
+                    if x != self.mouse_x || y != self.mouse_y {
+                        self.mouse_x = x;
+                        self.mouse_y = y;
+
+                        let anchor = self.selection_anchor;
+                        let cell = self.mouse_cell();
+                        let mode = self.selection_mode;
+                        self.set_selection(Some((anchor, cell)), mode);
+                    }
+                } else if self.state.console.mouse_rxvt && self.state.console.mouse_btn {
                     if self.mouse_left && (x != self.mouse_x || y != self.mouse_y) {
                         let string = format!("\x1B[<{};{};{}M", 32, self.mouse_x, self.mouse_y);
-                        self.input.extend(string.as_bytes());
+                        self.state.input.extend(string.as_bytes());
                     }
                 }
                 self.mouse_x = x;
                 self.mouse_y = y;
+
+                if ! self.selecting {
+                    let (cell_x, cell_y) = self.mouse_cell();
+                    let hover = self.link_at(cell_x, cell_y);
+                    self.set_hover(hover);
+                }
             },
             EventOption::Button(button_event) => {
-                if self.console.mouse_rxvt {
-                    if button_event.left {
-                        if ! self.mouse_left {
-                            let string = format!("\x1B[<{};{};{}M", 0, self.mouse_x, self.mouse_y);
-                            self.input.extend(string.as_bytes());
-                        }
-                    } else if self.mouse_left {
+                // Holding shift selects text even when the program has asked for mouse reports
+                let reporting = self.state.console.mouse_rxvt && ! self.state.shift;
+                if button_event.left && ! self.mouse_left {
+                    let (cell_x, cell_y) = self.mouse_cell();
+                    let link = if self.state.ctrl { self.link_at(cell_x, cell_y) } else { None };
+                    if let Some((_cells, target)) = link {
+                        self.open = Some(target);
+                        self.link_click = true;
+                    } else if reporting {
+                        let string = format!("\x1B[<{};{};{}M", 0, self.mouse_x, self.mouse_y);
+                        self.state.input.extend(string.as_bytes());
+                    } else {
+                        self.start_selection();
+                    }
+                } else if ! button_event.left && self.mouse_left {
+                    if self.link_click {
+                        self.link_click = false;
+                    } else if self.selecting {
+                        self.selecting = false;
+                    } else if reporting {
                         let string = format!("\x1B[<{};{};{}m", 0, self.mouse_x, self.mouse_y);
-                        self.input.extend(string.as_bytes());
+                        self.state.input.extend(string.as_bytes());
                     }
-                    self.mouse_left = button_event.left;
                 }
+                self.mouse_left = button_event.left;
             },
             EventOption::Scroll(scroll_event) => {
-                if self.console.mouse_rxvt {
+                if self.state.console.mouse_rxvt {
                     if scroll_event.y > 0 {
                         let string = format!("\x1B[<{};{};{}M", 64, self.mouse_x, self.mouse_y);
-                        self.input.extend(string.as_bytes());
+                        self.state.input.extend(string.as_bytes());
                     } else if scroll_event.y < 0 {
                         let string = format!("\x1B[<{};{};{}M", 65, self.mouse_x, self.mouse_y);
-                        self.input.extend(string.as_bytes());
+                        self.state.input.extend(string.as_bytes());
                     }
+                } else {
+                    self.scroll_view(scroll_event.y as isize * 3);
                 }
             },
-            EventOption::Resize(resize_event) => {
-                let w = resize_event.width as usize/8;
-                let h = resize_event.height as usize/16;
-
-                let mut grid = vec![Block {
-                    c: '\0', fg: self.console.foreground.data, bg: self.console.background.data, bold: false
-                }; w * h].into_boxed_slice();
-
-                let mut alt_grid = vec![Block {
-                    c: '\0', fg: self.console.foreground.data, bg: self.console.background.data, bold: false
-                }; w * h].into_boxed_slice();
-
-                self.window.set(Color { data: self.console.background.data });
-
-                {
-                    let font = &self.font;
-                    let font_bold = &self.font_bold;
-                    let window = &mut self.window;
-                    let mut str_buf = [0; 4];
-                    for y in 0..self.console.h {
-                        for x in 0..self.console.w {
-                            let block = self.grid[y * self.console.w + x];
-                            if y < h && x < w {
-                                grid[y * w + x] = block;
-
-                                let alt_block = self.alt_grid[y * self.console.w + x];
-                                alt_grid[y * w + x] = alt_block;
-                            }
-
-                            window.rect(x as i32 * 8, y as i32 * 16, 8, 16, Color { data: block.bg });
-                            if block.c != '\0' {
-                                if block.bold {
-                                    font_bold.render(&block.c.encode_utf8(&mut str_buf), 16.0).draw(window, x as i32 * 8, y as i32 * 16, Color { data: block.fg });
-                                } else {
-                                    font.render(&block.c.encode_utf8(&mut str_buf), 16.0).draw(window, x as i32 * 8, y as i32 * 16, Color { data: block.fg });
-                                }
-                            }
-                        }
-                        self.changed.insert(y as usize);
-                    }
-                }
+            _ => ()
+        }
+    }
+
+    /// Send typed or pasted bytes to the program, echoed by the line editor when not in raw mode
+    fn send(&mut self, buf: Vec<u8>) {
+        if ! buf.is_empty() {
+            self.show_cursor();
+        }
+        if ! buf.is_empty() && self.state.scroll_offset > 0 {
+            self.state.scroll_offset = 0;
+            self.selection = None;
+            self.draw_view();
+        }
 
-                self.console.w = w;
-                self.console.h = h;
-                self.grid = grid;
-                self.alt_grid = alt_grid;
+        if self.state.console.raw_mode {
+            self.state.send(&buf);
+        } else if ! buf.is_empty() {
+            self.clear_selection();
+            self.pending = true;
+            self.state.send(&buf);
+            self.redraw();
+        }
+    }
+
+    /// Send pasted text, bracketed when the program asked for it
+    pub fn paste(&mut self, text: &str) {
+        if self.pending {
+            self.redraw();
+        }
+
+        // Newlines are sent as carriage returns, like Enter, and escapes are dropped so a paste cannot end bracketed paste early
+        let text = text.replace("\r\n", "\r").replace('\n', "\r").replace('\x1B', "");
+        let mut buf = Vec::new();
+        if self.state.bracketed_paste {
+            buf.extend_from_slice(b"\x1B[200~");
+            buf.extend_from_slice(text.as_bytes());
+            buf.extend_from_slice(b"\x1B[201~");
+        } else {
+            buf.extend_from_slice(text.as_bytes());
+        }
+        self.send(buf);
+    }
+
+    /// Fit the console to a new size in pixels
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.selection = None;
+        self.selecting = false;
+        self.hover = None;
+        if let Some(ref mut search) = self.search {
+            search.stale = true;
+        }
 
-                if self.console.cursor && self.console.x < self.console.w && self.console.y < self.console.h {
-                    let x = self.console.x;
-                    let y = self.console.y;
-                    self.invert(x * 8, y * 16, 8, 16);
+        self.state.resize(width as usize/self.fonts.cell_w, height as usize/self.fonts.cell_h);
+
+        // The image is replaced, so every cell is drawn again
+        self.image = Image::from_color(width, height, Color { data: self.state.theme.background });
+        self.pending = true;
+        self.redraw();
+    }
+
+    /// Draw the cursor over its cell in the theme's cursor colour and the shape the program asked for, until the cell is
+    /// next drawn
+    fn draw_cursor(&mut self) {
+        let x = self.state.console.x;
+        let y = self.state.console.y;
+        if ! self.state.console.cursor || x >= self.state.console.w || y >= self.state.console.h {
+            return;
+        }
+        if self.focused && self.state.cursor_blink && ! self.cursor_on {
+            return;
+        }
+
+        // Over a wide character the cursor covers both of its cells
+        let cells = if self.state.grid[y * self.state.console.w + x].width == 2 { 2 } else { 1 };
+        let cells = cmp::min(cells, self.state.console.w - x);
+        let (cell_w, cell_h) = (self.fonts.cell_w, self.fonts.cell_h);
+        let (left, top) = ((x * cell_w) as i32, (y * cell_h) as i32);
+        let (width, height) = ((cells * cell_w) as u32, cell_h as u32);
+        let color = Color { data: self.state.theme.cursor };
+
+        if ! self.focused {
+            self.image.rect(left, top, width, 1, color);
+            self.image.rect(left, top + height as i32 - 1, width, 1, color);
+            self.image.rect(left, top, 1, height, color);
+            self.image.rect(left + width as i32 - 1, top, 1, height, color);
+        } else {
+            match self.state.cursor_shape {
+                CursorShape::Block => for cursor_x in x..x + cells {
+                    let mut block = self.state.grid[y * self.state.console.w + cursor_x];
+                    block.fg = self.state.theme.background;
+                    block.bg = self.state.theme.cursor;
+                    block.attrs &= !(REVERSE | HIDDEN | BLINK);
+                    draw_block(&mut self.image, &mut self.fonts, cursor_x, y, &block, &self.state.clusters, self.blink_on);
+                },
+                CursorShape::Underline => {
+                    let thickness = cmp::max(2, cell_h / 8) as u32;
+                    self.image.rect(left, top + (height - thickness) as i32, width, thickness, color);
+                },
+                CursorShape::Bar => {
+                    let thickness = cmp::max(2, cell_w / 8) as u32;
+                    self.image.rect(left, top, thickness, height, color);
                 }
+            }
+        }
+        self.changed.insert(y);
+    }
 
-                self.sync();
-            },
-            _ => ()
+    /// Show a blinking cursor again and start its time over, so it stays in sight while output comes or keys are typed
+    fn show_cursor(&mut self) {
+        if ! self.cursor_on {
+            self.cursor_on = true;
+            self.state.touch_cursor();
+            self.pending = true;
         }
+        self.cursor_time = Instant::now();
+    }
+
+    /// Draw the cursor hollow while the pane does not have the keyboard
+    pub fn set_focused(&mut self, focused: bool) {
+        if focused != self.focused {
+            self.focused = focused;
+            self.state.touch_cursor();
+            self.pending = true;
+        }
+        self.show_cursor();
     }
 
     pub fn invert(&mut self, x: usize, y: usize, w: usize, h: usize) {
-        let width = self.window.width() as usize;
-        let height = self.window.height() as usize;
+        let width = self.image.width() as usize;
+        let height = self.image.height() as usize;
 
         let start_y = cmp::min(height - 1, y);
         let end_y = cmp::min(height, y + h);
@@ -282,7 +579,7 @@ impl Console {
         let start_x = cmp::min(width - 1, x);
         let len = cmp::min(width, x + w) - start_x;
 
-        let mut offscreen_ptr = self.window.data_mut().as_mut_ptr() as usize;
+        let mut offscreen_ptr = self.image.data_mut().as_mut_ptr() as usize;
 
         let stride = width * 4;
 
@@ -306,157 +603,588 @@ impl Console {
         }
     }
 
-    pub fn write(&mut self, buf: &[u8], sync: bool) -> Result<usize> {
-        if self.console.cursor && self.console.x < self.console.w && self.console.y < self.console.h {
-            let x = self.console.x;
-            let y = self.console.y;
-            self.invert(x * 8, y * 16, 8, 16);
-            self.changed.insert(y);
+    /// Cell under the mouse, clamped to the screen
+    fn mouse_cell(&self) -> (usize, usize) {
+        (cmp::min(self.mouse_x.saturating_sub(1) as usize, self.state.console.w.saturating_sub(1)),
+         cmp::min(self.mouse_y.saturating_sub(1) as usize, self.state.console.h.saturating_sub(1)))
+    }
+
+    fn start_selection(&mut self) {
+        let cell = self.mouse_cell();
+        let now = Instant::now();
+        self.clicks = match self.last_click {
+            Some((time, last_cell)) if last_cell == cell && now.duration_since(time) < Duration::from_millis(500) => self.clicks % 3 + 1,
+            _ => 1
+        };
+        self.last_click = Some((now, cell));
+
+        let mode = match self.clicks {
+            1 => SelectionMode::Char,
+            2 => SelectionMode::Word,
+            _ => SelectionMode::Line
+        };
+        self.selection_anchor = cell;
+        self.selecting = true;
+
+        // A single click only clears the selection, dragging makes a new one
+        if mode == SelectionMode::Char {
+            self.set_selection(None, mode);
+        } else {
+            self.set_selection(Some((cell, cell)), mode);
         }
+    }
 
-        {
-            let font = &self.font;
-            let font_bold = &self.font_bold;
-            let console_bg = self.console.background;
-            let console_w = self.console.w;
-            let console_h = self.console.h;
-            let alt = &mut self.alternate;
-            let grid = &mut self.grid;
-            let alt_grid = &mut self.alt_grid;
-            let window = &mut self.window;
-            let input = &mut self.input;
-            let changed = &mut self.changed;
-            let mut str_buf = [0; 4];
-            self.console.write(buf, |event| {
-                match event {
-                    ransid::Event::Char { x, y, c, color, bold, .. } => {
-                        if bold {
-                            font_bold.render(&c.encode_utf8(&mut str_buf), 16.0).draw(window, x as i32 * 8, y as i32 * 16, Color { data: color.data });
-                        } else {
-                            font.render(&c.encode_utf8(&mut str_buf), 16.0).draw(window, x as i32 * 8, y as i32 * 16, Color { data: color.data });
-                        }
-                        {
-                            let block = &mut grid[y * console_w + x];
-                            block.c = c;
-                            block.fg = color.data;
-                            block.bold = bold;
-                        }
-                        changed.insert(y);
-                    },
-                    ransid::Event::Input { data } => {
-                        input.extend(data);
-                    },
-                    ransid::Event::Rect { x, y, w, h, color } => {
-                        window.rect(x as i32 * 8, y as i32 * 16, w as u32 * 8, h as u32 * 16, Color { data: color.data });
-
-                        for y2 in y..y + h {
-                            for x2 in x..x + w {
-                                let block = &mut grid[y2 * console_w + x2];
-                                block.c = '\0';
-                                block.bg = color.data;
-                            }
-                            changed.insert(y2);
-                        }
-                    },
-                    ransid::Event::ScreenBuffer { alternate, clear } => {
-                        if *alt != alternate {
-                            mem::swap(grid, alt_grid);
-
-                            window.set(Color { data: console_bg.data });
-
-                            for y in 0..console_h {
-                                for x in 0..console_w {
-                                    let block = &mut grid[y * console_w + x];
-
-                                    if clear {
-                                        block.c = '\0';
-                                        block.bg = console_bg.data;
-                                    }
-
-                                    window.rect(x as i32 * 8, y as i32 * 16, 8, 16, Color { data: block.bg });
-                                    if block.c != '\0' {
-                                        if block.bold {
-                                            font_bold.render(&block.c.encode_utf8(&mut str_buf), 16.0).draw(window, x as i32 * 8, y as i32 * 16, Color { data: block.fg });
-                                        } else {
-                                            font.render(&block.c.encode_utf8(&mut str_buf), 16.0).draw(window, x as i32 * 8, y as i32 * 16, Color { data: block.fg });
-                                        }
-                                    }
-                                }
-                                changed.insert(y as usize);
-                            }
-                        }
-                        *alt = alternate;
-                    },
-                    ransid::Event::Scroll { rows, color } => {
-                        let pixel_rows = rows as u32 * 16;
-
-                        let width = window.width();
-                        let height = window.height();
-                        if pixel_rows > 0 && pixel_rows < height {
-                            let off1 = pixel_rows * width;
-                            let off2 = height * width - off1;
-                            unsafe {
-                                let data_ptr = window.data_mut().as_mut_ptr() as *mut u32;
-                                fast_copy(data_ptr as *mut u8, data_ptr.offset(off1 as isize) as *const u8, off2 as usize * 4);
-                                fast_set32(data_ptr.offset(off2 as isize), color.data, off1 as usize);
-                            }
+    /// First and last selected cells in reading order, widened to whole words or lines
+    fn selection_range(&self) -> Option<((usize, usize), (usize, usize))> {
+        self.selection.map(|(a, b)| {
+            let (mut start, mut end) = if (a.1, a.0) <= (b.1, b.0) {
+                (a, b)
+            } else {
+                (b, a)
+            };
+
+            match self.selection_mode {
+                SelectionMode::Char => (),
+                SelectionMode::Word => {
+                    let row = self.state.view_row(start.1);
+                    if start.0 < row.len() && is_word(row[start.0].c) {
+                        while start.0 > 0 && is_word(row[start.0 - 1].c) {
+                            start.0 -= 1;
                         }
+                    }
 
-                        for y in 0..console_h {
-                            if y >= rows {
-                                for x in 0..console_w {
-                                    let mut block = grid[y * console_w + x];
-                                    grid[(y - rows) * console_w + x] = block;
-                                    if y >= console_h - rows {
-                                        block.c = '\0';
-                                        block.bg = console_bg.data;
-                                        grid[y * console_w + x] = block;
-                                    }
-                                }
-                            }
-                            changed.insert(y as usize);
+                    let row = self.state.view_row(end.1);
+                    if end.0 < row.len() && is_word(row[end.0].c) {
+                        while end.0 + 1 < row.len() && is_word(row[end.0 + 1].c) {
+                            end.0 += 1;
                         }
-                    },
-                    ransid::Event::Title { title } => {
-                        window.set_title(&title);
+                    }
+                },
+                SelectionMode::Line => {
+                    start.0 = 0;
+                    end.0 = self.state.console.w.saturating_sub(1);
+                }
+            }
+
+            (start, end)
+        })
+    }
+
+    fn selected(&self, x: usize, y: usize) -> bool {
+        match self.selection_range() {
+            Some((start, end)) => (y, x) >= (start.1, start.0) && (y, x) <= (end.1, end.0),
+            None => false
+        }
+    }
+
+    fn invert_selection(&mut self) {
+        if let Some((start, end)) = self.selection_range() {
+            for y in start.1..end.1 + 1 {
+                let x0 = if y == start.1 { start.0 } else { 0 };
+                let x1 = if y == end.1 { end.0 + 1 } else { self.state.console.w };
+                if x0 < x1 {
+                    let (cell_w, cell_h) = (self.fonts.cell_w, self.fonts.cell_h);
+                    self.invert(x0 * cell_w, y * cell_h, (x1 - x0) * cell_w, cell_h);
+                }
+                self.changed.insert(y);
+            }
+        }
+    }
+
+    fn clear_selection(&mut self) {
+        if self.selection.is_some() {
+            let mode = self.selection_mode;
+            self.set_selection(None, mode);
+        }
+    }
+
+    /// Replace the selection, undoing the inversion of the old one and inverting the new one
+    pub fn set_selection(&mut self, selection: Option<((usize, usize), (usize, usize))>, mode: SelectionMode) {
+        self.invert_selection();
+        self.selection = selection;
+        self.selection_mode = mode;
+        self.invert_selection();
+    }
+
+    /// Text of the selected cells, with trailing blanks removed from each line
+    pub fn selected_text(&self) -> String {
+        let mut text = String::new();
+        if let Some((start, end)) = self.selection_range() {
+            for y in start.1..end.1 + 1 {
+                let row = self.state.view_row(y);
+                let x1 = cmp::min(row.len(), if y == end.1 { end.0 + 1 } else { row.len() });
+                let x0 = cmp::min(x1, if y == start.1 { start.0 } else { 0 });
+
+                text.push_str(self.state.text(&row[x0..x1]).trim_right());
+                if y < end.1 {
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+
+    /// The link at a cell of the view, either an OSC 8 hyperlink or a URL or path found in the text of its row
+    fn link_at(&self, x: usize, y: usize) -> Option<(Vec<(usize, usize)>, Target)> {
+        let block = *self.state.view_row(y).get(x)?;
+        if block.link != 0 {
+            let target = Target::Url(self.state.links.get(block.link as usize - 1)?.clone());
+
+            // A hyperlink can wrap onto other rows, every visible part of it is underlined
+            let mut cells = Vec::new();
+            for view_y in 0..self.state.console.h {
+                for (view_x, other) in self.state.view_row(view_y).iter().enumerate() {
+                    if other.link == block.link {
+                        cells.push((view_x, view_y));
+                    }
+                }
+            }
+            return Some((cells, target));
+        }
+
+        let text: Vec<char> = self.state.view_row(y).iter().map(|block| block.c).collect();
+        links::detect(&text).into_iter().find(|link| x >= link.start && x < link.end).map(|link| {
+            ((link.start..link.end).map(|link_x| (link_x, y)).collect(), link.target)
+        })
+    }
+
+    /// Forget the hovered link, marking its cells to be drawn again without the underline
+    fn clear_hover(&mut self) {
+        if let Some((cells, _target)) = self.hover.take() {
+            if self.state.scroll_offset == 0 {
+                let w = self.state.console.w;
+                for &(x, y) in cells.iter() {
+                    if let Some(cell) = self.state.dirty.get_mut(y * w + x) {
+                        *cell = true;
                     }
                 }
-            });
+            }
+            self.pending = true;
+        }
+    }
+
+    fn set_hover(&mut self, hover: Option<(Vec<(usize, usize)>, Target)>) {
+        if self.hover.as_ref().map(|hover| &hover.0) == hover.as_ref().map(|hover| &hover.0) {
+            return;
+        }
+
+        self.clear_hover();
+        self.pending = self.pending || hover.is_some();
+        self.hover = hover;
+
+        // Cells drawn again lose the inversion of a selection over them, so draw everything when there is one
+        if self.selection.is_some() {
+            self.draw_view();
+        } else {
+            self.redraw();
+        }
+    }
+
+    /// Underline the hovered link, over cells as they are drawn
+    fn draw_hover(&mut self) {
+        let cells = match self.hover {
+            Some((ref cells, _)) => cells.clone(),
+            None => return
+        };
+
+        let (cell_w, cell_h) = (self.fonts.cell_w, self.fonts.cell_h);
+        let line = cmp::max(1, cell_h / 16);
+        for (x, y) in cells {
+            let color = match self.state.view_row(y).get(x) {
+                Some(block) => if block.attrs & REVERSE == REVERSE { block.bg } else { block.fg },
+                None => continue
+            };
+            self.image.rect((x * cell_w) as i32, (y * cell_h + cell_h - line) as i32, cell_w as u32, line as u32, Color { data: color });
+            self.changed.insert(y);
+        }
+    }
+
+    /// Open the search bar, or close it when it is open
+    pub fn toggle_search(&mut self) {
+        if self.pending {
+            self.redraw();
+        }
+
+        self.search = match self.search {
+            Some(_) => None,
+            None => Some(Search::new())
+        };
+        self.draw_view();
+    }
+
+    /// Edit the query or move between matches.
+    ///
+    /// Enter, F3 and Down go to the next match, the same with Shift and Up go to the previous one, Ctrl+R switches
+    /// between plain text and regular expressions and Escape closes the search.
+    fn search_key(&mut self, scancode: u8, character: char) {
+        let (ctrl, shift) = (self.state.ctrl, self.state.shift);
+        match scancode {
+            // Escape
+            0x01 => {
+                self.search = None;
+                self.draw_view();
+                return;
+            },
+            // Enter and F3
+            0x1C | 0x3D => self.step_search(if shift { -1 } else { 1 }),
+            // Up
+            0x48 => self.step_search(-1),
+            // Down
+            0x50 => self.step_search(1),
+            // Backspace
+            0x0E => if let Some(ref mut search) = self.search {
+                search.query.pop();
+                search.update(&self.state);
+            },
+            // Ctrl+R
+            0x13 if ctrl => if let Some(ref mut search) = self.search {
+                search.regex = ! search.regex;
+                search.update(&self.state);
+            },
+            _ => if ! ctrl && character != '\0' && ! character.is_control() {
+                if let Some(ref mut search) = self.search {
+                    search.query.push(character);
+                    search.update(&self.state);
+                }
+            }
+        }
+
+        self.show_match();
+        self.draw_view();
+    }
+
+    fn step_search(&mut self, step: isize) {
+        if let Some(ref mut search) = self.search {
+            search.step(step);
+        }
+    }
+
+    /// Scroll the view to the current match when it is not in view, leaving it in the middle
+    fn show_match(&mut self) {
+        let row = match self.search.as_ref().and_then(|search| search.current.and_then(|i| search.matches.get(i))) {
+            Some(found) => found.row,
+            None => return
+        };
+
+        let h = self.state.console.h;
+        let top = self.state.scrollback.len() - self.state.scroll_offset;
+        // The last row is covered by the search bar
+        if row >= top && row + 1 < top + h {
+            return;
+        }
+
+        let top = cmp::min(row.saturating_sub(h / 2), self.state.scrollback.len());
+        self.state.scroll_offset = self.state.scrollback.len() - top;
+        self.selection = None;
+    }
+
+    /// Highlight the matches in view, the current one in the cursor colour
+    fn draw_matches(&mut self) {
+        let mut found = Vec::new();
+        if let Some(ref search) = self.search {
+            let top = self.state.scrollback.len() - self.state.scroll_offset;
+            for (i, m) in search.matches.iter().enumerate() {
+                if m.row >= top && m.row < top + self.state.console.h {
+                    found.push((m.row - top, m.start, m.end, search.current == Some(i)));
+                }
+            }
+        }
+
+        let theme = self.state.theme;
+        for (y, start, end, current) in found {
+            for x in start..end {
+                let mut block = match self.state.view_row(y).get(x) {
+                    Some(block) => *block,
+                    None => continue
+                };
+                block.fg = theme.background;
+                block.bg = if current { theme.cursor } else { theme.palette[3] };
+                block.attrs &= !(REVERSE | HIDDEN | BLINK | DIM);
+                draw_block(&mut self.image, &mut self.fonts, x, y, &block, &self.state.clusters, self.blink_on);
+            }
+            self.changed.insert(y);
+        }
+    }
+
+    /// Draw the query and the number of matches over the last row of the view
+    fn draw_search_bar(&mut self) {
+        let status = match self.search {
+            Some(ref search) => search.status(),
+            None => return
+        };
+
+        let w = self.state.console.w;
+        let y = match self.state.console.h.checked_sub(1) {
+            Some(y) => y,
+            None => return
+        };
+        let mut chars = status.chars();
+        for x in 0..w {
+            let block = Block {
+                c: chars.next().unwrap_or('\0'),
+                fg: self.state.theme.background,
+                bg: self.state.theme.foreground,
+                bold: false,
+                attrs: 0,
+                link: 0,
+                width: 1,
+                cluster: 0,
+                image: 0,
+                tile: (0, 0),
+            };
+            draw_block(&mut self.image, &mut self.fonts, x, y, &block, &self.state.clusters, self.blink_on);
         }
+        self.changed.insert(y);
+    }
+
+    pub fn write(&mut self, buf: &[u8], sync: bool) -> Result<usize> {
+        // Output can change the selected cells and links, so the selection and the hovered link do not outlive it
+        self.clear_selection();
+        self.clear_hover();
 
-        if self.console.cursor && self.console.x < self.console.w && self.console.y < self.console.h {
-            let x = self.console.x;
-            let y = self.console.y;
-            self.invert(x * 8, y * 16, 8, 16);
-            self.changed.insert(y as usize);
+        self.show_cursor();
+        self.pending = true;
+        let background = self.state.theme.background;
+        self.state.write(buf);
+        // Every cell is drawn again in the new colours, this covers the strip the cells leave at the edges
+        if self.state.theme.background != background {
+            self.image.set(Color { data: self.state.theme.background });
+        }
+        if let Some(ref mut search) = self.search {
+            search.stale = true;
         }
 
-        if ! self.console.raw_mode && sync {
-            self.sync();
+        if ! self.state.console.raw_mode && sync {
+            self.redraw();
         }
 
         Ok(buf.len())
     }
 
-    fn sync(&mut self) {
-        /*
-        let width = self.window.width;
-        for change in self.changed.iter() {
-            self.display.sync(0, change * 16, width, 16);
+    /// Time until `tick` has something to do: output to draw, or the cursor or text on the screen to blink. None while
+    /// there is nothing, so the event loop can sleep until something happens.
+    pub fn next_tick(&self) -> Option<Duration> {
+        let remaining = |time: Instant, interval: Duration| interval.checked_sub(time.elapsed()).unwrap_or(Duration::from_millis(0));
+
+        let mut due = None;
+        if self.pending {
+            due = Some(remaining(self.redraw_time, Duration::from_millis(16)));
+        }
+        if self.state.cursor_blink && self.focused && self.state.scroll_offset == 0 {
+            let cursor = remaining(self.cursor_time, self.cursor_blink);
+            due = Some(due.map_or(cursor, |due| cmp::min(due, cursor)));
+        }
+        let blinking = (0..self.state.console.h).any(|y| self.state.view_row(y).iter().any(|block| block.attrs & BLINK == BLINK));
+        if blinking {
+            let text = remaining(self.blink_time, Duration::from_millis(500));
+            due = Some(due.map_or(text, |due| cmp::min(due, text)));
+        }
+        due
+    }
+
+    /// Flip blinking text on and off every half second, called regularly by the event loop
+    pub fn tick(&mut self) {
+        // A blinking cursor is only drawn on the screen at the bottom, and stays hollow without the keyboard
+        if self.state.cursor_blink && self.focused && self.state.scroll_offset == 0 && self.cursor_time.elapsed() >= self.cursor_blink {
+            self.cursor_time = Instant::now();
+            self.cursor_on = ! self.cursor_on;
+            self.state.touch_cursor();
+            self.pending = true;
+        }
+
+        if self.pending {
+            self.redraw();
+        }
+
+        if self.blink_time.elapsed() < Duration::from_millis(500) {
+            return;
+        }
+        self.blink_time = Instant::now();
+        self.blink_on = ! self.blink_on;
+
+        // Blinking cells would be drawn over the highlighted matches and the search bar
+        if self.search.is_some() {
+            self.draw_view();
+            return;
+        }
+
+        // The cursor hides blinking under it, so its cell is left alone
+        let cursor = if self.state.scroll_offset == 0 && self.state.console.cursor {
+            Some((self.state.console.x, self.state.console.y))
+        } else {
+            None
+        };
+
+        let (cell_w, cell_h) = (self.fonts.cell_w, self.fonts.cell_h);
+        for y in 0..self.state.console.h {
+            for x in 0..self.state.console.w {
+                let block = self.state.view_row(y).get(x).cloned();
+                if let Some(block) = block {
+                    if block.attrs & BLINK == BLINK && cursor != Some((x, y)) {
+                        draw_block(&mut self.image, &mut self.fonts, x, y, &block, &self.state.clusters, self.blink_on);
+                        if self.selected(x, y) {
+                            self.invert(x * cell_w, y * cell_h, cell_w, cell_h);
+                        }
+                        self.changed.insert(y);
+                    }
+                }
+            }
         }
-        */
-        if ! self.changed.is_empty() {
-            self.window.sync();
+
+        self.draw_hover();
+    }
+
+    /// Move the view into the scrollback by `rows`, towards older output when positive
+    pub fn scroll_view(&mut self, rows: isize) {
+        let offset = cmp::max(0, cmp::min(self.state.scrollback.len() as isize, self.state.scroll_offset as isize + rows)) as usize;
+        if offset != self.state.scroll_offset {
+            self.state.scroll_offset = offset;
+            self.selection = None;
+            self.draw_view();
         }
-        self.changed.clear();
     }
 
+    /// Redraw the whole window from the scrollback and the grid at the current scroll offset
+    pub fn draw_view(&mut self) {
+        let w = self.state.console.w;
+        let h = self.state.console.h;
+        let background = self.state.theme.background;
+        let foreground = self.state.theme.foreground;
+
+        if let Some(ref mut search) = self.search {
+            if search.stale {
+                search.update(&self.state);
+            }
+        }
+
+        self.image.set(Color { data: background });
+
+        {
+            let fonts = &mut self.fonts;
+            let (cell_w, cell_h) = (fonts.cell_w, fonts.cell_h);
+            let blink_on = self.blink_on;
+            let image = &mut self.image;
+            let graphics = &self.state.graphics;
+            let top = self.state.scrollback.len() - self.state.scroll_offset;
+            for y in 0..h {
+                let i = top + y;
+                let row = if i < self.state.scrollback.len() {
+                    &self.state.scrollback[i][..]
+                } else {
+                    let grid_y = i - self.state.scrollback.len();
+                    &self.state.grid[grid_y * w..(grid_y + 1) * w]
+                };
+
+                for (x, block) in row.iter().take(w).enumerate() {
+                    draw_block(image, fonts, x, y, block, &self.state.clusters, blink_on);
+                    draw_tile(image, cell_w, cell_h, x, y, block, graphics);
+                }
+            }
+        }
+
+        if self.state.scroll_offset == 0 {
+            self.draw_cursor();
+        } else {
+            // Drawn in the cells at the end of the first row, so it has the size of the font the console uses
+            let indicator = format!(" {}/{} ", self.state.scroll_offset, self.state.scrollback.len());
+            let start = w.saturating_sub(indicator.len());
+            for (x, c) in (start..w).zip(indicator.chars()) {
+                let block = Block {
+                    c: c,
+                    fg: background,
+                    bg: foreground,
+                    bold: false,
+                    attrs: 0,
+                    link: 0,
+                    width: 1,
+                    cluster: 0,
+                    image: 0,
+                    tile: (0, 0),
+                };
+                draw_block(&mut self.image, &mut self.fonts, x, 0, &block, &self.state.clusters, self.blink_on);
+            }
+        }
+
+        self.draw_matches();
+        self.draw_hover();
+        self.invert_selection();
+        self.draw_search_bar();
+
+        for cell in self.state.dirty.iter_mut() {
+            *cell = false;
+        }
+        self.state.scrolled = 0;
+        self.pending = false;
+        self.redraw_time = Instant::now();
+
+        for y in 0..h {
+            self.changed.insert(y);
+        }
+    }
+
+    /// Move the window contents up by `pixel_rows`, filling the space left at the bottom with the background
+    fn scroll_pixels(&mut self, pixel_rows: usize) {
+        let width = self.image.width() as usize;
+        let height = cmp::min(self.image.height() as usize, self.state.console.h * self.fonts.cell_h);
+        let background = Color { data: self.state.theme.background };
+        scroll::scroll_pixels(self.image.data_mut(), width, height, pixel_rows, background);
+    }
+
+    /// Draw the cells written since the last redraw and the cursor, for the frontend to copy to the window
     pub fn redraw(&mut self) {
-        /*
-        let width = self.window.width;
-        let height = self.window.height;
-        */
-        self.window.sync();
-        self.changed.clear();
+        if ! self.pending {
+            return;
+        }
+
+        // Matches move with the output, so while searching everything is drawn again
+        if self.state.scroll_offset > 0 || self.search.is_some() {
+            self.draw_view();
+            return;
+        }
+
+        self.pending = false;
+        self.redraw_time = Instant::now();
+
+        let w = self.state.console.w;
+        let h = self.state.console.h;
+        if self.state.scrolled > 0 {
+            if self.state.scrolled < h {
+                let pixel_rows = self.state.scrolled * self.fonts.cell_h;
+                self.scroll_pixels(pixel_rows);
+            } else {
+                for cell in self.state.dirty.iter_mut() {
+                    *cell = true;
+                }
+            }
+            self.state.scrolled = 0;
+            for y in 0..h {
+                self.changed.insert(y);
+            }
+        }
+
+        {
+            let fonts = &mut self.fonts;
+            let (cell_w, cell_h) = (fonts.cell_w, fonts.cell_h);
+            let blink_on = self.blink_on;
+            let image = &mut self.image;
+            for y in 0..h {
+                let mut row_changed = false;
+                for x in 0..w {
+                    let i = y * w + x;
+                    if self.state.dirty[i] {
+                        self.state.dirty[i] = false;
+                        draw_block(image, fonts, x, y, &self.state.grid[i], &self.state.clusters, blink_on);
+                        draw_tile(image, cell_w, cell_h, x, y, &self.state.grid[i], &self.state.graphics);
+                        row_changed = true;
+                    }
+                }
+                if row_changed {
+                    self.changed.insert(y);
+                }
+            }
+        }
+
+        self.draw_cursor();
+        self.draw_hover();
     }
 }
@@ -0,0 +1,21 @@
+//! SDL's window event queue, looked at before the event loop sleeps so events already read from the display server
+//! are handled at once
+
+use libc::c_int;
+
+const FIRST_EVENT: u32 = 0;
+const LAST_EVENT: u32 = 0xFFFF;
+
+// Linked through orbclient, which opens its windows with SDL
+extern "C" {
+    fn SDL_PumpEvents();
+    fn SDL_HasEvents(min: u32, max: u32) -> c_int;
+}
+
+/// Whether window events are waiting, including those the display library read while the window was drawn
+pub fn events_waiting() -> bool {
+    unsafe {
+        SDL_PumpEvents();
+        SDL_HasEvents(FIRST_EVENT, LAST_EVENT) != 0
+    }
+}
@@ -2,7 +2,7 @@ use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
 #[cfg(not(target_os="redox"))]
-pub fn getpty() -> (RawFd, PathBuf) {
+pub fn getpty() -> Result<(RawFd, PathBuf), String> {
     use libc;
     use std::ffi::CStr;
     use std::fs::OpenOptions;
@@ -21,27 +21,42 @@ pub fn getpty() -> (RawFd, PathBuf) {
         .write(true)
         .custom_flags(libc::O_NONBLOCK)
         .open("/dev/ptmx")
-        .unwrap()
+        .map_err(|err| format!("failed to open /dev/ptmx: {}", err))?
         .into_raw_fd();
+
+    let fail = |call: &str| -> String {
+        let err = Error::last_os_error();
+        unsafe { libc::close(master_fd); }
+        format!("{}: {}", call, err)
+    };
     unsafe {
         if grantpt(master_fd) < 0 {
-            panic!("grantpt: {:?}", Error::last_os_error());
+            return Err(fail("grantpt"));
         }
         if unlockpt(master_fd) < 0 {
-            panic!("unlockpt: {:?}", Error::last_os_error());
+            return Err(fail("unlockpt"));
+        }
+        let name = ptsname(master_fd);
+        if name.is_null() {
+            return Err(fail("ptsname"));
         }
+        Ok((master_fd, PathBuf::from(CStr::from_ptr(name).to_string_lossy().into_owned())))
     }
-
-    let tty_path = unsafe { PathBuf::from(CStr::from_ptr(ptsname(master_fd)).to_string_lossy().into_owned()) };
-    (master_fd, tty_path)
 }
 
 #[cfg(target_os="redox")]
-pub fn getpty() -> (RawFd, PathBuf) {
+pub fn getpty() -> Result<(RawFd, PathBuf), String> {
     use syscall;
 
-    let master = syscall::open("pty:", syscall::O_RDWR | syscall::O_CREAT | syscall::O_NONBLOCK).unwrap();
+    let master = syscall::open("pty:", syscall::O_RDWR | syscall::O_CREAT | syscall::O_NONBLOCK)
+        .map_err(|err| format!("failed to open pty: {}", err))?;
     let mut buf: [u8; 4096] = [0; 4096];
-    let count = syscall::fpath(master, &mut buf).unwrap();
-    (master, PathBuf::from(unsafe { String::from_utf8_unchecked(Vec::from(&buf[..count])) }))
+    let count = match syscall::fpath(master, &mut buf) {
+        Ok(count) => count,
+        Err(err) => {
+            let _ = syscall::close(master);
+            return Err(format!("failed to get PTY path: {}", err));
+        }
+    };
+    Ok((master, PathBuf::from(String::from_utf8_lossy(&buf[..count]).into_owned())))
 }
@@ -0,0 +1,109 @@
+//! Images shown inline by programs, with sixel or the kitty graphics protocol, kept in the cells they cover
+
+use std::cmp;
+use std::rc::Rc;
+
+/// Widest and tallest image taken, in pixels, so a bad size cannot use up all the memory
+pub const MAX_SIZE: usize = 4096;
+
+/// Pixels of an image as `0xAARRGGBB`, a row at a time
+#[derive(Clone, Debug, PartialEq)]
+pub struct Graphic {
+    pub width: usize,
+    pub height: usize,
+    pub data: Box<[u32]>,
+}
+
+impl Graphic {
+    /// The part of the image from `x`, `y` that is `w` by `h` pixels, clipped to the image
+    pub fn crop(&self, x: usize, y: usize, w: usize, h: usize) -> Graphic {
+        let x = cmp::min(x, self.width);
+        let y = cmp::min(y, self.height);
+        let w = cmp::min(w, self.width - x);
+        let h = cmp::min(h, self.height - y);
+
+        let mut data = Vec::with_capacity(w * h);
+        for row in y..y + h {
+            data.extend_from_slice(&self.data[row * self.width + x..row * self.width + x + w]);
+        }
+        Graphic {
+            width: w,
+            height: h,
+            data: data.into_boxed_slice(),
+        }
+    }
+
+    /// The image stretched or shrunk to `w` by `h` pixels, taking the nearest pixel
+    pub fn scale(&self, w: usize, h: usize) -> Graphic {
+        let w = cmp::min(w, MAX_SIZE);
+        let h = cmp::min(h, MAX_SIZE);
+
+        let mut data = Vec::with_capacity(w * h);
+        for row in 0..h {
+            let src_y = row * self.height / cmp::max(1, h);
+            for col in 0..w {
+                let src_x = col * self.width / cmp::max(1, w);
+                data.push(self.data.get(src_y * self.width + src_x).cloned().unwrap_or(0));
+            }
+        }
+        Graphic {
+            width: w,
+            height: h,
+            data: data.into_boxed_slice(),
+        }
+    }
+}
+
+/// Where the cursor goes once an image has been placed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum After {
+    /// The start of the row below the image, like xterm does for sixel
+    NextLine,
+    /// The cell after the image on its last row, like kitty does
+    AfterImage,
+    /// Where it was before
+    Stay,
+}
+
+/// An image placed on the screen, which the cells it covers refer to
+pub struct Placement {
+    pub graphic: Rc<Graphic>,
+    /// The kitty image id it was placed from, or zero, so it can be deleted by id
+    pub kitty_id: u32,
+}
+
+/// Number of cells `size` pixels take up when each is `cell` pixels
+pub fn cells(size: usize, cell: usize) -> usize {
+    let cell = cmp::max(1, cell);
+    (size + cell - 1) / cell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cells, Graphic};
+
+    fn graphic() -> Graphic {
+        Graphic {
+            width: 3,
+            height: 2,
+            data: vec![1, 2, 3, 4, 5, 6].into_boxed_slice(),
+        }
+    }
+
+    #[test]
+    fn crop() {
+        assert_eq!(graphic().crop(1, 1, 5, 5).data.to_vec(), vec![5, 6]);
+    }
+
+    #[test]
+    fn scale() {
+        let scaled = graphic().scale(6, 2);
+        assert_eq!(scaled.data.to_vec(), vec![1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6]);
+    }
+
+    #[test]
+    fn cells_rounds_up() {
+        assert_eq!(cells(17, 8), 3);
+        assert_eq!(cells(16, 8), 2);
+    }
+}
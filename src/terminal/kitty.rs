@@ -0,0 +1,408 @@
+//! The kitty graphics protocol: `ESC _ G key=value,... ; base64 payload ESC \`
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use orbimage;
+
+use graphics::{After, Graphic, MAX_SIZE};
+
+/// Most image data held for one image, counting chunks not finished yet
+const DATA_MAX: usize = MAX_SIZE * MAX_SIZE * 4;
+
+/// What a command leaves for the terminal to do
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Show an image at the cursor, over `cols` by `rows` cells when given, otherwise at its own size
+    Place {
+        graphic: Rc<Graphic>,
+        id: u32,
+        cols: Option<usize>,
+        rows: Option<usize>,
+        after: After,
+    },
+    /// Take the images placed from the image with this id off the screen, or all of them
+    Delete(Option<u32>),
+    /// Bytes to send back to the program
+    Reply(Vec<u8>),
+}
+
+/// Keys of a command, which the first chunk of an image carries for the rest
+#[derive(Default)]
+struct Command {
+    action: u8,
+    format: u32,
+    medium: u8,
+    width: usize,
+    height: usize,
+    id: u32,
+    quiet: u32,
+    compression: u8,
+    cols: usize,
+    rows: usize,
+    no_move: bool,
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    delete: u8,
+}
+
+impl Command {
+    fn parse(keys: &[u8]) -> Command {
+        let mut command = Command::default();
+        command.action = b't';
+        command.format = 32;
+        command.medium = b'd';
+        command.delete = b'a';
+
+        for pair in keys.split(|&b| b == b',') {
+            let mut parts = pair.splitn(2, |&b| b == b'=');
+            let key = parts.next().and_then(|key| key.first().cloned()).unwrap_or(0);
+            let value = parts.next().unwrap_or(&[]);
+            let number = || -> usize {
+                value.iter().take_while(|&&b| b >= b'0' && b <= b'9')
+                    .fold(0usize, |n, &b| n.saturating_mul(10).saturating_add((b - b'0') as usize))
+            };
+            let letter = value.first().cloned().unwrap_or(0);
+            match key {
+                b'a' => command.action = letter,
+                b'f' => command.format = number() as u32,
+                b't' => command.medium = letter,
+                b's' => command.width = number(),
+                b'v' => command.height = number(),
+                b'i' => command.id = number() as u32,
+                b'q' => command.quiet = number() as u32,
+                b'o' => command.compression = letter,
+                b'c' => command.cols = number(),
+                b'r' => command.rows = number(),
+                b'C' => command.no_move = number() == 1,
+                b'x' => command.x = number(),
+                b'y' => command.y = number(),
+                b'w' => command.w = number(),
+                b'h' => command.h = number(),
+                b'd' => command.delete = letter,
+                _ => ()
+            }
+        }
+        command
+    }
+
+    /// The answer kitty gives, sent only when the command has an id and quiet does not hold it back
+    fn reply(&self, result: &Result<(), String>) -> Option<Action> {
+        if self.id == 0 {
+            return None;
+        }
+        let message = match *result {
+            Ok(()) if self.quiet == 0 => "OK".to_string(),
+            Err(ref err) if self.quiet < 2 => err.clone(),
+            _ => return None
+        };
+        Some(Action::Reply(format!("\x1B_Gi={};{}\x1B\\", self.id, message).into_bytes()))
+    }
+}
+
+/// Decode base64, skipping anything that is not part of the alphabet
+pub fn base64(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for &b in data.iter() {
+        let value = match b {
+            b'A' ... b'Z' => b - b'A',
+            b'a' ... b'z' => b - b'a' + 26,
+            b'0' ... b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => continue
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    bytes
+}
+
+/// Encode base64, with padding, for answers that carry data
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &b)| bits | ((b as u32) << (16 - i * 8)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[((bits >> (18 - i * 6)) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Images sent by the program and kept by id, and the image being sent in chunks
+pub struct Kitty {
+    images: HashMap<u32, Rc<Graphic>>,
+    chunk: Option<(Command, Vec<u8>)>,
+}
+
+impl Kitty {
+    pub fn new() -> Kitty {
+        Kitty {
+            images: HashMap::new(),
+            chunk: None,
+        }
+    }
+
+    /// Carry out the command in an APC string starting with `G`
+    pub fn command(&mut self, data: &[u8]) -> Vec<Action> {
+        let mut parts = data[1..].splitn(2, |&b| b == b';');
+        let keys = parts.next().unwrap_or(&[]);
+        let payload = base64(parts.next().unwrap_or(&[]));
+
+        // Chunks after the first have only `m` and maybe `q`, the rest comes from the first
+        let more = keys.split(|&b| b == b',').any(|pair| pair == b"m=1");
+        let (command, data) = match self.chunk.take() {
+            Some((command, mut data)) => {
+                data.extend_from_slice(&payload);
+                (command, data)
+            },
+            None => (Command::parse(keys), payload)
+        };
+        if data.len() > DATA_MAX {
+            return command.reply(&Err("EFBIG:image data too large".to_string())).into_iter().collect();
+        }
+        if more {
+            self.chunk = Some((command, data));
+            return Vec::new();
+        }
+
+        let mut actions = Vec::new();
+        let result = self.run(&command, data, &mut actions);
+        actions.extend(command.reply(&result));
+        actions
+    }
+
+    fn run(&mut self, command: &Command, data: Vec<u8>, actions: &mut Vec<Action>) -> Result<(), String> {
+        match command.action {
+            b't' | b'T' | b'q' => {
+                let graphic = Rc::new(Kitty::load(command, data)?);
+                match command.action {
+                    b't' => {
+                        self.images.insert(command.id, graphic);
+                    },
+                    b'T' => {
+                        if command.id != 0 {
+                            self.images.insert(command.id, graphic.clone());
+                        }
+                        actions.push(Kitty::place(command, graphic));
+                    },
+                    // A query only checks that the image could be shown
+                    _ => ()
+                }
+                Ok(())
+            },
+            b'p' => {
+                let graphic = self.images.get(&command.id).cloned().ok_or_else(|| "ENOENT:no image with that id".to_string())?;
+                actions.push(Kitty::place(command, graphic));
+                Ok(())
+            },
+            b'd' => {
+                match command.delete {
+                    b'i' | b'I' => {
+                        actions.push(Action::Delete(Some(command.id)));
+                        if command.delete == b'I' {
+                            self.images.remove(&command.id);
+                        }
+                    },
+                    b'a' | b'A' => {
+                        actions.push(Action::Delete(None));
+                        if command.delete == b'A' {
+                            self.images.clear();
+                        }
+                    },
+                    _ => return Err("EINVAL:unsupported delete".to_string())
+                }
+                Ok(())
+            },
+            _ => Err("EINVAL:unsupported action".to_string())
+        }
+    }
+
+    /// The file a transmission names, which anything written to the screen can give, so it has to be a regular file,
+    /// and to be removed after reading, a temporary one in a temporary directory as the protocol asks
+    fn check_path(path: &str, temporary: bool) -> Result<PathBuf, String> {
+        let path = fs::canonicalize(path).map_err(|err| format!("EBADF:{}", err))?;
+        let metadata = fs::metadata(&path).map_err(|err| format!("EBADF:{}", err))?;
+        if ! metadata.is_file() {
+            return Err("EPERM:not a regular file".to_string());
+        }
+
+        if temporary {
+            let mut dirs = vec![PathBuf::from("/tmp"), PathBuf::from("/dev/shm"), env::temp_dir()];
+            if let Some(tmpdir) = env::var_os("TMPDIR") {
+                dirs.push(PathBuf::from(tmpdir));
+            }
+            let in_temp = dirs.iter().filter_map(|dir| fs::canonicalize(dir).ok()).any(|dir| path.starts_with(dir));
+            let named = path.to_string_lossy().contains("tty-graphics-protocol");
+            if ! in_temp || ! named {
+                return Err("EPERM:not a temporary file of the graphics protocol".to_string());
+            }
+        }
+
+        Ok(path)
+    }
+
+    /// The image a transmission holds, read from a file when it names one
+    fn load(command: &Command, data: Vec<u8>) -> Result<Graphic, String> {
+        if command.compression != 0 {
+            return Err("EINVAL:compression is not supported".to_string());
+        }
+
+        let data = match command.medium {
+            b'd' => data,
+            b'f' | b't' => {
+                let path = Kitty::check_path(&String::from_utf8_lossy(&data), command.medium == b't')?;
+                let mut contents = Vec::new();
+                File::open(&path).and_then(|file| file.take(DATA_MAX as u64 + 1).read_to_end(&mut contents))
+                    .map_err(|err| format!("EBADF:{}", err))?;
+                if contents.len() > DATA_MAX {
+                    return Err("EFBIG:image data too large".to_string());
+                }
+                // A temporary file is the terminal's to remove once read
+                if command.medium == b't' {
+                    let _ = fs::remove_file(&path);
+                }
+                contents
+            },
+            _ => return Err("EINVAL:unsupported transmission medium".to_string())
+        };
+
+        let graphic = match command.format {
+            24 | 32 => {
+                let depth = command.format as usize / 8;
+                let (width, height) = (command.width, command.height);
+                if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE || data.len() < width * height * depth {
+                    return Err("EINVAL:size does not match the data".to_string());
+                }
+                let pixels = data.chunks(depth).take(width * height).map(|pixel| {
+                    let alpha = if depth == 4 { pixel[3] as u32 } else { 0xFF };
+                    alpha << 24 | (pixel[0] as u32) << 16 | (pixel[1] as u32) << 8 | pixel[2] as u32
+                }).collect::<Vec<u32>>();
+                Graphic {
+                    width: width,
+                    height: height,
+                    data: pixels.into_boxed_slice(),
+                }
+            },
+            100 => {
+                let image = orbimage::parse_png(&data).map_err(|err| format!("EBADPNG:{}", err))?;
+                let (width, height) = (image.width() as usize, image.height() as usize);
+                if width > MAX_SIZE || height > MAX_SIZE {
+                    return Err("EINVAL:image too large".to_string());
+                }
+                Graphic {
+                    width: width,
+                    height: height,
+                    data: image.data().iter().map(|pixel| pixel.data).collect::<Vec<u32>>().into_boxed_slice(),
+                }
+            },
+            _ => return Err("EINVAL:unsupported format".to_string())
+        };
+
+        // Only part of the image is shown when a source rectangle is given
+        if command.x > 0 || command.y > 0 || command.w > 0 || command.h > 0 {
+            let w = if command.w > 0 { command.w } else { graphic.width };
+            let h = if command.h > 0 { command.h } else { graphic.height };
+            Ok(graphic.crop(command.x, command.y, w, h))
+        } else {
+            Ok(graphic)
+        }
+    }
+
+    fn place(command: &Command, graphic: Rc<Graphic>) -> Action {
+        Action::Place {
+            graphic: graphic,
+            id: command.id,
+            cols: if command.cols > 0 { Some(command.cols) } else { None },
+            rows: if command.rows > 0 { Some(command.rows) } else { None },
+            after: if command.no_move { After::Stay } else { After::AfterImage },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+
+    use super::{base64, base64_encode, Action, Kitty};
+
+    #[test]
+    fn base64_decodes() {
+        assert_eq!(base64(b"aGVsbG8="), b"hello");
+        assert_eq!(base64(b"AAEC\n/w=="), vec![0, 1, 2, 255]);
+        assert_eq!(base64_encode(b"hello"), "aGVsbG8=");
+        assert_eq!(base64_encode(&[0, 1, 2, 255]), "AAEC/w==");
+    }
+
+    #[test]
+    fn transmit_and_display_in_chunks() {
+        let mut kitty = Kitty::new();
+        // Two RGB pixels, red and green, split over two chunks
+        assert_eq!(kitty.command(b"Ga=T,f=24,s=2,v=1,i=7,m=1;/wAA"), Vec::new());
+        let actions = kitty.command(b"Gm=0;AP8A");
+        match actions[0] {
+            Action::Place { ref graphic, id, .. } => {
+                assert_eq!(id, 7);
+                assert_eq!(graphic.data.to_vec(), vec![0xFFFF0000, 0xFF00FF00]);
+            },
+            _ => panic!("image not placed")
+        }
+        assert_eq!(actions[1], Action::Reply(b"\x1B_Gi=7;OK\x1B\\".to_vec()));
+
+        // The image is kept by its id, to be placed again
+        assert_eq!(kitty.command(b"Ga=p,i=7,q=1").len(), 1);
+    }
+
+    #[test]
+    fn errors() {
+        let mut kitty = Kitty::new();
+        assert_eq!(kitty.command(b"Ga=p,i=3"), vec![Action::Reply(b"\x1B_Gi=3;ENOENT:no image with that id\x1B\\".to_vec())]);
+        assert_eq!(kitty.command(b"Ga=p,i=3,q=2"), Vec::new());
+        // Without an id there is nobody to answer
+        assert_eq!(kitty.command(b"Ga=T,f=24,s=5,v=5;AAAA"), Vec::new());
+    }
+
+    #[test]
+    fn files_checked() {
+        let mut kitty = Kitty::new();
+        let command = |kitty: &mut Kitty, medium: &str, path: &str| {
+            kitty.command(format!("Ga=t,f=24,s=1,v=1,i=1,t={};{}", medium, base64_encode(path.as_bytes())).as_bytes())
+        };
+
+        // Devices and other files that are not regular ones are never read
+        assert_eq!(command(&mut kitty, "f", "/dev/zero"), vec![Action::Reply(b"\x1B_Gi=1;EPERM:not a regular file\x1B\\".to_vec())]);
+
+        // A file is only removed from a temporary directory, and only with the name the protocol asks for
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let path = path.to_str().unwrap();
+        assert_eq!(command(&mut kitty, "t", path),
+                   vec![Action::Reply(b"\x1B_Gi=1;EPERM:not a temporary file of the graphics protocol\x1B\\".to_vec())]);
+        assert!(Path::new(path).exists());
+
+        let path = env::temp_dir().join("kitty-test-tty-graphics-protocol");
+        File::create(&path).unwrap().write_all(&[255, 0, 0]).unwrap();
+        assert_eq!(command(&mut kitty, "t", path.to_str().unwrap()), vec![Action::Reply(b"\x1B_Gi=1;OK\x1B\\".to_vec())]);
+        assert!(! path.exists());
+    }
+}
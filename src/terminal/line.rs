@@ -0,0 +1,325 @@
+//! The line editor a PTY gives programs that have not asked for raw mode, for platforms where the terminal provides it
+
+use std::cmp;
+
+/// Signals that control characters send to the program in the foreground
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    /// SIGINT, from ^C
+    Interrupt,
+    /// SIGQUIT, from ^\
+    Quit,
+    /// SIGTSTP, from ^Z
+    Suspend,
+}
+
+/// What typed input turns into, in the order it has to happen
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Bytes to show on the screen
+    Echo(Vec<u8>),
+    /// Bytes for the program to read
+    Send(Vec<u8>),
+    /// End of file for the program, which reads nothing
+    Eof,
+    Signal(Signal),
+}
+
+/// The termios flags the line discipline follows, set like those of a new terminal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Flags {
+    /// ECHO: show what is typed, turned off and on by the program with `ESC[12h` and `ESC[12l` (SRM)
+    pub echo: bool,
+    /// ISIG: send signals for ^C, ^\ and ^Z instead of passing them on
+    pub isig: bool,
+    /// ICRNL: pass carriage returns on as newlines
+    pub icrnl: bool,
+}
+
+impl Flags {
+    pub fn new() -> Flags {
+        Flags {
+            echo: true,
+            isig: true,
+            icrnl: true,
+        }
+    }
+}
+
+/// Keys that move the cursor within the line or delete under it, sent as escape sequences
+#[derive(Clone, Copy, PartialEq)]
+enum Edit {
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+}
+
+/// The edit for the escape sequence at the start of `text`, with its length in characters
+fn escape(text: &[char]) -> (Option<Edit>, usize) {
+    match text.get(1) {
+        // CSI, with numeric parameters and a final character
+        Some(&'[') => {
+            let mut end = 2;
+            while end < text.len() && (text[end].is_digit(10) || text[end] == ';') {
+                end += 1;
+            }
+            let param: String = text[2..end].iter().take_while(|c| c.is_digit(10)).collect();
+            let edit = match (text.get(end), param.as_str()) {
+                (Some(&'D'), _) => Some(Edit::Left),
+                (Some(&'C'), _) => Some(Edit::Right),
+                (Some(&'H'), _) | (Some(&'~'), "1") | (Some(&'~'), "7") => Some(Edit::Home),
+                (Some(&'F'), _) | (Some(&'~'), "4") | (Some(&'~'), "8") => Some(Edit::End),
+                (Some(&'~'), "3") => Some(Edit::Delete),
+                _ => None
+            };
+            (edit, cmp::min(end + 1, text.len()))
+        },
+        // SS3, sent for cursor keys in application mode
+        Some(&'O') => {
+            let edit = match text.get(2) {
+                Some(&'D') => Some(Edit::Left),
+                Some(&'C') => Some(Edit::Right),
+                Some(&'H') => Some(Edit::Home),
+                Some(&'F') => Some(Edit::End),
+                _ => None
+            };
+            (edit, cmp::min(3, text.len()))
+        },
+        // Alt with a key, or escape on its own
+        Some(_) => (None, 2),
+        None => (None, 1)
+    }
+}
+
+/// Collects typed characters into a line, which the program reads once Enter is pressed
+pub struct LineDiscipline {
+    pub flags: Flags,
+    line: Vec<char>,
+    /// Position of the cursor in the line, in characters
+    cursor: usize,
+}
+
+impl LineDiscipline {
+    pub fn new() -> LineDiscipline {
+        LineDiscipline {
+            flags: Flags::new(),
+            line: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    /// Edit the line with typed or pasted bytes, returning what to echo and what to pass on.
+    ///
+    /// Keys are sent whole, so escape sequences and UTF-8 characters are never split between calls.
+    pub fn input(&mut self, buf: &[u8]) -> Vec<Action> {
+        let text: Vec<char> = String::from_utf8_lossy(buf).chars().collect();
+        let mut actions = Vec::new();
+
+        let mut i = 0;
+        while i < text.len() {
+            let c = text[i];
+            i += 1;
+            match c {
+                '\x1B' => {
+                    let (edit, len) = escape(&text[i - 1..]);
+                    i += len - 1;
+                    if let Some(edit) = edit {
+                        self.edit(edit, &mut actions);
+                    }
+                },
+                '\r' | '\n' => {
+                    let end = if c == '\r' && self.flags.icrnl { '\n' } else { c };
+                    self.line.push(end);
+                    self.echo(&mut actions, b"\n".to_vec());
+                    self.send(&mut actions);
+                },
+                '\x03' | '\x1C' | '\x1A' if self.flags.isig => {
+                    let (signal, name) = match c {
+                        '\x03' => (Signal::Interrupt, "^C"),
+                        '\x1C' => (Signal::Quit, "^\\"),
+                        _ => (Signal::Suspend, "^Z")
+                    };
+                    // Whatever was typed is thrown away along with the program's work
+                    let mut echo = String::new();
+                    if self.line.len() > self.cursor {
+                        echo.push_str(&format!("\x1B[{}C", self.line.len() - self.cursor));
+                    }
+                    echo.push_str(name);
+                    echo.push('\n');
+                    self.line.clear();
+                    self.cursor = 0;
+                    self.echo(&mut actions, echo.into_bytes());
+                    actions.push(Action::Signal(signal));
+                },
+                // ^D ends the file on an empty line, otherwise it passes the line on without a newline
+                '\x04' => if self.line.is_empty() {
+                    actions.push(Action::Eof);
+                } else {
+                    self.send(&mut actions);
+                },
+                '\x08' | '\x7F' => if self.cursor > 0 {
+                    let old = (self.cursor, self.line.len());
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                    self.refresh(old, &mut actions);
+                },
+                // ^U
+                '\x15' => {
+                    let old = (self.cursor, self.line.len());
+                    self.line = self.line.split_off(self.cursor);
+                    self.cursor = 0;
+                    self.refresh(old, &mut actions);
+                },
+                // ^W erases the word before the cursor and the blanks after it
+                '\x17' => {
+                    let old = (self.cursor, self.line.len());
+                    let mut start = self.cursor;
+                    while start > 0 && self.line[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    while start > 0 && ! self.line[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    let after = self.line.split_off(self.cursor);
+                    self.line.truncate(start);
+                    self.line.extend(after);
+                    self.cursor = start;
+                    self.refresh(old, &mut actions);
+                },
+                '\t' => self.insert(c, &mut actions),
+                // Other control characters mean nothing to the line editor
+                c if c.is_control() => (),
+                c => self.insert(c, &mut actions)
+            }
+        }
+
+        actions
+    }
+
+    fn echo(&self, actions: &mut Vec<Action>, bytes: Vec<u8>) {
+        if self.flags.echo && ! bytes.is_empty() {
+            actions.push(Action::Echo(bytes));
+        }
+    }
+
+    /// Pass the line on and start a new one
+    fn send(&mut self, actions: &mut Vec<Action>) {
+        let line: String = self.line.drain(..).collect();
+        self.cursor = 0;
+        actions.push(Action::Send(line.into_bytes()));
+    }
+
+    fn insert(&mut self, c: char, actions: &mut Vec<Action>) {
+        let old = (self.cursor, self.line.len());
+        self.line.insert(self.cursor, c);
+        self.cursor += 1;
+        if self.cursor == self.line.len() {
+            let mut buf = [0; 4];
+            let bytes = c.encode_utf8(&mut buf).as_bytes().to_vec();
+            self.echo(actions, bytes);
+        } else {
+            self.refresh(old, actions);
+        }
+    }
+
+    fn edit(&mut self, edit: Edit, actions: &mut Vec<Action>) {
+        let old = (self.cursor, self.line.len());
+        match edit {
+            Edit::Left => self.cursor = self.cursor.saturating_sub(1),
+            Edit::Right => if self.cursor < self.line.len() {
+                self.cursor += 1;
+            },
+            Edit::Home => self.cursor = 0,
+            Edit::End => self.cursor = self.line.len(),
+            Edit::Delete => if self.cursor < self.line.len() {
+                self.line.remove(self.cursor);
+            }
+        }
+        if (self.cursor, self.line.len()) != old {
+            self.refresh(old, actions);
+        }
+    }
+
+    /// Echo the line again after an edit, from the cursor position and length it had before
+    fn refresh(&self, old: (usize, usize), actions: &mut Vec<Action>) {
+        let (old_cursor, old_len) = old;
+        let mut echo = String::new();
+        if old_cursor > 0 {
+            echo.push_str(&format!("\x1B[{}D", old_cursor));
+        }
+        echo.extend(self.line.iter());
+        if old_len > self.line.len() {
+            echo.push_str("\x1B[K");
+        }
+        if self.line.len() > self.cursor {
+            echo.push_str(&format!("\x1B[{}D", self.line.len() - self.cursor));
+        }
+        self.echo(actions, echo.into_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, LineDiscipline, Signal};
+
+    /// Everything passed on to the program, with the end of file shown as `<EOF>`
+    fn sent(actions: &[Action]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for action in actions.iter() {
+            match *action {
+                Action::Send(ref data) => bytes.extend_from_slice(data),
+                Action::Eof => bytes.extend_from_slice(b"<EOF>"),
+                _ => ()
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn line_is_sent_on_enter() {
+        let mut line = LineDiscipline::new();
+        assert_eq!(sent(&line.input(b"echo hi")), b"");
+        assert_eq!(sent(&line.input(b"\r")), b"echo hi\n");
+    }
+
+    #[test]
+    fn kill_and_word_erase() {
+        let mut line = LineDiscipline::new();
+        assert_eq!(sent(&line.input(b"first\x15second word  \x17last\n")), b"second last\n");
+        assert_eq!(sent(&line.input(b"a b\x17\x17c\n")), b"c\n");
+        assert_eq!(sent(&line.input(b"gone\x15kept\n")), b"kept\n");
+    }
+
+    #[test]
+    fn cursor_editing() {
+        let mut line = LineDiscipline::new();
+        assert_eq!(sent(&line.input(b"ac\x1B[Db\x1B[H\x1B[3~\x1BOF!\r")), b"bc!\n");
+    }
+
+    #[test]
+    fn end_of_file() {
+        let mut line = LineDiscipline::new();
+        assert_eq!(sent(&line.input(b"part\x04\x04")), b"part<EOF>");
+    }
+
+    #[test]
+    fn signals() {
+        let mut line = LineDiscipline::new();
+        let actions = line.input(b"sleep 10\x03");
+        assert_eq!(actions.last(), Some(&Action::Signal(Signal::Interrupt)));
+        assert_eq!(sent(&actions), b"");
+
+        line.flags.isig = false;
+        assert_eq!(line.input(b"\x1A"), Vec::new());
+    }
+
+    #[test]
+    fn no_echo() {
+        let mut line = LineDiscipline::new();
+        line.flags.echo = false;
+        let actions = line.input(b"secret\r");
+        assert_eq!(actions, vec![Action::Send(b"secret\n".to_vec())]);
+    }
+}
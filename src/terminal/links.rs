@@ -0,0 +1,216 @@
+//! Links found in the text on screen, or given explicitly with OSC 8, and opening them
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Url(String),
+    /// A file, with the line it was given with as `path:line`
+    Path(String, usize),
+}
+
+/// A link in a row of cells, from `start` up to but not including `end`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    pub start: usize,
+    pub end: usize,
+    pub target: Target,
+}
+
+const SCHEMES: [&'static str; 5] = ["https://", "http://", "ftp://", "file://", "mailto:"];
+
+fn is_url(c: char) -> bool {
+    ! c.is_whitespace() && c != '\0' && ! "<>\"'`{}|\\^".contains(c)
+}
+
+fn is_path(c: char) -> bool {
+    c.is_alphanumeric() || "_-.~/+".contains(c)
+}
+
+fn starts_with(text: &[char], i: usize, prefix: &str) -> bool {
+    let mut j = i;
+    for c in prefix.chars() {
+        if text.get(j) != Some(&c) {
+            return false;
+        }
+        j += 1;
+    }
+    true
+}
+
+/// Digits starting at `i`, with the index after them
+fn number(text: &[char], i: usize) -> Option<(usize, usize)> {
+    let mut j = i;
+    let mut value = 0usize;
+    while let Some(digit) = text.get(j).and_then(|c| c.to_digit(10)) {
+        value = value.saturating_mul(10).saturating_add(digit as usize);
+        j += 1;
+    }
+    if j > i { Some((value, j)) } else { None }
+}
+
+/// URLs and `path:line` references in a row of cells, one character each
+pub fn detect(text: &[char]) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    let mut i = 0;
+    while i < text.len() {
+        // A link starts at a word boundary
+        if i > 0 && (is_path(text[i - 1]) || text[i - 1] == ':') {
+            i += 1;
+            continue;
+        }
+
+        if let Some(scheme) = SCHEMES.iter().find(|scheme| starts_with(text, i, scheme)) {
+            let mut end = i + scheme.chars().count();
+            while end < text.len() && is_url(text[end]) {
+                end += 1;
+            }
+            // Punctuation after a URL is more likely to end the sentence than to be part of it
+            while end > i && (".,;:!?".contains(text[end - 1]) || (text[end - 1] == ')' && ! text[i..end].contains(&'('))) {
+                end -= 1;
+            }
+            if end > i + scheme.chars().count() {
+                links.push(Link {
+                    start: i,
+                    end: end,
+                    target: Target::Url(text[i..end].iter().collect()),
+                });
+                i = end;
+                continue;
+            }
+        }
+
+        let mut colon = i;
+        while colon < text.len() && is_path(text[colon]) {
+            colon += 1;
+        }
+        let path: String = text[i..colon].iter().collect();
+        // A bare word followed by a number, like `error:12`, is not taken for a file
+        let looks_like_path = path.contains('/') || path.trim_left_matches('.').contains('.');
+        if colon > i && looks_like_path && text.get(colon) == Some(&':') {
+            if let Some((line, mut end)) = number(text, colon + 1) {
+                // A column may follow the line
+                if text.get(end) == Some(&':') {
+                    if let Some((_column, column_end)) = number(text, end + 1) {
+                        end = column_end;
+                    }
+                }
+                links.push(Link {
+                    start: i,
+                    end: end,
+                    target: Target::Path(path, line),
+                });
+                i = end;
+                continue;
+            }
+        }
+
+        i = if colon > i { colon } else { i + 1 };
+    }
+
+    links
+}
+
+/// The path of a `file://` URL, with its `%XX` escapes decoded. The host part is either empty or this machine.
+pub fn file_path(url: &str) -> String {
+    let path = &url["file://".len()..];
+    let path = match path.find('/') {
+        Some(slash) => &path[slash..],
+        None => path
+    };
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            ::std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escape {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Open a link, URLs in the browser and files through the launcher, which picks the application for them.
+///
+/// Relative paths are taken from `cwd`, the directory of the shell that printed them, when it is known.
+pub fn open(target: &Target, cwd: Option<&Path>) {
+    let result = match *target {
+        Target::Url(ref url) if url.starts_with("file://") => Command::new("launcher").arg(file_path(url)).spawn(),
+        Target::Url(ref url) => Command::new("browser").arg(url).spawn(),
+        // The editor cannot be asked for a line yet, so only the file is opened
+        Target::Path(ref path, _line) => {
+            let mut full = PathBuf::from(path);
+            if let Some(cwd) = cwd {
+                if full.is_relative() {
+                    full = cwd.join(full);
+                }
+            }
+            if ! full.exists() {
+                println!("terminal: {} does not exist", full.display());
+                return;
+            }
+            Command::new("launcher").arg(full).spawn()
+        }
+    };
+
+    if let Err(err) = result {
+        println!("terminal: failed to open {:?}: {}", target, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, file_path, Link, Target};
+
+    fn links(text: &str) -> Vec<Link> {
+        detect(&text.chars().collect::<Vec<char>>())
+    }
+
+    #[test]
+    fn url() {
+        assert_eq!(links("see https://example.com/a?b=c."), vec![Link {
+            start: 4,
+            end: 29,
+            target: Target::Url("https://example.com/a?b=c".to_string()),
+        }]);
+    }
+
+    #[test]
+    fn file_url() {
+        assert_eq!(file_path("file://host/home/user/My%20Files"), "/home/user/My Files");
+        assert_eq!(file_path("file:///tmp/100%"), "/tmp/100%");
+    }
+
+    #[test]
+    fn url_in_parentheses() {
+        assert_eq!(links("(http://example.com)")[0].target, Target::Url("http://example.com".to_string()));
+    }
+
+    #[test]
+    fn path_and_line() {
+        assert_eq!(links("  --> src/terminal/main.rs:12:5"), vec![Link {
+            start: 6,
+            end: 31,
+            target: Target::Path("src/terminal/main.rs".to_string(), 12),
+        }]);
+    }
+
+    #[test]
+    fn not_a_path() {
+        assert!(links("error:12 at 10:30").is_empty());
+    }
+}
@@ -1,10 +1,9 @@
 #![deny(warnings)]
-#![feature(asm)]
-#![feature(const_fn)]
-#![cfg_attr(not(target_os = "redox"), feature(process_try_wait))]
 
 extern crate orbclient;
 extern crate orbfont;
+extern crate orbimage;
+extern crate regex;
 
 #[cfg(not(target_os = "redox"))]
 extern crate libc;
@@ -12,107 +11,91 @@ extern crate libc;
 #[cfg(target_os = "redox")]
 extern crate syscall;
 
-use orbclient::event::EventOption;
-use std::{cmp, env, str};
-use std::error::Error;
+use std::{cmp, env};
+use std::io::{self, Write};
+
+#[cfg(target_os = "redox")]
 use std::fs::{File, OpenOptions};
-use std::io::{self, Result, Read, Write};
-use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
-use std::os::unix::process::CommandExt;
-use std::process::{Child, Command, Stdio};
+#[cfg(target_os = "redox")]
+use std::io::Read;
 
-use console::Console;
-use getpty::getpty;
+use config::Config;
+use console::Fonts;
+use tabs::Terminal;
 
+mod asciicast;
+mod attributes;
+mod config;
 mod console;
+#[cfg(not(target_os = "redox"))]
+mod display;
 mod getpty;
-
-#[cfg(not(target_os="redox"))]
-pub fn before_exec() -> Result<()> {
-    use libc;
-    unsafe {
-        if libc::setsid() < 0 {
-            panic!("setsid: {:?}", io::Error::last_os_error());
-        }
-        if libc::ioctl(0, libc::TIOCSCTTY, 1) < 0 {
-            panic!("ioctl: {:?}", io::Error::last_os_error());
+mod graphics;
+#[cfg(test)]
+mod headless;
+mod kitty;
+mod line;
+mod links;
+mod pty;
+mod replay;
+mod scroll;
+mod search;
+mod sixel;
+mod state;
+mod tabs;
+mod theme;
+mod wcwidth;
+
+/// Ask for a timer event `nsec` nanoseconds from now, to drive blinking and draw output held back by `handle`
+#[cfg(target_os = "redox")]
+fn schedule_tick(time_file: &mut File, nsec: i32) {
+    let mut time = syscall::TimeSpec::default();
+    if time_file.read(&mut time).expect("terminal: failed to read time file") >= std::mem::size_of::<syscall::TimeSpec>() {
+        time.tv_nsec += nsec;
+        while time.tv_nsec >= 1_000_000_000 {
+            time.tv_sec += 1;
+            time.tv_nsec -= 1_000_000_000;
         }
+        time_file.write(&time).expect("terminal: failed to write time file");
     }
-    Ok(())
-}
-
-#[cfg(target_os="redox")]
-pub fn before_exec() -> Result<()> {
-    Ok(())
 }
 
 #[cfg(target_os = "redox")]
-fn handle(console: &mut Console, master_fd: RawFd, process: &mut Child) {
-    extern crate syscall;
-
+fn handle(terminal: &mut Terminal) {
     use std::os::unix::io::AsRawFd;
 
     let mut event_file = File::open("event:").expect("terminal: failed to open event file");
 
-    let window_fd = console.window.as_raw_fd();
+    let window_fd = terminal.window.as_raw_fd();
     syscall::fevent(window_fd, syscall::flag::EVENT_READ).expect("terminal: failed to fevent console window");
 
-    let mut master = unsafe { File::from_raw_fd(master_fd) };
-    syscall::fevent(master_fd, syscall::flag::EVENT_READ).expect("terminal: failed to fevent master PTY");
+    let mut time_file = OpenOptions::new().read(true).write(true).open(&format!("time:{}", syscall::CLOCK_MONOTONIC)).expect("terminal: failed to open time file");
+    let time_fd = time_file.as_raw_fd();
+    syscall::fevent(time_fd, syscall::flag::EVENT_READ).expect("terminal: failed to fevent time file");
+    schedule_tick(&mut time_file, 100_000_000);
 
     let mut handle_event = |event_id: usize, event_count: usize| -> bool {
         if event_id == window_fd {
-            for event in console.window.events() {
-                let event_option = event.to_option();
-
-                console.input(event_option);
-
-                match event_option {
-                    EventOption::Quit(_) => return false,
-                    EventOption::Resize(_) => {
-                        //TODO: Send resize to PTY
-                    },
-                    _ => ()
-                }
-            }
-        } else if event_id == master_fd {
-            let mut packet = [0; 4096];
-            let count = master.read(&mut packet).expect("terminal: failed to read master PTY");
-            if count == 0 {
-                if event_count == 0 {
+            for event in terminal.window.events() {
+                if ! terminal.event(event.to_option()) {
                     return false;
                 }
-            } else {
-                console.write(&packet[1..count], true).expect("terminal: failed to write to console");
-
-                //if packet[0] & 1 == 1
-                {
-                    console.redraw();
-                }
             }
+            terminal.update(false);
+        } else if event_id == time_fd {
+            terminal.update(true);
+            // Output held back below is drawn on the next tick, so come back sooner while there is some
+            schedule_tick(&mut time_file, if terminal.pending() { 16_000_000 } else { 100_000_000 });
         } else {
-            println!("Unknown event {}", event_id);
-        }
-
-        if ! console.input.is_empty()  {
-            if let Err(err) = master.write(&console.input) {
-                let term_stderr = io::stderr();
-                let mut term_stderr = term_stderr.lock();
-
-                let _ = term_stderr.write(b"failed to write stdin: ");
-                let _ = term_stderr.write(err.description().as_bytes());
-                let _ = term_stderr.write(b"\n");
-                return false;
-            }
-            let _ = master.flush();
-            console.input.clear();
+            // Each pane's master PTY, drawn at most once a frame while output streams in
+            terminal.read(event_id, event_count);
+            terminal.update(false);
         }
 
-        true
+        ! terminal.is_empty()
     };
 
     handle_event(window_fd, 0);
-    handle_event(master_fd, 0);
 
     'events: loop {
         let mut sys_event = syscall::Event::default();
@@ -121,146 +104,156 @@ fn handle(console: &mut Console, master_fd: RawFd, process: &mut Child) {
             break 'events;
         }
     }
-
-    let _ = process.kill();
-    process.wait().expect("terminal: failed to wait on shell");
 }
 
 #[cfg(not(target_os = "redox"))]
-fn handle(console: &mut Console, master_fd: RawFd, process: &mut Child) {
+fn handle(terminal: &mut Terminal) {
     use libc;
     use std::io::ErrorKind;
-    use std::thread;
-    use std::time::Duration;
-
-    unsafe {
-        let size = libc::winsize {
-            ws_row: console.console.h as libc::c_ushort,
-            ws_col: console.console.w as libc::c_ushort,
-            ws_xpixel: 0,
-            ws_ypixel: 0
-        };
-        if libc::ioctl(master_fd, libc::TIOCSWINSZ, &size as *const libc::winsize) < 0 {
-            panic!("ioctl: {:?}", io::Error::last_os_error());
-        }
-    }
 
-    console.console.raw_mode = true;
+    // Window events are only seen by looking at SDL's queue, so waiting on the master PTYs is cut short after this
+    // long to look again
+    const WINDOW_POLL_MS: libc::c_int = 16;
 
-    let mut master = unsafe { File::from_raw_fd(master_fd) };
+    let mut poll_fds: Vec<libc::pollfd> = Vec::new();
 
     'events: loop {
-        for event in console.window.events() {
-            let event_option = event.to_option();
-
-            console.input(event_option);
-
-            match event_option {
-                EventOption::Quit(_) => break 'events,
-                EventOption::Resize(_) => unsafe {
-                    let size = libc::winsize {
-                        ws_row: console.console.h as libc::c_ushort,
-                        ws_col: console.console.w as libc::c_ushort,
-                        ws_xpixel: 0,
-                        ws_ypixel: 0
-                    };
-                    if libc::ioctl(master_fd, libc::TIOCSWINSZ, &size as *const libc::winsize) < 0 {
-                        panic!("ioctl: {:?}", io::Error::last_os_error());
-                    }
-                },
-                _ => ()
+        for event in terminal.window.events() {
+            if ! terminal.event(event.to_option()) {
+                break 'events;
             }
         }
 
-        let mut packet = [0; 4096];
-        match master.read(&mut packet) {
-            Ok(0) => break 'events,
-            Ok(count) => {
-                console.write(&packet[..count], true).expect("terminal: failed to write to console");
-                console.redraw();
-            },
-            Err(err) => match err.kind() {
-                ErrorKind::WouldBlock => (),
-                _ => panic!("terminal: failed to read master PTY: {:?}", err)
+        // Take in everything each shell has written, up to a frame's worth of time, before drawing it once
+        for poll_fd in poll_fds.iter() {
+            if poll_fd.revents != 0 {
+                terminal.read(poll_fd.fd, 0);
             }
         }
 
-        if ! console.input.is_empty()  {
-            if let Err(err) = master.write(&console.input) {
-                let term_stderr = io::stderr();
-                let mut term_stderr = term_stderr.lock();
+        terminal.update(true);
+        if terminal.is_empty() {
+            break 'events;
+        }
 
-                let _ = term_stderr.write(b"failed to write stdin: ");
-                let _ = term_stderr.write(err.description().as_bytes());
-                let _ = term_stderr.write(b"\n");
-                break 'events;
-            }
-            let _ = master.flush();
-            console.input.clear();
+        // Sleep until a shell writes something, something is due to be drawn, such as the next blink, or it is time to
+        // look for window events again. Reading stopped early with output still waiting wakes up at once.
+        let mut timeout = match terminal.next_tick() {
+            Some(due) => cmp::min(due.as_secs() * 1000 + (due.subsec_nanos() as u64 + 999_999) / 1_000_000, i32::max_value() as u64) as libc::c_int,
+            None => -1
+        };
+        if timeout < 0 || timeout > WINDOW_POLL_MS {
+            timeout = WINDOW_POLL_MS;
+        }
+        if display::events_waiting() {
+            timeout = 0;
         }
 
-        match process.try_wait() {
-            Ok(status) => match status {
-                Some(_code) => break 'events,
-                None => ()
-            },
-            Err(err) => match err.kind() {
-                ErrorKind::WouldBlock => (),
-                _ => panic!("terminal: failed to wait on child: {:?}", err)
+        poll_fds = terminal.master_fds().into_iter().map(|fd| libc::pollfd {
+            fd: fd,
+            events: libc::POLLIN,
+            revents: 0
+        }).collect();
+        if unsafe { libc::poll(poll_fds.as_mut_ptr(), poll_fds.len() as libc::nfds_t, timeout) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != ErrorKind::Interrupted {
+                panic!("terminal: failed to poll master PTY: {:?}", err);
             }
         }
-
-        thread::sleep(Duration::new(0, 10));
     }
-
-    let _ = process.kill();
-    process.wait().expect("terminal: failed to wait on shell");
 }
 
 fn main() {
-    let mut args = env::args().skip(1);
-    let shell = args.next().unwrap_or("sh".to_string());
-
-    let (master_fd, tty_path) = getpty();
+    // Output of the first tab is recorded to this file
+    let mut record = None;
+    // A recording to play instead of starting a shell, and how fast to play it
+    let mut playback = None;
+    let mut speed = 1.0;
+    // Named settings from the config file, applied before the other options
+    let mut profile = None;
+    // Settings given as options, such as `--working-directory` to start the shell in a given directory
+    let mut options = Vec::new();
+
+    // Options come before the shell and its arguments
+    let mut args = env::args().skip(1).peekable();
+    loop {
+        let arg = match args.peek() {
+            Some(arg) if arg.starts_with("--") => arg.clone(),
+            _ => break
+        };
+        args.next();
 
-    let slave_stdin = OpenOptions::new().read(true).write(false).open(&tty_path).unwrap();
-    let slave_stdout = OpenOptions::new().read(false).write(true).open(&tty_path).unwrap();
-    let slave_stderr = OpenOptions::new().read(false).write(true).open(&tty_path).unwrap();
+        if arg == "--" {
+            break;
+        }
+        let key = arg[2..].replace('-', "_");
+
+        match args.next() {
+            Some(value) => match key.as_str() {
+                "profile" => profile = Some(value),
+                "record" => record = Some(value),
+                "replay" => playback = Some(value),
+                "speed" => match value.parse::<f64>() {
+                    Ok(value) if value > 0.0 => speed = value,
+                    _ => println!("terminal: Invalid speed {}", value)
+                },
+                _ => options.push((key, value))
+            },
+            None => println!("terminal: missing value for --{}", key.replace('_', "-"))
+        }
+    }
 
-    let (display_width, display_height) = orbclient::get_display_size().expect("viewer: failed to get display size");
-    let (width, height) = (cmp::min(1024, display_width * 4/5), cmp::min(768, display_height * 4/5));
+    let mut config = Config::load(profile.as_ref().map(|profile| profile.as_str()));
+    for (key, value) in options {
+        if let Err(err) = config.set(&key, &value) {
+            println!("terminal: {}", err);
+        }
+    }
 
-    env::set_var("COLUMNS", format!("{}", width / 8));
-    env::set_var("LINES", format!("{}", height / 16));
-    env::set_var("TERM", "linux");
-    env::set_var("TTY", format!("{}", tty_path.display()));
+    if let Some(path) = playback {
+        if let Err(err) = replay::replay(&config, &path, speed) {
+            let term_stderr = io::stderr();
+            let mut term_stderr = term_stderr.lock();
+            let _ = term_stderr.write(b"terminal: ");
+            let _ = term_stderr.write(err.as_bytes());
+            let _ = term_stderr.write(b"\n");
+        }
+        return;
+    }
 
-    let mut command = Command::new(&shell);
-    for arg in args {
-        command.arg(arg);
+    // The shell and its arguments, started again in every new tab and split, otherwise the one in the config
+    let mut command: Vec<String> = args.collect();
+    if command.is_empty() {
+        command = config.command.clone();
     }
-    unsafe {
-        command
-        .stdin(Stdio::from_raw_fd(slave_stdin.into_raw_fd()))
-        .stdout(Stdio::from_raw_fd(slave_stdout.into_raw_fd()))
-        .stderr(Stdio::from_raw_fd(slave_stderr.into_raw_fd()))
-        .before_exec(|| {
-            before_exec()
-        });
+    if command.is_empty() {
+        command.push("sh".to_string());
+    }
+
+    let (display_width, display_height) = orbclient::get_display_size().expect("viewer: failed to get display size");
+    let (mut width, mut height) = (cmp::min(1024, display_width * 4/5), cmp::min(768, display_height * 4/5));
+    // A size in cells takes measuring the font, which the first pane loads again
+    if config.columns.is_some() || config.rows.is_some() {
+        match Fonts::new(&config) {
+            Ok(fonts) => {
+                if let Some(columns) = config.columns {
+                    width = cmp::min(display_width, (columns * fonts.cell_w) as u32);
+                }
+                if let Some(rows) = config.rows {
+                    height = cmp::min(display_height, (rows * fonts.cell_h) as u32);
+                }
+            },
+            Err(err) => println!("terminal: {}", err)
+        }
     }
 
-    match command.spawn() {
-        Ok(mut process) => {
-            let mut console = Console::new(width, height);
-            handle(&mut console, master_fd, &mut process);
-        },
+    match Terminal::new(config, command, width, height, record.as_ref().map(|path| path.as_str())) {
+        Ok(mut terminal) => handle(&mut terminal),
         Err(err) => {
             let term_stderr = io::stderr();
             let mut term_stderr = term_stderr.lock();
-            let _ = term_stderr.write(b"failed to execute '");
-            let _ = term_stderr.write(shell.as_bytes());
-            let _ = term_stderr.write(b"': ");
-            let _ = term_stderr.write(err.description().as_bytes());
+            let _ = term_stderr.write(b"terminal: ");
+            let _ = term_stderr.write(err.as_bytes());
             let _ = term_stderr.write(b"\n");
         }
     }
@@ -0,0 +1,255 @@
+//! A pseudo terminal with a program running on it, the same to the rest of the terminal on Linux and Redox
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+use getpty::getpty;
+use line::Signal;
+
+/// Make the program the leader of a session with the PTY as its controlling terminal
+#[cfg(not(target_os = "redox"))]
+fn before_exec() -> io::Result<()> {
+    use libc;
+    unsafe {
+        if libc::setsid() < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::ioctl(0, libc::TIOCSCTTY, 1) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "redox")]
+fn before_exec() -> io::Result<()> {
+    use syscall;
+    // The program leads a process group of its own, for the line discipline to signal
+    syscall::setpgid(0, 0).map_err(|err| io::Error::from_raw_os_error(err.errno))?;
+    Ok(())
+}
+
+/// Tell the program on the PTY that it is `w` by `h` cells
+#[cfg(not(target_os = "redox"))]
+fn set_size(master_fd: RawFd, w: usize, h: usize) -> Result<(), String> {
+    use libc;
+    let size = libc::winsize {
+        ws_row: h as libc::c_ushort,
+        ws_col: w as libc::c_ushort,
+        ws_xpixel: 0,
+        ws_ypixel: 0
+    };
+    if unsafe { libc::ioctl(master_fd, libc::TIOCSWINSZ, &size as *const libc::winsize) } < 0 {
+        return Err(format!("failed to resize PTY: {}", io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "redox")]
+fn set_size(master_fd: RawFd, w: usize, h: usize) -> Result<(), String> {
+    use std::slice;
+    use syscall;
+
+    // The pty: scheme takes the size as a termios winsize, rows then columns, written to a handle duplicated from the master
+    let size_fd = syscall::dup(master_fd, b"winsize").map_err(|err| format!("failed to open PTY size: {}", err))?;
+    let size = [h as u16, w as u16];
+    let bytes = unsafe { slice::from_raw_parts(size.as_ptr() as *const u8, 4) };
+    let result = syscall::write(size_fd, bytes);
+    let _ = syscall::close(size_fd);
+    result.map(|_| ()).map_err(|err| format!("failed to resize PTY: {}", err))
+}
+
+/// Have output on the master reported to the event queue
+#[cfg(not(target_os = "redox"))]
+fn watch(_master_fd: RawFd) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(target_os = "redox")]
+fn watch(master_fd: RawFd) -> Result<(), String> {
+    use syscall;
+    syscall::fevent(master_fd, syscall::flag::EVENT_READ).map_err(|err| format!("failed to fevent master PTY: {}", err))?;
+    Ok(())
+}
+
+/// What came of reading the master
+pub enum Output<'a> {
+    Data(&'a [u8]),
+    /// Nothing more to read for now
+    Empty,
+    /// Every process has closed the slave side
+    Closed,
+}
+
+/// The master side of a PTY and the program started on its slave side, which is killed when this is dropped
+pub struct Pty {
+    master: File,
+    pub master_fd: RawFd,
+    process: Child,
+}
+
+impl Pty {
+    /// Open a PTY of `w` by `h` cells and start `command` on it in `dir`, or our own directory, with `env` added to its
+    /// environment
+    pub fn spawn(command: &[String], dir: Option<&Path>, env: &[(&str, String)], w: usize, h: usize) -> Result<Pty, String> {
+        let (master_fd, tty_path) = getpty()?;
+        // Owned from here on, so the master is closed again if anything below fails
+        let master = unsafe { File::from_raw_fd(master_fd) };
+
+        if let Err(err) = set_size(master_fd, w, h) {
+            println!("terminal: {}", err);
+        }
+        watch(master_fd)?;
+
+        let open = |read: bool, write: bool| {
+            OpenOptions::new().read(read).write(write).open(&tty_path)
+                .map_err(|err| format!("failed to open {}: {}", tty_path.display(), err))
+        };
+        let slave_stdin = open(true, false)?;
+        let slave_stdout = open(false, true)?;
+        let slave_stderr = open(false, true)?;
+
+        let shell = command.get(0).map(|shell| shell.as_str()).unwrap_or("sh");
+        let mut process = Command::new(shell);
+        if command.len() > 1 {
+            process.args(&command[1..]);
+        }
+        if let Some(dir) = dir {
+            process.current_dir(dir);
+        }
+        for &(key, ref value) in env.iter() {
+            process.env(key, value);
+        }
+        process.env("TTY", format!("{}", tty_path.display()));
+        unsafe {
+            process
+            .stdin(Stdio::from_raw_fd(slave_stdin.into_raw_fd()))
+            .stdout(Stdio::from_raw_fd(slave_stdout.into_raw_fd()))
+            .stderr(Stdio::from_raw_fd(slave_stderr.into_raw_fd()))
+            .before_exec(|| {
+                before_exec()
+            });
+        }
+
+        let process = process.spawn().map_err(|err| format!("failed to execute '{}': {}", shell, err))?;
+
+        Ok(Pty {
+            master: master,
+            master_fd: master_fd,
+            process: process,
+        })
+    }
+
+    pub fn resize(&self, w: usize, h: usize) -> Result<(), String> {
+        set_size(self.master_fd, w, h)
+    }
+
+    /// Read what the program has written, without waiting for more
+    #[cfg(not(target_os = "redox"))]
+    pub fn read<'a>(&mut self, buf: &'a mut [u8]) -> Result<Output<'a>, String> {
+        use libc;
+        match self.master.read(buf) {
+            Ok(0) => Ok(Output::Closed),
+            Ok(count) => Ok(Output::Data(&buf[..count])),
+            // Linux reports EIO once every process has closed the slave side
+            Err(ref err) if err.raw_os_error() == Some(libc::EIO) => Ok(Output::Closed),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(Output::Empty),
+            Err(err) => Err(format!("failed to read master PTY: {}", err))
+        }
+    }
+
+    #[cfg(target_os = "redox")]
+    pub fn read<'a>(&mut self, buf: &'a mut [u8]) -> Result<Output<'a>, String> {
+        let count = self.master.read(buf).map_err(|err| format!("failed to read master PTY: {}", err))?;
+        // Each packet starts with a byte saying what kind it is, the rest is output
+        if count == 0 {
+            Ok(Output::Empty)
+        } else {
+            Ok(Output::Data(&buf[1..count]))
+        }
+    }
+
+    /// Write input for the program, returning how much of it the PTY took
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, String> {
+        let count = match self.master.write(buf) {
+            Ok(count) => count,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => 0,
+            Err(err) => return Err(format!("failed to write to master PTY: {}", err))
+        };
+        let _ = self.master.flush();
+        Ok(count)
+    }
+
+    /// End the file the program is reading, by writing nothing so its read returns nothing
+    pub fn end_of_file(&mut self) -> Result<(), String> {
+        self.master.write(&[]).map(|_| ()).map_err(|err| format!("failed to write to master PTY: {}", err))
+    }
+
+    /// Send a signal from the line discipline to the foreground process group
+    #[cfg(not(target_os = "redox"))]
+    pub fn signal(&self, signal: Signal) -> Result<(), String> {
+        use libc;
+        let number = match signal {
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Quit => libc::SIGQUIT,
+            Signal::Suspend => libc::SIGTSTP
+        };
+        unsafe {
+            // The program leads the session, so its group is the one to fall back on
+            let mut group = libc::tcgetpgrp(self.master_fd);
+            if group <= 0 {
+                group = self.process.id() as libc::pid_t;
+            }
+            if libc::kill(-group, number) < 0 {
+                return Err(format!("failed to send {:?}: {}", signal, io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a signal from the line discipline to the program's process group.
+    ///
+    /// Redox cannot say which group is in the foreground, so programs the shell starts get it by staying in its group.
+    #[cfg(target_os = "redox")]
+    pub fn signal(&self, signal: Signal) -> Result<(), String> {
+        use syscall;
+        let number = match signal {
+            Signal::Interrupt => syscall::SIGINT,
+            Signal::Quit => syscall::SIGQUIT,
+            Signal::Suspend => syscall::SIGTSTP
+        };
+        let group = (self.process.id() as isize).wrapping_neg() as usize;
+        syscall::kill(group, number).map(|_| ()).map_err(|err| format!("failed to send {:?}: {}", signal, err))
+    }
+
+    /// Working directory of the program
+    #[cfg(not(target_os = "redox"))]
+    pub fn cwd(&self) -> Option<PathBuf> {
+        ::std::fs::read_link(format!("/proc/{}/cwd", self.process.id())).ok()
+    }
+
+    #[cfg(target_os = "redox")]
+    pub fn cwd(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Whether the program has exited
+    pub fn exited(&mut self) -> Result<bool, String> {
+        match self.process.try_wait() {
+            Ok(status) => Ok(status.is_some()),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(format!("failed to wait on child: {}", err))
+        }
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
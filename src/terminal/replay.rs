@@ -0,0 +1,179 @@
+use orbclient::{Color, EventOption, Renderer, Window, WindowFlag};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use asciicast::{Event, Recording};
+use config::Config;
+use console::{Console, Fonts};
+use tabs::{blit, Rect};
+
+/// Seconds skipped by the left and right arrows
+const SEEK: f64 = 5.0;
+
+/// A recording played through a console, at a position that can be moved in either direction
+struct Player<'a> {
+    config: &'a Config,
+    recording: Recording,
+    console: Console,
+    /// Index of the next event to play
+    next: usize,
+    /// Position in the recording, in seconds
+    clock: f64,
+    speed: f64,
+    paused: bool,
+    /// Whether the whole window has to be drawn again, after a resize or seeking backwards
+    full: bool,
+}
+
+impl<'a> Player<'a> {
+    fn new(config: &'a Config, recording: Recording, speed: f64) -> Result<Player<'a>, String> {
+        let console = Player::console(config, recording.width, recording.height)?;
+        Ok(Player {
+            config: config,
+            recording: recording,
+            console: console,
+            next: 0,
+            clock: 0.0,
+            speed: speed,
+            paused: false,
+            full: true,
+        })
+    }
+
+    /// A console of `width` by `height` cells, with nothing written to it yet
+    fn console(config: &Config, width: usize, height: usize) -> Result<Console, String> {
+        let fonts = Fonts::new(config)?;
+        let (cell_w, cell_h) = (fonts.cell_w, fonts.cell_h);
+        let mut console = Console::new((width * cell_w) as u32, (height * cell_h) as u32, fonts, config);
+        // Nothing is typed into a replay, this only keeps writes from drawing before the frame does
+        console.state.console.raw_mode = true;
+        Ok(console)
+    }
+
+    /// Play every event up to the current position
+    fn play(&mut self) {
+        while let Some(&(time, ref event)) = self.recording.events.get(self.next) {
+            if time > self.clock {
+                break;
+            }
+            match *event {
+                Event::Output(ref data) => {
+                    let _ = self.console.write(data.as_bytes(), false);
+                },
+                Event::Resize(width, height) => {
+                    let (cell_w, cell_h) = (self.console.fonts.cell_w, self.console.fonts.cell_h);
+                    self.console.resize((width * cell_w) as u32, (height * cell_h) as u32);
+                    self.full = true;
+                }
+            }
+            self.next += 1;
+        }
+    }
+
+    /// Move to `time` seconds into the recording, playing it again from the start to go backwards
+    fn seek(&mut self, time: f64) -> Result<(), String> {
+        let time = time.max(0.0).min(self.recording.duration());
+        if time < self.clock {
+            self.console = Player::console(self.config, self.recording.width, self.recording.height)?;
+            self.next = 0;
+            self.full = true;
+        }
+        self.clock = time;
+        self.play();
+        Ok(())
+    }
+
+    fn title(&self, path: &str) -> String {
+        format!("{} - {:.1}/{:.1}s x{}{}", path, self.clock.min(self.recording.duration()), self.recording.duration(),
+                self.speed, if self.paused { " (paused)" } else { "" })
+    }
+}
+
+/// Play a recording made with `--record` in a window of its own.
+///
+/// Space pauses, the left and right arrows seek, up and down double and halve the speed and Home starts again.
+pub fn replay(config: &Config, path: &str, speed: f64) -> Result<(), String> {
+    let recording = Recording::open(path)?;
+    let mut player = Player::new(config, recording, speed)?;
+
+    let (width, height) = (player.console.image.width(), player.console.image.height());
+    let mut window = Window::new_flags(-1, -1, width, height, path, &[WindowFlag::Async, WindowFlag::Resizable])
+        .ok_or_else(|| "failed to open window".to_string())?;
+
+    let mut title = String::new();
+    let mut last = Instant::now();
+    loop {
+        for event in window.events() {
+            match event.to_option() {
+                EventOption::Key(key_event) => if key_event.pressed {
+                    match key_event.scancode {
+                        // Space
+                        0x39 => player.paused = ! player.paused,
+                        // Left
+                        0x4B => {
+                            let time = player.clock - SEEK;
+                            player.seek(time)?;
+                        },
+                        // Right
+                        0x4D => {
+                            let time = player.clock + SEEK;
+                            player.seek(time)?;
+                        },
+                        // Up
+                        0x48 => player.speed = (player.speed * 2.0).min(64.0),
+                        // Down
+                        0x50 => player.speed = (player.speed / 2.0).max(1.0 / 64.0),
+                        // Home
+                        0x47 => player.seek(0.0)?,
+                        _ => ()
+                    }
+                },
+                EventOption::Resize(_) => player.full = true,
+                EventOption::Quit(_) => return Ok(()),
+                _ => ()
+            }
+        }
+
+        let elapsed = last.elapsed();
+        last = Instant::now();
+        if ! player.paused {
+            let time = player.clock + (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0) * player.speed;
+            player.seek(time)?;
+        }
+
+        player.console.tick();
+
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            w: player.console.image.width(),
+            h: player.console.image.height(),
+        };
+        let mut changed = false;
+        if player.full {
+            player.full = false;
+            window.set(Color { data: config.theme.background });
+            blit(&mut window, &player.console.image, rect, 0, rect.h as usize);
+            changed = true;
+        } else {
+            let cell_h = player.console.fonts.cell_h;
+            for &row in player.console.changed.iter() {
+                blit(&mut window, &player.console.image, rect, row * cell_h, (row + 1) * cell_h);
+                changed = true;
+            }
+        }
+        player.console.changed.clear();
+
+        let new_title = player.title(path);
+        if new_title != title {
+            window.set_title(&new_title);
+            title = new_title;
+        }
+
+        if changed {
+            window.sync();
+        }
+
+        thread::sleep(Duration::from_millis(16));
+    }
+}
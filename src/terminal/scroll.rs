@@ -0,0 +1,46 @@
+//! Moving the window contents, kept apart from `Console` so the benchmarks can build it on its own
+
+use std::cmp;
+
+/// Move the first `height` rows of `data`, `width` pixels each, up by `pixel_rows`, filling the rows left at the bottom
+/// with `background`
+pub fn scroll_pixels<T: Copy>(data: &mut [T], width: usize, height: usize, pixel_rows: usize, background: T) {
+    if pixel_rows == 0 || pixel_rows >= height || data.len() < height * width {
+        return;
+    }
+    let off1 = pixel_rows * width;
+    let off2 = height * width - off1;
+
+    // Each piece is copied from the `off1` pixels right after it, which never overlap it
+    let mut start = 0;
+    while start < off2 {
+        let len = cmp::min(off1, off2 - start);
+        let (dst, src) = data[start..].split_at_mut(off1);
+        dst[..len].copy_from_slice(&src[..len]);
+        start += len;
+    }
+
+    for pixel in data[off2..height * width].iter_mut() {
+        *pixel = background;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scroll_pixels;
+
+    #[test]
+    fn scroll() {
+        // Five rows of two pixels, moved up by fewer rows than stay on the screen and by more
+        let mut data: Vec<u32> = (0..10).collect();
+        scroll_pixels(&mut data, 2, 5, 2, 99);
+        assert_eq!(data, vec![4, 5, 6, 7, 8, 9, 99, 99, 99, 99]);
+        let mut data: Vec<u32> = (0..10).collect();
+        scroll_pixels(&mut data, 2, 5, 3, 99);
+        assert_eq!(data, vec![6, 7, 8, 9, 99, 99, 99, 99, 99, 99]);
+
+        // Nothing moves for the whole height or more
+        scroll_pixels(&mut data, 2, 5, 5, 0);
+        assert_eq!(data, vec![6, 7, 8, 9, 99, 99, 99, 99, 99, 99]);
+    }
+}
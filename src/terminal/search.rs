@@ -0,0 +1,213 @@
+//! Finding text in the scrollback and on screen
+
+use regex::{escape, RegexBuilder};
+
+use state::State;
+
+/// Text found in a row of the scrollback followed by the grid, from cell `start` up to but not including `end`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Match {
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Find `query` in rows of cells, one character each, or `'\0'` for the second half of a wide character.
+///
+/// Plain text is matched ignoring case unless the query has a capital letter in it, a regular expression is used as
+/// written. Matches do not continue from one row onto the next.
+pub fn find(rows: &[Vec<char>], query: &str, regex: bool) -> Result<Vec<Match>, String> {
+    let mut matches = Vec::new();
+    if query.is_empty() {
+        return Ok(matches);
+    }
+
+    let pattern = if regex {
+        query.to_string()
+    } else {
+        escape(query)
+    };
+    let ignore_case = ! regex && ! query.chars().any(|c| c.is_uppercase());
+    let re = RegexBuilder::new(&pattern).case_insensitive(ignore_case).build().map_err(|err| format!("{}", err))?;
+
+    for (row, text) in rows.iter().enumerate() {
+        // The cell each character of the line starts at, by its offset in the line, and the end of the row
+        let mut line = String::new();
+        let mut cells = Vec::new();
+        for (x, &c) in text.iter().enumerate() {
+            if c != '\0' {
+                cells.push((line.len(), x));
+                line.push(c);
+            }
+        }
+        cells.push((line.len(), text.len()));
+        let cell = |offset: usize| -> usize {
+            cells.binary_search_by_key(&offset, |&(start, _)| start).map(|i| cells[i].1).unwrap_or(0)
+        };
+
+        for found in re.find_iter(&line) {
+            // An empty match, like that of `a*`, has nothing to show
+            if found.start() == found.end() {
+                continue;
+            }
+            matches.push(Match {
+                row: row,
+                start: cell(found.start()),
+                end: cell(found.end()),
+            });
+        }
+    }
+
+    Ok(matches)
+}
+
+/// A search opened with Ctrl+Shift+F, updated as the query is typed
+pub struct Search {
+    pub query: String,
+    /// Whether the query is a regular expression rather than plain text
+    pub regex: bool,
+    /// Every match, in the order they were written
+    pub matches: Vec<Match>,
+    /// Index of the match moved to with next and previous
+    pub current: Option<usize>,
+    /// Why the query could not be used, such as a regular expression that is not finished yet
+    pub error: Option<String>,
+    /// Whether output has arrived since the matches were found
+    pub stale: bool,
+}
+
+impl Search {
+    pub fn new() -> Search {
+        Search {
+            query: String::new(),
+            regex: false,
+            matches: Vec::new(),
+            current: None,
+            error: None,
+            stale: false,
+        }
+    }
+
+    /// Find the query again in the scrollback and the grid of `state`.
+    ///
+    /// The current match stays where it was if it still matches, or moves on to the next one, so the view does not jump
+    /// around while the query is typed. A new search starts from the most recent match.
+    pub fn update(&mut self, state: &State) {
+        let anchor = self.current.and_then(|i| self.matches.get(i).cloned());
+        self.stale = false;
+
+        let rows: Vec<Vec<char>> = (0..state.history_len()).map(|i| {
+            state.history_row(i).iter().map(|block| {
+                if block.width == 0 {
+                    '\0'
+                } else if block.c == '\0' {
+                    ' '
+                } else {
+                    block.c
+                }
+            }).collect()
+        }).collect();
+
+        match find(&rows, &self.query, self.regex) {
+            Ok(matches) => {
+                self.matches = matches;
+                self.error = None;
+            },
+            Err(err) => {
+                // The last good matches stay shown until the expression is finished
+                self.error = Some(err);
+                return;
+            }
+        }
+
+        self.current = if self.matches.is_empty() {
+            None
+        } else {
+            match anchor {
+                Some(anchor) => {
+                    let next = self.matches.iter().position(|found| (found.row, found.start) >= (anchor.row, anchor.start));
+                    Some(next.unwrap_or(self.matches.len() - 1))
+                },
+                None => Some(self.matches.len() - 1)
+            }
+        };
+    }
+
+    /// Move `step` matches forward, or backward when negative, wrapping around at either end
+    pub fn step(&mut self, step: isize) {
+        let len = self.matches.len() as isize;
+        if len == 0 {
+            return;
+        }
+        let i = match self.current {
+            Some(i) => i as isize + step,
+            None => if step > 0 { step - 1 } else { len + step }
+        };
+        self.current = Some((((i % len) + len) % len) as usize);
+    }
+
+    /// The query and where the current match is among all of them, for the search bar
+    pub fn status(&self) -> String {
+        let position = match (self.error.as_ref(), self.current) {
+            (Some(_), _) => "invalid expression".to_string(),
+            (None, Some(i)) => format!("{} of {}", i + 1, self.matches.len()),
+            (None, None) if self.query.is_empty() => String::new(),
+            (None, None) => "no matches".to_string()
+        };
+        format!("{}: {}_  {}", if self.regex { "Find regex" } else { "Find" }, self.query, position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, Match, Search};
+
+    fn rows(lines: &[&str]) -> Vec<Vec<char>> {
+        lines.iter().map(|line| line.chars().collect()).collect()
+    }
+
+    #[test]
+    fn plain_text_ignores_case() {
+        let matches = find(&rows(&["test ok", "Error: TEST failed"]), "test", false).unwrap();
+        assert_eq!(matches, vec![
+            Match { row: 0, start: 0, end: 4 },
+            Match { row: 1, start: 7, end: 11 },
+        ]);
+        assert_eq!(find(&rows(&["test ok", "TEST"]), "TEST", false).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn plain_text_is_not_an_expression() {
+        assert_eq!(find(&rows(&["a.b axb"]), "a.b", false).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn regex() {
+        let matches = find(&rows(&["ok 1", "FAILED 23", "é FAILED 4"]), r"FAILED \d+", true).unwrap();
+        assert_eq!(matches, vec![
+            Match { row: 1, start: 0, end: 9 },
+            Match { row: 2, start: 2, end: 10 },
+        ]);
+        assert!(find(&rows(&["a"]), "(", true).is_err());
+        assert!(find(&rows(&["bbb"]), "a*", true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn wide_characters() {
+        // Each wide character is followed by the second cell it takes
+        let matches = find(&rows(&["日\0本\0 x"]), "本 x", false).unwrap();
+        assert_eq!(matches, vec![Match { row: 0, start: 2, end: 6 }]);
+    }
+
+    #[test]
+    fn step_wraps() {
+        let mut search = Search::new();
+        search.matches = find(&rows(&["x x x"]), "x", false).unwrap();
+        search.step(1);
+        assert_eq!(search.current, Some(0));
+        search.step(-1);
+        assert_eq!(search.current, Some(2));
+        search.step(2);
+        assert_eq!(search.current, Some(1));
+    }
+}
@@ -0,0 +1,255 @@
+//! Sixel images, sent in a DCS string as six pixel tall bands of characters
+
+use std::cmp;
+
+use graphics::{Graphic, MAX_SIZE};
+
+/// Colour registers of a VT340 before the image sets any, as percentages of red, green and blue
+const VT340_PALETTE: [(u32, u32, u32); 16] = [
+    (0, 0, 0), (20, 20, 80), (80, 13, 13), (20, 80, 20), (80, 20, 80), (20, 80, 80), (80, 80, 20), (53, 53, 53),
+    (26, 26, 26), (33, 33, 60), (60, 26, 26), (33, 60, 33), (60, 33, 60), (33, 60, 60), (60, 60, 33), (80, 80, 80),
+];
+
+/// Number of colour registers
+const REGISTERS: usize = 256;
+
+fn percent(value: u32) -> u32 {
+    cmp::min(value, 100) * 255 / 100
+}
+
+fn rgb(r: u32, g: u32, b: u32) -> u32 {
+    0xFF000000 | percent(r) << 16 | percent(g) << 8 | percent(b)
+}
+
+/// A colour given as hue in degrees and lightness and saturation in percent.
+///
+/// DEC puts blue at 0 degrees and red at 120, a third of a turn from where HLS usually has them.
+fn hls(hue: u32, lightness: u32, saturation: u32) -> u32 {
+    let h = ((hue + 240) % 360) as f32 / 360.0;
+    let l = cmp::min(lightness, 100) as f32 / 100.0;
+    let s = cmp::min(saturation, 100) as f32 / 100.0;
+    if s == 0.0 {
+        let v = (l * 255.0) as u32;
+        return 0xFF000000 | v << 16 | v << 8 | v;
+    }
+
+    let q = if l < 0.5 { l * (1.0 + s) } else { l + s - l * s };
+    let p = 2.0 * l - q;
+    let channel = |t: f32| -> u32 {
+        let t = if t < 0.0 { t + 1.0 } else if t > 1.0 { t - 1.0 } else { t };
+        let v = if t < 1.0 / 6.0 {
+            p + (q - p) * 6.0 * t
+        } else if t < 0.5 {
+            q
+        } else if t < 2.0 / 3.0 {
+            p + (q - p) * (2.0 / 3.0 - t) * 6.0
+        } else {
+            p
+        };
+        (v * 255.0).round() as u32
+    };
+    0xFF000000 | channel(h + 1.0 / 3.0) << 16 | channel(h) << 8 | channel(h - 1.0 / 3.0)
+}
+
+/// Pixels painted so far, growing as the image goes further right and down
+struct Canvas {
+    width: usize,
+    height: usize,
+    data: Vec<u32>,
+    /// Furthest extent painted, which the image is cut down to at the end
+    used_w: usize,
+    used_h: usize,
+}
+
+impl Canvas {
+    fn grow(&mut self, w: usize, h: usize) {
+        if w <= self.width && h <= self.height {
+            return;
+        }
+        let new_w = cmp::min(MAX_SIZE, cmp::max(w, self.width * 2));
+        let new_h = cmp::min(MAX_SIZE, cmp::max(h, self.height * 2));
+        let mut data = vec![0; new_w * new_h];
+        for y in 0..self.height {
+            data[y * new_w..y * new_w + self.width].copy_from_slice(&self.data[y * self.width..(y + 1) * self.width]);
+        }
+        self.width = new_w;
+        self.height = new_h;
+        self.data = data;
+    }
+
+    /// Paint the pixels set in `bits`, the lowest at the top, `count` times starting at `x`
+    fn sixel(&mut self, x: usize, y: usize, bits: u8, count: usize, color: u32) {
+        if bits == 0 || x >= MAX_SIZE || y >= MAX_SIZE {
+            return;
+        }
+        let end_x = cmp::min(x + count, MAX_SIZE);
+        let end_y = cmp::min(y + 6, MAX_SIZE);
+        self.grow(end_x, end_y);
+        for row in y..end_y {
+            if bits & 1 << (row - y) != 0 {
+                for col in x..end_x {
+                    self.data[row * self.width + col] = color;
+                }
+                self.used_h = cmp::max(self.used_h, row + 1);
+            }
+        }
+        self.used_w = cmp::max(self.used_w, end_x);
+    }
+}
+
+/// Read a number, returning it with the index after it
+fn number(data: &[u8], i: usize) -> (u32, usize) {
+    let mut j = i;
+    let mut value = 0u32;
+    while j < data.len() && data[j] >= b'0' && data[j] <= b'9' {
+        value = value.saturating_mul(10).saturating_add((data[j] - b'0') as u32);
+        j += 1;
+    }
+    (value, j)
+}
+
+/// Numbers separated by semicolons starting at `i`, returning them with the index after them
+fn numbers(data: &[u8], i: usize) -> (Vec<u32>, usize) {
+    let mut values = Vec::new();
+    let mut j = i;
+    loop {
+        let (value, end) = number(data, j);
+        values.push(value);
+        if data.get(end) == Some(&b';') {
+            j = end + 1;
+        } else {
+            return (values, end);
+        }
+    }
+}
+
+/// Decode the sixel data following the `q` of a DCS string, or nothing if it paints no pixels.
+///
+/// Pixels that are never painted are left transparent, whatever the background parameter asks for.
+pub fn decode(data: &[u8]) -> Option<Graphic> {
+    let mut palette = [0u32; REGISTERS];
+    for (i, &(r, g, b)) in VT340_PALETTE.iter().enumerate() {
+        palette[i] = rgb(r, g, b);
+    }
+    let mut color = palette[0];
+
+    let mut canvas = Canvas {
+        width: 0,
+        height: 0,
+        data: Vec::new(),
+        used_w: 0,
+        used_h: 0,
+    };
+    let (mut x, mut y) = (0, 0);
+    let mut raster = (0, 0);
+
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            // Raster attributes: aspect ratio, then the size of the image
+            b'"' => {
+                let (values, end) = numbers(data, i + 1);
+                if let (Some(&w), Some(&h)) = (values.get(2), values.get(3)) {
+                    raster = (cmp::min(w as usize, MAX_SIZE), cmp::min(h as usize, MAX_SIZE));
+                    canvas.grow(raster.0, raster.1);
+                }
+                i = end;
+            },
+            // Pick a colour register, or set one
+            b'#' => {
+                let (values, end) = numbers(data, i + 1);
+                let register = values[0] as usize % REGISTERS;
+                if values.len() >= 5 {
+                    palette[register] = match values[1] {
+                        1 => hls(values[2], values[3], values[4]),
+                        _ => rgb(values[2], values[3], values[4])
+                    };
+                }
+                color = palette[register];
+                i = end;
+            },
+            // Repeat the next sixel
+            b'!' => {
+                let (count, end) = number(data, i + 1);
+                if let Some(&c) = data.get(end) {
+                    if c >= b'?' && c <= b'~' {
+                        canvas.sixel(x, y, c - b'?', count as usize, color);
+                        x += count as usize;
+                    }
+                }
+                i = end + 1;
+            },
+            // Back to the start of the band
+            b'$' => {
+                x = 0;
+                i += 1;
+            },
+            // On to the next band
+            b'-' => {
+                x = 0;
+                y += 6;
+                i += 1;
+            },
+            c @ b'?' ... b'~' => {
+                canvas.sixel(x, y, c - b'?', 1, color);
+                x += 1;
+                i += 1;
+            },
+            _ => i += 1
+        }
+    }
+
+    if canvas.used_w == 0 || canvas.used_h == 0 {
+        return None;
+    }
+    // A size given in the raster attributes holds even where the edges were not painted
+    let width = cmp::max(canvas.used_w, raster.0);
+    let height = cmp::max(canvas.used_h, raster.1);
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in 0..height {
+        pixels.extend_from_slice(&canvas.data[row * canvas.width..row * canvas.width + width]);
+    }
+    Some(Graphic {
+        width: width,
+        height: height,
+        data: pixels.into_boxed_slice(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, hls};
+
+    #[test]
+    fn bands_and_repeats() {
+        // A red register, two columns of the top pixel, then a full column on the next band
+        let graphic = decode(b"#1;2;100;0;0#1!2@-~").unwrap();
+        assert_eq!((graphic.width, graphic.height), (2, 12));
+        assert_eq!(graphic.data[0], 0xFFFF0000);
+        assert_eq!(graphic.data[1], 0xFFFF0000);
+        assert_eq!(graphic.data[2], 0);
+        assert_eq!(graphic.data[6 * 2], 0xFFFF0000);
+        assert_eq!(graphic.data[6 * 2 + 1], 0);
+    }
+
+    #[test]
+    fn overprint() {
+        // `$` goes back over the band, painting the second pixel down in the second colour
+        let graphic = decode(b"#1;2;0;0;100~$#2;2;0;100;0A").unwrap();
+        assert_eq!(graphic.data[0], 0xFF0000FF);
+        assert_eq!(graphic.data[1], 0xFF00FF00);
+    }
+
+    #[test]
+    fn hls_hues() {
+        assert_eq!(hls(120, 50, 100), 0xFFFF0000);
+        assert_eq!(hls(240, 50, 100), 0xFF00FF00);
+        assert_eq!(hls(0, 50, 100), 0xFF0000FF);
+    }
+
+    #[test]
+    fn nothing_painted() {
+        assert!(decode(b"\"1;1;10;10").is_none());
+    }
+}
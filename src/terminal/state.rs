@@ -0,0 +1,1072 @@
+extern crate ransid;
+
+use std::{cmp, mem};
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::str;
+
+use attributes::{indexed, Attributes, Sequence, SequenceParser};
+use config::{ClipboardAccess, Config, CursorShape};
+use graphics::{cells, After, Graphic, Placement, MAX_SIZE};
+use kitty::{self, Kitty};
+use links;
+use line::{Action, LineDiscipline, Signal};
+use sixel;
+use theme::{self, Theme};
+use wcwidth;
+
+#[derive(Clone, Copy)]
+pub struct Block {
+    pub c: char,
+    pub fg: u32,
+    pub bg: u32,
+    pub bold: bool,
+    /// Bits from the `attributes` module for the other SGR attributes
+    pub attrs: u8,
+    /// OSC 8 hyperlink, as an index into `State::links` plus one, or zero for none
+    pub link: u16,
+    /// Cells the character takes: 1, or 2 for the first half of a wide character and 0 for its second half, which
+    /// holds a copy of the first so each half can be drawn on its own
+    pub width: u8,
+    /// `c` with the combining characters written after it, as an index into `State::clusters` plus one, or zero for
+    /// `c` alone
+    pub cluster: u16,
+    /// Image drawn over the cell, as an index into `State::graphics` plus one, or zero for none
+    pub image: u16,
+    /// Which cell of the image this is, counting columns and rows from its top left
+    pub tile: (u16, u16),
+}
+
+/// Something a program asked for with an OSC sequence that only the frontend can do
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    /// Copy the text to the clipboard
+    SetClipboard(String),
+    /// Send the clipboard to the program with OSC 52, naming the selections it asked for
+    ReadClipboard(String),
+    /// Show a desktop notification with a title and a body
+    Notify(String, String),
+}
+
+impl Block {
+    fn blank(theme: &Theme) -> Block {
+        Block {
+            c: '\0', fg: theme.foreground, bg: theme.background, bold: false, attrs: 0, link: 0, width: 1, cluster: 0, image: 0, tile: (0, 0)
+        }
+    }
+
+    /// Clear the cell to `bg`, as erasing and scrolling do
+    fn erase(&mut self, bg: u32) {
+        self.c = '\0';
+        self.bg = bg;
+        self.attrs = 0;
+        self.link = 0;
+        self.width = 1;
+        self.cluster = 0;
+        self.image = 0;
+    }
+}
+
+/// Blank the other half of a wide character when cell `i` of a grid `w` cells wide is about to be written over
+fn split_wide(grid: &mut [Block], dirty: &mut [bool], w: usize, i: usize) {
+    let other = match grid[i].width {
+        0 if i % w > 0 => i - 1,
+        2 if i % w + 1 < w => i + 1,
+        _ => return
+    };
+    let bg = grid[other].bg;
+    grid[other].erase(bg);
+    dirty[other] = true;
+}
+
+/// Number of bytes at the end of `data` that start a UTF-8 character without finishing it
+fn incomplete_utf8(data: &[u8]) -> usize {
+    for back in 1..cmp::min(data.len(), 3) + 1 {
+        let b = data[data.len() - back];
+        if b & 0xC0 == 0x80 {
+            continue;
+        }
+        let len = if b >= 0xF0 { 4 } else if b >= 0xE0 { 3 } else if b >= 0xC0 { 2 } else { 1 };
+        return if len > back { back } else { 0 };
+    }
+    0
+}
+
+/// The UTF-8 character at the start of `data` and its length, if it is a whole and valid one
+fn decode_utf8(data: &[u8]) -> Option<(char, usize)> {
+    let len = match data.first() {
+        Some(&b) if b >= 0xF0 => 4,
+        Some(&b) if b >= 0xE0 => 3,
+        Some(_) => 2,
+        None => return None
+    };
+    let text = str::from_utf8(data.get(..len)?).ok()?;
+    text.chars().next().map(|c| (c, len))
+}
+
+/// What the program running in the terminal has written and the modes it has set, independent of how it is drawn
+pub struct State {
+    pub console: ransid::Console,
+    pub title: String,
+    pub alternate: bool,
+    pub grid: Box<[Block]>,
+    pub alt_grid: Box<[Block]>,
+    pub theme: Theme,
+    /// Cells of the grid written since the frontend last drew them
+    pub dirty: Box<[bool]>,
+    /// Rows the grid scrolled since the frontend last drew it
+    pub scrolled: usize,
+    /// Rows that scrolled off the top of the primary screen, oldest first
+    pub scrollback: VecDeque<Box<[Block]>>,
+    pub scrollback_max: usize,
+    /// How many rows the view is scrolled back from the live screen
+    pub scroll_offset: usize,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    /// Set by the program with `ESC[?2004h` to have pastes wrapped in `ESC[200~` and `ESC[201~`
+    pub bracketed_paste: bool,
+    /// Set with `ESC[?1h` (DECCKM) to send cursor keys as `ESC O A` instead of `ESC [ A`
+    pub cursor_keys_application: bool,
+    /// Set with `ESC =` (DECKPAM) to send number pad keys as `ESC O p` and so on instead of digits
+    pub keypad_application: bool,
+    /// Set by the program with DECSCUSR, `ESC[5 q` for a blinking bar and so on
+    pub cursor_shape: CursorShape,
+    pub cursor_blink: bool,
+    /// Shape and blinking from the config, which DECSCUSR 0 and a reset go back to
+    default_cursor: (CursorShape, bool),
+    sequences: SequenceParser,
+    /// Attributes given to the characters ransid writes next
+    pub attributes: Attributes,
+    /// Targets of the OSC 8 hyperlinks written so far
+    pub links: Vec<String>,
+    /// Hyperlink given to the characters ransid writes next
+    link: u16,
+    /// Characters with combining characters after them, for cells that have more than one
+    pub clusters: Vec<String>,
+    cluster_ids: HashMap<String, u16>,
+    /// The start of a UTF-8 character at the end of the last write, held back until the rest of it arrives
+    utf8_tail: Vec<u8>,
+    /// Size of a cell in pixels, set by the frontend, which decides how many cells an image covers
+    pub cell_size: (usize, usize),
+    /// Images placed on the screen, kept while any cell in the grids or the scrollback still shows them
+    pub graphics: Vec<Option<Placement>>,
+    kitty: Kitty,
+    /// Bytes for the program to read, written to the PTY by the frontend
+    pub input: Vec<u8>,
+    /// Whether the program should read an end of file after `input`
+    pub end_of_input: bool,
+    /// Signals for the frontend to send to the program in the foreground
+    pub signals: Vec<Signal>,
+    /// OSC requests for the frontend to carry out
+    pub requests: Vec<Request>,
+    /// Colours from the config, which OSC 104, 110, 111 and 112 and a reset go back to
+    default_theme: Theme,
+    clipboard_access: ClipboardAccess,
+    /// Working directory the shell reported with OSC 7
+    pub cwd: Option<String>,
+    /// Line editing for when the platform leaves it to the terminal and the program has not asked for raw mode
+    pub line: LineDiscipline,
+}
+
+impl State {
+    /// A screen of `w` by `h` cells
+    pub fn new(w: usize, h: usize, config: &Config) -> State {
+        let theme = config.theme;
+
+        let ransid = ransid::Console::new(w, h);
+        let grid = vec![Block::blank(&theme); ransid.w * ransid.h].into_boxed_slice();
+
+        State {
+            console: ransid,
+            title: "Terminal".to_string(),
+            alternate: false,
+            grid: grid.clone(),
+            alt_grid: grid,
+            theme: theme,
+            dirty: vec![false; w * h].into_boxed_slice(),
+            scrolled: 0,
+            scrollback: VecDeque::new(),
+            scrollback_max: config.scrollback,
+            scroll_offset: 0,
+            ctrl: false,
+            shift: false,
+            alt: false,
+            bracketed_paste: false,
+            cursor_keys_application: false,
+            keypad_application: false,
+            cursor_shape: config.cursor_shape,
+            cursor_blink: config.cursor_blink,
+            default_cursor: (config.cursor_shape, config.cursor_blink),
+            sequences: SequenceParser::new(),
+            attributes: Attributes::new(),
+            links: Vec::new(),
+            link: 0,
+            clusters: Vec::new(),
+            cluster_ids: HashMap::new(),
+            utf8_tail: Vec::new(),
+            cell_size: (8, 16),
+            graphics: Vec::new(),
+            kitty: Kitty::new(),
+            input: Vec::new(),
+            end_of_input: false,
+            signals: Vec::new(),
+            requests: Vec::new(),
+            default_theme: theme,
+            clipboard_access: config.clipboard_access,
+            cwd: None,
+            line: LineDiscipline::new(),
+        }
+    }
+
+    /// Fit the grids to `w` by `h` cells, keeping what fits of their contents
+    pub fn resize(&mut self, w: usize, h: usize) {
+        self.scroll_offset = 0;
+
+        let mut grid = vec![Block::blank(&self.theme); w * h].into_boxed_slice();
+        let mut alt_grid = grid.clone();
+
+        for y in 0..cmp::min(h, self.console.h) {
+            for x in 0..cmp::min(w, self.console.w) {
+                grid[y * w + x] = self.grid[y * self.console.w + x];
+                alt_grid[y * w + x] = self.alt_grid[y * self.console.w + x];
+            }
+        }
+
+        self.console.w = w;
+        self.console.h = h;
+        self.grid = grid;
+        self.alt_grid = alt_grid;
+
+        self.dirty = vec![true; w * h].into_boxed_slice();
+        self.scrolled = 0;
+    }
+
+    /// Number of rows in the scrollback and the grid together
+    pub fn history_len(&self) -> usize {
+        self.scrollback.len() + self.console.h
+    }
+
+    /// Row `i` of the scrollback followed by the grid, counting from the oldest row kept
+    pub fn history_row(&self, i: usize) -> &[Block] {
+        let w = self.console.w;
+        if i < self.scrollback.len() {
+            &self.scrollback[i]
+        } else {
+            let grid_y = i - self.scrollback.len();
+            &self.grid[grid_y * w..(grid_y + 1) * w]
+        }
+    }
+
+    /// Row `y` of the view, from the scrollback or the grid
+    pub fn view_row(&self, y: usize) -> &[Block] {
+        self.history_row(self.scrollback.len() - self.scroll_offset + y)
+    }
+
+    /// Send typed or pasted bytes to the program, through the line editor when not in raw mode
+    pub fn send(&mut self, buf: &[u8]) {
+        if self.console.raw_mode {
+            self.input.extend_from_slice(buf);
+        } else {
+            for action in self.line.input(buf) {
+                match action {
+                    Action::Echo(data) => self.write(&data),
+                    Action::Send(data) => self.input.extend_from_slice(&data),
+                    Action::Eof => self.end_of_input = true,
+                    Action::Signal(signal) => self.signals.push(signal)
+                }
+            }
+        }
+    }
+
+    /// Bytes xterm sends for a key press, given the modifiers held and the cursor key and keypad modes
+    pub fn encode_key(&self, scancode: u8, character: char) -> Vec<u8> {
+        // xterm modifier parameter: 1, plus 1 for shift, 2 for alt and 4 for ctrl
+        let modifier = 1 + if self.shift { 1 } else { 0 } + if self.alt { 2 } else { 0 } + if self.ctrl { 4 } else { 0 };
+
+        let cursor = |c: char| -> Vec<u8> {
+            if modifier > 1 {
+                format!("\x1B[1;{}{}", modifier, c).into_bytes()
+            } else if self.cursor_keys_application {
+                format!("\x1BO{}", c).into_bytes()
+            } else {
+                format!("\x1B[{}", c).into_bytes()
+            }
+        };
+
+        let function = |c: char| -> Vec<u8> {
+            if modifier > 1 {
+                format!("\x1B[1;{}{}", modifier, c).into_bytes()
+            } else {
+                format!("\x1BO{}", c).into_bytes()
+            }
+        };
+
+        let tilde = |n: u8| -> Vec<u8> {
+            if modifier > 1 {
+                format!("\x1B[{};{}~", n, modifier).into_bytes()
+            } else {
+                format!("\x1B[{}~", n).into_bytes()
+            }
+        };
+
+        // The number pad shares scancodes with the cursor keys, but has a character when num lock is on
+        let keypad = match scancode {
+            0x47 ... 0x53 => match character {
+                '0' ... '9' | '.' | '-' | '+' => Some(character),
+                _ => None
+            },
+            0x37 => Some('*'),
+            _ => None
+        };
+
+        if let Some(c) = keypad {
+            if self.keypad_application {
+                let code = match c {
+                    '0' ... '9' => (b'p' + (c as u8 - b'0')) as char,
+                    '.' => 'n',
+                    '-' => 'm',
+                    '+' => 'k',
+                    _ => 'j'
+                };
+                return format!("\x1BO{}", code).into_bytes();
+            }
+        } else {
+            match scancode {
+                0x0E => { // Backspace
+                    let c = if self.ctrl { b'\x08' } else { b'\x7F' };
+                    return if self.alt { vec![b'\x1B', c] } else { vec![c] };
+                },
+                0x0F if self.shift => { // Shift+Tab
+                    return b"\x1B[Z".to_vec();
+                },
+                0x3B ... 0x3E => { // F1 to F4
+                    return function((b'P' + (scancode - 0x3B)) as char);
+                },
+                0x3F => return tilde(15), // F5
+                0x40 => return tilde(17), // F6
+                0x41 => return tilde(18), // F7
+                0x42 => return tilde(19), // F8
+                0x43 => return tilde(20), // F9
+                0x44 => return tilde(21), // F10
+                0x57 => return tilde(23), // F11
+                0x58 => return tilde(24), // F12
+                0x47 => return cursor('H'), // Home
+                0x48 => return cursor('A'), // Up
+                0x49 => return tilde(5), // Page up
+                0x4B => return cursor('D'), // Left
+                0x4D => return cursor('C'), // Right
+                0x4F => return cursor('F'), // End
+                0x50 => return cursor('B'), // Down
+                0x51 => return tilde(6), // Page down
+                0x52 => return tilde(2), // Insert
+                0x53 => return tilde(3), // Delete
+                _ => ()
+            }
+        }
+
+        if character == '\0' && ! (self.ctrl && scancode == 0x39) {
+            return Vec::new();
+        }
+
+        let c = if self.ctrl {
+            match character {
+                c @ 'A' ... 'Z' => ((c as u8 - b'A') + b'\x01') as char,
+                c @ 'a' ... 'z' => ((c as u8 - b'a') + b'\x01') as char,
+                '\0' | ' ' | '@' | '2' => '\x00',
+                '[' | '3' => '\x1B',
+                '\\' | '4' => '\x1C',
+                ']' | '5' => '\x1D',
+                '^' | '6' => '\x1E',
+                '_' | '/' | '-' | '7' => '\x1F',
+                '?' | '8' => '\x7F',
+                c => c
+            }
+        } else {
+            character
+        };
+
+        // Alt sends the key prefixed with escape, like xterm's metaSendsEscape
+        let mut buf = Vec::new();
+        if self.alt {
+            buf.push(b'\x1B');
+        }
+        let mut str_buf = [0; 4];
+        buf.extend_from_slice(c.encode_utf8(&mut str_buf).as_bytes());
+        buf
+    }
+
+    /// Mark the cells under the cursor to be drawn again, without the cursor over them
+    pub fn touch_cursor(&mut self) {
+        if self.console.x < self.console.w && self.console.y < self.console.h {
+            let i = self.console.y * self.console.w + self.console.x;
+            self.dirty[i] = true;
+            if self.grid[i].width == 2 && self.console.x + 1 < self.console.w {
+                self.dirty[i + 1] = true;
+            }
+        }
+    }
+
+    /// Take in output from the program, updating the grids and marking the cells it changed as dirty
+    pub fn write(&mut self, buf: &[u8]) {
+        // The cursor is drawn over its cell, which has to be drawn again without it once the cursor moves
+        self.touch_cursor();
+
+        let scrollback_len = self.scrollback.len();
+
+        // A character split between writes is held back whole, so its width is known before ransid sees any of it
+        let mut data = mem::replace(&mut self.utf8_tail, Vec::new());
+        data.extend_from_slice(buf);
+        let keep = incomplete_utf8(&data);
+        self.utf8_tail = data.split_off(data.len() - keep);
+        let buf = &data[..];
+
+        // ransid is fed up to the end of each SGR or OSC sequence at a time, so the attributes of every character are known
+        let mut start = 0;
+        while start < buf.len() {
+            // ransid only sees the introducer of a DCS or APC string, it would write the rest as text
+            while start < buf.len() && self.sequences.hidden() {
+                if let Some(sequence) = self.sequences.advance(buf[start]) {
+                    self.sequence(sequence);
+                }
+                start += 1;
+            }
+
+            let mut end = buf.len();
+            let mut sequence = None;
+            let mut special = None;
+            for i in start..buf.len() {
+                // Text that is not one cell wide per character is written apart from the rest
+                if buf[i] >= 0xC0 && self.sequences.ground() {
+                    if let Some((c, len)) = decode_utf8(&buf[i..]) {
+                        if wcwidth::width(c) != 1 {
+                            end = i;
+                            special = Some((c, i + len));
+                            break;
+                        }
+                    }
+                }
+                if let Some(found) = self.sequences.advance(buf[i]) {
+                    end = i + 1;
+                    sequence = Some(found);
+                    break;
+                }
+                if self.sequences.hidden() {
+                    end = i + 1;
+                    break;
+                }
+            }
+
+            self.write_chunk(&buf[start..end]);
+
+            if let Some(sequence) = sequence {
+                self.sequence(sequence);
+            }
+
+            start = match special {
+                Some((c, next)) => {
+                    if wcwidth::width(c) == 0 {
+                        self.combine(c);
+                    } else {
+                        self.write_wide(&buf[end..next]);
+                    }
+                    next
+                },
+                None => end
+            };
+        }
+
+        if self.scroll_offset > 0 {
+            // Keep the same rows in view while new output pushes older ones into the scrollback
+            let added = self.scrollback.len().saturating_sub(scrollback_len);
+            self.scroll_offset = cmp::min(self.scroll_offset + added, self.scrollback.len());
+        }
+    }
+
+    /// Write a character two cells wide, starting a new row if only one cell is left in this one
+    fn write_wide(&mut self, bytes: &[u8]) {
+        let w = self.console.w;
+        if w >= 2 && self.console.x + 1 == w {
+            self.write_chunk(b" ");
+        }
+        self.write_chunk(bytes);
+
+        // ransid moved the cursor one cell, the second half goes in the next one
+        let x = self.console.x;
+        if x == 0 || x >= w || self.console.y >= self.console.h {
+            return;
+        }
+        let i = self.console.y * w + x;
+        split_wide(&mut self.grid, &mut self.dirty, w, i);
+        self.grid[i - 1].width = 2;
+        let mut block = self.grid[i - 1];
+        block.width = 0;
+        self.grid[i] = block;
+        self.dirty[i] = true;
+        self.console.x += 1;
+    }
+
+    /// Add a zero width character to the cell before the cursor, so it is drawn over the character there
+    fn combine(&mut self, c: char) {
+        let w = self.console.w;
+        let x = cmp::min(self.console.x, w);
+        if x == 0 || self.console.y >= self.console.h {
+            return;
+        }
+        let mut i = self.console.y * w + x - 1;
+        if self.grid[i].width == 0 && x > 1 {
+            i -= 1;
+        }
+        if self.grid[i].c == '\0' {
+            return;
+        }
+
+        let mut text = match self.clusters.get((self.grid[i].cluster as usize).wrapping_sub(1)) {
+            Some(cluster) => cluster.clone(),
+            None => self.grid[i].c.to_string()
+        };
+        text.push(c);
+        let cluster = self.cluster_id(text);
+
+        self.grid[i].cluster = cluster;
+        self.dirty[i] = true;
+        if self.grid[i].width == 2 && i % w + 1 < w {
+            self.grid[i + 1].cluster = cluster;
+            self.dirty[i + 1] = true;
+        }
+    }
+
+    fn cluster_id(&mut self, text: String) -> u16 {
+        if let Some(&id) = self.cluster_ids.get(&text) {
+            return id;
+        }
+        if self.clusters.len() >= u16::max_value() as usize {
+            let len = self.clusters.len();
+            let ids = self.renumber(len, |block| block.cluster, |block, id| block.cluster = id);
+            let clusters = mem::replace(&mut self.clusters, Vec::new());
+            self.clusters = clusters.into_iter().zip(ids.iter()).filter(|&(_, &id)| id > 0).map(|(cluster, _)| cluster).collect();
+            self.cluster_ids = self.clusters.iter().enumerate().map(|(i, cluster)| (cluster.clone(), i as u16 + 1)).collect();
+        }
+        // Once the table is full of clusters still on the screen, further combining characters are left out
+        if self.clusters.len() >= u16::max_value() as usize {
+            return 0;
+        }
+        self.clusters.push(text.clone());
+        let id = self.clusters.len() as u16;
+        self.cluster_ids.insert(text, id);
+        id
+    }
+
+    /// Text of a row of cells, with blanks as spaces, wide characters once and combining characters after theirs
+    pub fn text(&self, row: &[Block]) -> String {
+        let mut text = String::new();
+        for block in row.iter() {
+            match (block.width, self.clusters.get((block.cluster as usize).wrapping_sub(1))) {
+                (0, _) => (),
+                (_, Some(cluster)) => text.push_str(cluster),
+                _ => text.push(if block.c == '\0' { ' ' } else { block.c })
+            }
+        }
+        text
+    }
+
+    fn sequence(&mut self, sequence: Sequence) {
+        match sequence {
+            Sequence::Sgr(params) => self.attributes.apply(&params),
+            Sequence::CursorStyle(style) => self.cursor_style(style),
+            Sequence::Osc(data) => self.osc(&data),
+            Sequence::Dcs(data) => self.dcs(&data),
+            Sequence::Apc(data) => self.apc(&data),
+            Sequence::Mode(modes, set) => for mode in modes {
+                // SRM, send/receive, where set turns off local echo
+                if mode == 12 {
+                    self.line.flags.echo = ! set;
+                }
+            },
+            Sequence::PrivateMode(modes, set) => for mode in modes {
+                match mode {
+                    1 => self.cursor_keys_application = set,
+                    2004 => self.bracketed_paste = set,
+                    _ => ()
+                }
+            },
+            Sequence::Keypad(application) => self.keypad_application = application,
+            Sequence::Reset => {
+                self.attributes.apply(&[vec![0]]);
+                self.bracketed_paste = false;
+                self.cursor_keys_application = false;
+                self.keypad_application = false;
+                self.line.flags.echo = true;
+                self.cursor_shape = self.default_cursor.0;
+                self.cursor_blink = self.default_cursor.1;
+                self.theme = self.default_theme;
+            }
+        }
+    }
+
+    /// Pass output to ransid, with the current attributes given to the characters it writes
+    fn write_chunk(&mut self, buf: &[u8]) {
+        let theme = self.theme;
+        let console_w = self.console.w;
+        let console_h = self.console.h;
+        let alt = &mut self.alternate;
+        let grid = &mut self.grid;
+        let alt_grid = &mut self.alt_grid;
+        let dirty = &mut self.dirty;
+        let scrolled = &mut self.scrolled;
+        let title = &mut self.title;
+        let input = &mut self.input;
+        let scrollback = &mut self.scrollback;
+        let scrollback_max = self.scrollback_max;
+        let attributes = self.attributes;
+        let link = self.link;
+
+        let (fg, bg) = attributes.colors(&theme);
+        let bold = attributes.bold;
+        let attrs = attributes.attrs;
+
+        self.console.write(buf, |event| {
+            match event {
+                ransid::Event::Char { x, y, c, .. } => {
+                    let block = Block {
+                        c: c, fg: fg, bg: bg, bold: bold, attrs: attrs, link: link, width: 1, cluster: 0, image: 0, tile: (0, 0)
+                    };
+                    split_wide(grid, dirty, console_w, y * console_w + x);
+                    grid[y * console_w + x] = block;
+                    dirty[y * console_w + x] = true;
+                },
+                ransid::Event::Input { data } => {
+                    input.extend(data);
+                },
+                ransid::Event::Rect { x, y, w, h, .. } => {
+                    for y2 in y..y + h {
+                        // A wide character cut in two by the edges goes as a whole
+                        if w > 0 {
+                            split_wide(grid, dirty, console_w, y2 * console_w + x);
+                            split_wide(grid, dirty, console_w, y2 * console_w + x + w - 1);
+                        }
+                        for x2 in x..x + w {
+                            grid[y2 * console_w + x2].erase(bg);
+                            dirty[y2 * console_w + x2] = true;
+                        }
+                    }
+                },
+                ransid::Event::ScreenBuffer { alternate, clear } => {
+                    if *alt != alternate {
+                        mem::swap(grid, alt_grid);
+
+                        if clear {
+                            for block in grid.iter_mut() {
+                                block.erase(bg);
+                            }
+                        }
+
+                        for cell in dirty.iter_mut() {
+                            *cell = true;
+                        }
+                    }
+                    *alt = alternate;
+                },
+                ransid::Event::Scroll { rows, .. } => {
+                    if ! *alt && scrollback_max > 0 {
+                        for y in 0..cmp::min(rows, console_h) {
+                            scrollback.push_back(grid[y * console_w..(y + 1) * console_w].to_vec().into_boxed_slice());
+                        }
+                        while scrollback.len() > scrollback_max {
+                            scrollback.pop_front();
+                        }
+                    }
+
+                    // The window contents are moved once by `redraw`, however many times this happens before it
+                    let rows = cmp::min(rows, console_h);
+                    *scrolled += rows;
+
+                    for y in rows..console_h {
+                        for x in 0..console_w {
+                            grid[(y - rows) * console_w + x] = grid[y * console_w + x];
+                            dirty[(y - rows) * console_w + x] = dirty[y * console_w + x];
+                        }
+                    }
+
+                    for y in console_h - rows..console_h {
+                        for x in 0..console_w {
+                            grid[y * console_w + x].erase(bg);
+                            dirty[y * console_w + x] = true;
+                        }
+                    }
+                },
+                ransid::Event::Title { title: new_title } => {
+                    *title = new_title.to_string();
+                }
+            }
+        });
+    }
+
+    /// Apply DECSCUSR: 1 and 2 for a block, 3 and 4 for an underline, 5 and 6 for a bar, odd ones blinking
+    fn cursor_style(&mut self, style: u32) {
+        let (shape, blink) = match style {
+            0 => self.default_cursor,
+            1 | 2 => (CursorShape::Block, style == 1),
+            3 | 4 => (CursorShape::Underline, style == 3),
+            5 | 6 => (CursorShape::Bar, style == 5),
+            _ => return
+        };
+        self.cursor_shape = shape;
+        self.cursor_blink = blink;
+    }
+
+    /// Handle an OSC sequence that ransid leaves alone
+    fn osc(&mut self, data: &[u8]) {
+        let mut parts = data.splitn(2, |&b| b == b';');
+        let code = parts.next().unwrap_or(&[]);
+        let rest = parts.next().unwrap_or(&[]);
+        match code {
+            b"4" => {
+                // OSC 4 ; index ; colour, for as many pairs as given, where a colour of `?` asks for it instead
+                let mut parts = rest.split(|&b| b == b';');
+                while let (Some(index), Some(spec)) = (parts.next(), parts.next()) {
+                    let index = match str::from_utf8(index).ok().and_then(|index| index.parse::<u8>().ok()) {
+                        Some(index) => index,
+                        None => continue
+                    };
+                    if spec == b"?" {
+                        let color = theme::format_xcolor(indexed(&self.theme, index));
+                        self.reply(&format!("4;{};{}", index, color));
+                    } else if index < 16 {
+                        if let Some(color) = str::from_utf8(spec).ok().and_then(theme::parse_xcolor) {
+                            let old = self.theme.palette[index as usize];
+                            self.theme.palette[index as usize] = color;
+                            self.recolor(old, color);
+                        }
+                    }
+                }
+            },
+            b"7" => {
+                // OSC 7 ; file://host/path, sent by the shell when it changes directory
+                let url = String::from_utf8_lossy(rest);
+                if url.starts_with("file://") {
+                    self.cwd = Some(links::file_path(&url));
+                }
+            },
+            b"8" => {
+                // OSC 8 ; params ; URI starts a hyperlink, an empty URI ends it
+                let uri = rest.splitn(2, |&b| b == b';').nth(1).unwrap_or(&[]);
+                self.link = if uri.is_empty() {
+                    0
+                } else {
+                    self.link_id(String::from_utf8_lossy(uri).into_owned())
+                };
+            },
+            b"9" => {
+                // OSC 9 ; message, where ConEmu's OSC 9 ; 4 ; ... progress reports are no message
+                if ! rest.starts_with(b"4;") {
+                    self.requests.push(Request::Notify(self.title.clone(), String::from_utf8_lossy(rest).into_owned()));
+                }
+            },
+            b"10" | b"11" | b"12" => {
+                // Each colour after the first goes to the next code, so OSC 10 ; fg ; bg sets both
+                let first = if code == b"10" { 0 } else if code == b"11" { 1 } else { 2 };
+                for (i, spec) in rest.split(|&b| b == b';').enumerate().take(3 - first) {
+                    let which = first + i;
+                    let old = match which {
+                        0 => self.theme.foreground,
+                        1 => self.theme.background,
+                        _ => self.theme.cursor
+                    };
+                    if spec == b"?" {
+                        self.reply(&format!("{};{}", 10 + which, theme::format_xcolor(old)));
+                    } else if let Some(color) = str::from_utf8(spec).ok().and_then(theme::parse_xcolor) {
+                        match which {
+                            0 => self.theme.foreground = color,
+                            1 => self.theme.background = color,
+                            _ => self.theme.cursor = color
+                        }
+                        if which < 2 {
+                            self.recolor(old, color);
+                        } else {
+                            self.touch_cursor();
+                        }
+                    }
+                }
+            },
+            b"52" => {
+                // OSC 52 ; selections ; base64 text, or `?` to ask for the clipboard
+                let mut parts = rest.splitn(2, |&b| b == b';');
+                let selections = String::from_utf8_lossy(parts.next().unwrap_or(&[])).into_owned();
+                let data = parts.next().unwrap_or(&[]);
+                if data == b"?" {
+                    if self.clipboard_access == ClipboardAccess::ReadWrite {
+                        self.requests.push(Request::ReadClipboard(if selections.is_empty() { "c".to_string() } else { selections }));
+                    }
+                } else if self.clipboard_access != ClipboardAccess::None {
+                    let text = String::from_utf8_lossy(&kitty::base64(data)).into_owned();
+                    self.requests.push(Request::SetClipboard(text));
+                }
+            },
+            b"104" => {
+                // OSC 104 ; index ; ... puts back the given colours of the palette, or all of them
+                let default_theme = self.default_theme;
+                let indices: Vec<usize> = if rest.is_empty() {
+                    (0..16).collect()
+                } else {
+                    rest.split(|&b| b == b';')
+                        .filter_map(|index| str::from_utf8(index).ok().and_then(|index| index.parse::<usize>().ok()))
+                        .filter(|&index| index < 16)
+                        .collect()
+                };
+                for index in indices {
+                    let old = self.theme.palette[index];
+                    self.theme.palette[index] = default_theme.palette[index];
+                    self.recolor(old, default_theme.palette[index]);
+                }
+            },
+            b"110" => {
+                let (old, color) = (self.theme.foreground, self.default_theme.foreground);
+                self.theme.foreground = color;
+                self.recolor(old, color);
+            },
+            b"111" => {
+                let (old, color) = (self.theme.background, self.default_theme.background);
+                self.theme.background = color;
+                self.recolor(old, color);
+            },
+            b"112" => {
+                self.theme.cursor = self.default_theme.cursor;
+                self.touch_cursor();
+            },
+            b"777" => {
+                // OSC 777 ; notify ; title ; body, from urxvt
+                let mut parts = rest.splitn(3, |&b| b == b';');
+                if parts.next() == Some(&b"notify"[..]) {
+                    let title = String::from_utf8_lossy(parts.next().unwrap_or(&[])).into_owned();
+                    let body = String::from_utf8_lossy(parts.next().unwrap_or(&[])).into_owned();
+                    self.requests.push(Request::Notify(title, body));
+                }
+            },
+            _ => ()
+        }
+    }
+
+    /// Answer an OSC query, ended with ST
+    fn reply(&mut self, text: &str) {
+        self.input.extend_from_slice(b"\x1B]");
+        self.input.extend_from_slice(text.as_bytes());
+        self.input.extend_from_slice(b"\x1B\\");
+    }
+
+    /// Give the cells drawn in a colour that was just changed the new one, as they would have if colours were kept as
+    /// indices, and have everything drawn again
+    fn recolor(&mut self, old: u32, new: u32) {
+        if old == new {
+            return;
+        }
+        let rows = self.grid.iter_mut().chain(self.alt_grid.iter_mut())
+            .chain(self.scrollback.iter_mut().flat_map(|row| row.iter_mut()));
+        for block in rows {
+            if block.fg == old {
+                block.fg = new;
+            }
+            if block.bg == old {
+                block.bg = new;
+            }
+        }
+        for cell in self.dirty.iter_mut() {
+            *cell = true;
+        }
+    }
+
+    fn link_id(&mut self, uri: String) -> u16 {
+        if let Some(i) = self.links.iter().rposition(|link| *link == uri) {
+            return i as u16 + 1;
+        }
+        if self.links.len() >= u16::max_value() as usize {
+            let len = self.links.len();
+            let ids = self.renumber(len, |block| block.link, |block, id| block.link = id);
+            let links = mem::replace(&mut self.links, Vec::new());
+            self.links = links.into_iter().zip(ids.iter()).filter(|&(_, &id)| id > 0).map(|(link, _)| link).collect();
+        }
+        // Once the table is full of links still on the screen, further links are shown as plain text
+        if self.links.len() >= u16::max_value() as usize {
+            return 0;
+        }
+        self.links.push(uri);
+        self.links.len() as u16
+    }
+
+    /// Renumber a table that cells refer to by index plus one, leaving out the entries no cell in the grids or the
+    /// scrollback refers to any more, and return the new id of each entry, or zero for those left out
+    fn renumber<G, S>(&mut self, len: usize, get: G, set: S) -> Vec<u16> where G: Fn(&Block) -> u16, S: Fn(&mut Block, u16) {
+        let mut ids = vec![0; len];
+        {
+            let scrollback = self.scrollback.iter().flat_map(|row| row.iter());
+            for block in self.grid.iter().chain(self.alt_grid.iter()).chain(scrollback) {
+                let id = get(block) as usize;
+                if id > 0 && id <= len {
+                    ids[id - 1] = 1;
+                }
+            }
+        }
+        let mut next = 0;
+        for id in ids.iter_mut() {
+            if *id > 0 {
+                next += 1;
+                *id = next;
+            }
+        }
+
+        {
+            let scrollback = self.scrollback.iter_mut().flat_map(|row| row.iter_mut());
+            for block in self.grid.iter_mut().chain(self.alt_grid.iter_mut()).chain(scrollback) {
+                let id = get(block) as usize;
+                if id > 0 {
+                    set(block, ids.get(id - 1).cloned().unwrap_or(0));
+                }
+            }
+        }
+        for cell in self.dirty.iter_mut() {
+            *cell = true;
+        }
+        ids
+    }
+
+    /// Handle a DCS string, of which only sixel images are understood
+    fn dcs(&mut self, data: &[u8]) {
+        // Numeric parameters, then `q` and the sixel data
+        let start = data.iter().position(|&b| !(b >= b'0' && b <= b'9' || b == b';'));
+        if let Some(i) = start {
+            if data[i] == b'q' {
+                if let Some(graphic) = sixel::decode(&data[i + 1..]) {
+                    self.place(Rc::new(graphic), 0, None, None, After::NextLine);
+                }
+            }
+        }
+    }
+
+    /// Handle an APC string, of which only kitty graphics commands are understood
+    fn apc(&mut self, data: &[u8]) {
+        if data.first() != Some(&b'G') {
+            return;
+        }
+        for action in self.kitty.command(data) {
+            match action {
+                kitty::Action::Place { graphic, id, cols, rows, after } => self.place(graphic, id, cols, rows, after),
+                kitty::Action::Delete(id) => self.delete_graphics(id),
+                kitty::Action::Reply(data) => self.input.extend_from_slice(&data)
+            }
+        }
+    }
+
+    /// Show an image from the cursor onwards, scaled to `cols` by `rows` cells when given, scrolling if it goes past the bottom
+    fn place(&mut self, graphic: Rc<Graphic>, kitty_id: u32, cols: Option<usize>, rows: Option<usize>, after: After) {
+        let (cell_w, cell_h) = (cmp::max(1, self.cell_size.0), cmp::max(1, self.cell_size.1));
+        // The program can ask for any number of cells, which is kept to the largest image there can be
+        let cols = cols.map(|cols| cmp::min(cols, MAX_SIZE / cell_w));
+        let rows = rows.map(|rows| cmp::min(rows, MAX_SIZE / cell_h));
+        // With only one of the sizes given, the other keeps the image's shape
+        let (width, height) = match (cols, rows) {
+            (Some(cols), Some(rows)) => (cols * cell_w, rows * cell_h),
+            (Some(cols), None) => (cols * cell_w, graphic.height.saturating_mul(cols * cell_w) / cmp::max(1, graphic.width)),
+            (None, Some(rows)) => (graphic.width.saturating_mul(rows * cell_h) / cmp::max(1, graphic.height), rows * cell_h),
+            (None, None) => (graphic.width, graphic.height)
+        };
+        let (width, height) = (cmp::min(width, MAX_SIZE), cmp::min(height, MAX_SIZE));
+        if width == 0 || height == 0 || self.console.w == 0 || self.console.h == 0 {
+            return;
+        }
+        let graphic = if (width, height) == (graphic.width, graphic.height) {
+            graphic
+        } else {
+            Rc::new(graphic.scale(width, height))
+        };
+
+        let image = match self.placement_id(Placement { graphic: graphic.clone(), kitty_id: kitty_id }) {
+            Some(image) => image,
+            None => return
+        };
+
+        let (cursor_x, cursor_y) = (self.console.x, self.console.y);
+        let x = cmp::min(cursor_x, self.console.w - 1);
+        let cols = cells(graphic.width, cell_w);
+        let rows = cells(graphic.height, cell_h);
+        // Rows the screen scrolled up while the image was written, which take the row it started on up with them
+        let mut scrolled = 0;
+        for row in 0..rows {
+            if row > 0 {
+                let y = self.console.y;
+                self.write_chunk(b"\n");
+                if self.console.y == y {
+                    scrolled += 1;
+                }
+            }
+            self.console.x = x;
+
+            let w = self.console.w;
+            let y = self.console.y;
+            for col in 0..cmp::min(cols, w - x) {
+                let block = &mut self.grid[y * w + x + col];
+                block.image = image;
+                block.tile = (col as u16, row as u16);
+                self.dirty[y * w + x + col] = true;
+            }
+        }
+
+        match after {
+            After::NextLine => {
+                self.write_chunk(b"\n");
+                self.console.x = 0;
+            },
+            After::AfterImage => self.console.x = cmp::min(x + cols, self.console.w - 1),
+            After::Stay => {
+                // Back to the first row of the image, or the top of the screen once that has scrolled off
+                self.console.x = cursor_x;
+                self.console.y = cursor_y.saturating_sub(scrolled);
+            }
+        }
+    }
+
+    /// A free slot in `graphics` for the placement, once those no cell shows any more are dropped
+    fn placement_id(&mut self, placement: Placement) -> Option<u16> {
+        let mut used = vec![false; self.graphics.len()];
+        {
+            let scrollback = self.scrollback.iter().flat_map(|row| row.iter());
+            for block in self.grid.iter().chain(self.alt_grid.iter()).chain(scrollback) {
+                if block.image > 0 {
+                    used[block.image as usize - 1] = true;
+                }
+            }
+        }
+        for (slot, &used) in self.graphics.iter_mut().zip(used.iter()) {
+            if ! used {
+                *slot = None;
+            }
+        }
+
+        if let Some(i) = self.graphics.iter().position(|slot| slot.is_none()) {
+            self.graphics[i] = Some(placement);
+            return Some(i as u16 + 1);
+        }
+        if self.graphics.len() >= u16::max_value() as usize {
+            return None;
+        }
+        self.graphics.push(Some(placement));
+        Some(self.graphics.len() as u16)
+    }
+
+    /// Take images off the screen, those placed from the kitty image `id` or all of them
+    fn delete_graphics(&mut self, id: Option<u32>) {
+        let graphics = &self.graphics;
+        for (block, dirty) in self.grid.iter_mut().zip(self.dirty.iter_mut()) {
+            if block.image == 0 {
+                continue;
+            }
+            let matches = match (id, &graphics[block.image as usize - 1]) {
+                (Some(id), &Some(ref placement)) => placement.kitty_id == id,
+                _ => true
+            };
+            if matches {
+                block.image = 0;
+                *dirty = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,931 @@
+use orbclient::{Color, EventOption, MouseEvent, Renderer, Window, WindowFlag};
+use orbimage::Image;
+use std::{cmp, env, mem};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use asciicast::Recorder;
+use config::Config;
+use console::{Console, Fonts};
+use kitty;
+use links;
+use pty::{Output, Pty};
+use state::Request;
+
+/// Height of the tab bar, shown once there is more than one tab
+const TAB_BAR_HEIGHT: u32 = 20;
+/// Widest a tab gets in the tab bar
+const TAB_WIDTH: u32 = 200;
+/// Gap left between split panes, filled with the divider colour
+const DIVIDER: u32 = 2;
+
+/// The `TERM` set in the config, otherwise our own terminfo entry when it is installed, otherwise the xterm entry it
+/// builds on
+pub fn term(config: &Config) -> String {
+    if let Some(ref term) = config.term {
+        return term.clone();
+    }
+
+    let mut dirs: Vec<PathBuf> = vec![PathBuf::from("/usr/share/terminfo"), PathBuf::from("/usr/lib/terminfo"), PathBuf::from("/etc/terminfo")];
+    if let Ok(home) = env::var("HOME") {
+        dirs.push(Path::new(&home).join(".terminfo"));
+    }
+    if let Ok(terminfo) = env::var("TERMINFO") {
+        dirs.push(PathBuf::from(terminfo));
+    }
+
+    if dirs.iter().any(|dir| dir.join("o").join("orbterm").exists() || dir.join("6f").join("orbterm").exists()) {
+        "orbterm".to_string()
+    } else {
+        "xterm-256color".to_string()
+    }
+}
+
+/// Show a desktop notification with the command from the config, or in our output when there is none or it fails
+fn notify(config: &Config, title: &str, body: &str) {
+    if let Some(ref command) = config.notify_command {
+        match Command::new(command).arg(title).arg(body).spawn() {
+            Ok(_) => return,
+            Err(err) => println!("terminal: failed to run {}: {}", command, err)
+        }
+    }
+    println!("terminal: {}: {}", title, body);
+}
+
+/// Leave line editing to the kernel where it does it, otherwise the console does it until a program asks for raw mode
+#[cfg(not(target_os = "redox"))]
+fn prepare(console: &mut Console) {
+    console.state.console.raw_mode = true;
+}
+
+#[cfg(target_os = "redox")]
+fn prepare(_console: &mut Console) {
+}
+
+/// One shell, with its own console, PTY and grids
+pub struct Pane {
+    pub console: Console,
+    pub pty: Pty,
+    /// Where the shell's output is recorded with `--record`
+    pub recorder: Option<Recorder>,
+}
+
+impl Pane {
+    /// Start `command` on a new PTY, in a console of `width` by `height` pixels, in `dir` if given and otherwise the
+    /// directory from the config
+    pub fn spawn(config: &Config, command: &[String], dir: Option<&Path>, width: u32, height: u32) -> Result<Pane, String> {
+        let fonts = Fonts::new(config)?;
+        let mut console = Console::new(width, height, fonts, config);
+        prepare(&mut console);
+
+        let (w, h) = (console.state.console.w, console.state.console.h);
+        let mut env = vec![
+            ("COLUMNS", format!("{}", w)),
+            ("LINES", format!("{}", h)),
+            ("TERM", term(config)),
+            ("COLORTERM", "truecolor".to_string()),
+        ];
+        // Variables from the config come last, so they can replace any of ours
+        env.extend(config.env.iter().map(|&(ref name, ref value)| (name.as_str(), value.clone())));
+        let dir = dir.or(config.working_directory.as_ref().map(|dir| Path::new(dir)));
+        let pty = Pty::spawn(command, dir, &env, w, h)?;
+
+        Ok(Pane {
+            console: console,
+            pty: pty,
+            recorder: None,
+        })
+    }
+
+    /// Fit the console and the PTY to a new size in pixels
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if self.console.image.width() != width || self.console.image.height() != height {
+            self.console.resize(width, height);
+            let (w, h) = (self.console.state.console.w, self.console.state.console.h);
+            if let Err(err) = self.pty.resize(w, h) {
+                println!("terminal: {}", err);
+            }
+            if let Some(ref mut recorder) = self.recorder {
+                recorder.resize(w, h);
+            }
+        }
+    }
+
+    fn output(&mut self, data: &[u8]) {
+        self.console.write(data, false).expect("terminal: failed to write to console");
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.output(data);
+        }
+    }
+
+    /// Take in everything the shell has written, up to a frame's worth of time, returning false once it is gone
+    #[cfg(not(target_os = "redox"))]
+    pub fn read(&mut self, _event_count: usize) -> bool {
+        use std::time::Instant;
+
+        let start = Instant::now();
+        let mut packet = [0; 4096];
+        loop {
+            match self.pty.read(&mut packet) {
+                Ok(Output::Data(data)) => {
+                    self.output(data);
+                    if start.elapsed() >= Duration::from_millis(16) {
+                        return true;
+                    }
+                },
+                Ok(Output::Empty) => return true,
+                Ok(Output::Closed) => return false,
+                Err(err) => {
+                    println!("terminal: {}", err);
+                    return false;
+                }
+            }
+        }
+    }
+
+    #[cfg(target_os = "redox")]
+    pub fn read(&mut self, event_count: usize) -> bool {
+        let mut packet = [0; 4096];
+        match self.pty.read(&mut packet) {
+            Ok(Output::Data(data)) => {
+                self.output(data);
+                true
+            },
+            Ok(Output::Empty) => event_count != 0,
+            Ok(Output::Closed) => false,
+            Err(err) => {
+                println!("terminal: {}", err);
+                false
+            }
+        }
+    }
+
+    /// Send typed input and signals to the shell, returning false if it can no longer be written to
+    pub fn flush(&mut self) -> bool {
+        for signal in mem::replace(&mut self.console.state.signals, Vec::new()) {
+            if let Err(err) = self.pty.signal(signal) {
+                println!("terminal: {}", err);
+            }
+        }
+
+        let mut result = Ok(());
+        if ! self.console.state.input.is_empty() {
+            // Whatever the PTY has no room for yet is kept for the next flush
+            match self.pty.write(&self.console.state.input) {
+                Ok(count) => {
+                    self.console.state.input.drain(..count);
+                },
+                Err(err) => result = Err(err)
+            }
+        }
+        if result.is_ok() && self.console.state.end_of_input && self.console.state.input.is_empty() {
+            self.console.state.end_of_input = false;
+            result = self.pty.end_of_file();
+        }
+
+        if let Err(err) = result {
+            let term_stderr = io::stderr();
+            let mut term_stderr = term_stderr.lock();
+
+            let _ = term_stderr.write(b"terminal: ");
+            let _ = term_stderr.write(err.as_bytes());
+            let _ = term_stderr.write(b"\n");
+            return false;
+        }
+        true
+    }
+
+    /// Working directory of the shell, to find the files named in its output, as it last reported it with OSC 7 or
+    /// otherwise as the system knows it
+    pub fn cwd(&self) -> Option<PathBuf> {
+        self.console.state.cwd.as_ref().map(PathBuf::from).or_else(|| self.pty.cwd())
+    }
+
+    /// Whether the shell has exited
+    pub fn exited(&mut self) -> bool {
+        match self.pty.exited() {
+            Ok(exited) => exited,
+            Err(err) => {
+                println!("terminal: {}", err);
+                true
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Split {
+    /// Side by side
+    Columns,
+    /// One above the other
+    Rows,
+}
+
+/// How the panes of a tab share its area, split in two as many times as needed
+pub enum Layout {
+    Pane(usize),
+    Split(Split, Box<Layout>, Box<Layout>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Rect {
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.w as i32 && y < self.y + self.h as i32
+    }
+}
+
+impl Layout {
+    /// Where each pane goes within `rect`
+    fn place(&self, rect: Rect, places: &mut Vec<(usize, Rect)>) {
+        match *self {
+            Layout::Pane(id) => places.push((id, rect)),
+            Layout::Split(split, ref first, ref second) => {
+                let (a, b) = match split {
+                    Split::Columns => {
+                        let w = rect.w.saturating_sub(DIVIDER) / 2;
+                        (Rect { w: w, ..rect }, Rect {
+                            x: rect.x + (w + DIVIDER) as i32, w: rect.w.saturating_sub(w + DIVIDER), ..rect
+                        })
+                    },
+                    Split::Rows => {
+                        let h = rect.h.saturating_sub(DIVIDER) / 2;
+                        (Rect { h: h, ..rect }, Rect {
+                            y: rect.y + (h + DIVIDER) as i32, h: rect.h.saturating_sub(h + DIVIDER), ..rect
+                        })
+                    }
+                };
+                first.place(a, places);
+                second.place(b, places);
+            }
+        }
+    }
+
+    /// Split the pane `id` in two, with `new_id` in the second half
+    fn split(&mut self, id: usize, new_id: usize, split: Split) -> bool {
+        let found = match *self {
+            Layout::Pane(pane) => pane == id,
+            Layout::Split(_, ref mut first, ref mut second) => return first.split(id, new_id, split) || second.split(id, new_id, split)
+        };
+        if found {
+            *self = Layout::Split(split, Box::new(Layout::Pane(id)), Box::new(Layout::Pane(new_id)));
+        }
+        found
+    }
+
+    /// Take the pane `id` out, its sibling taking the space, returning `None` if nothing is left
+    fn remove(self, id: usize) -> Option<Layout> {
+        match self {
+            Layout::Pane(pane) => if pane == id {
+                None
+            } else {
+                Some(Layout::Pane(pane))
+            },
+            Layout::Split(split, first, second) => match ((*first).remove(id), (*second).remove(id)) {
+                (Some(first), Some(second)) => Some(Layout::Split(split, Box::new(first), Box::new(second))),
+                (Some(only), None) | (None, Some(only)) => Some(only),
+                (None, None) => None
+            }
+        }
+    }
+
+    fn panes(&self, ids: &mut Vec<usize>) {
+        match *self {
+            Layout::Pane(id) => ids.push(id),
+            Layout::Split(_, ref first, ref second) => {
+                first.panes(ids);
+                second.panes(ids);
+            }
+        }
+    }
+}
+
+pub struct Tab {
+    pub layout: Layout,
+    /// Pane that gets keyboard input
+    pub focus: usize,
+}
+
+/// The window, holding tabs of split panes and passing events to the focused one
+pub struct Terminal {
+    pub window: Window,
+    config: Config,
+    /// Shell and arguments started in every new pane
+    command: Vec<String>,
+    pub panes: BTreeMap<usize, Pane>,
+    next_id: usize,
+    tabs: Vec<Tab>,
+    active: usize,
+    /// Where each pane of the active tab is drawn in the window
+    places: Vec<(usize, Rect)>,
+    /// Titles shown in the tab bar when it was last drawn
+    titles: Vec<String>,
+    ctrl: bool,
+    shift: bool,
+    mouse_x: i32,
+    mouse_y: i32,
+    /// Pane that gets mouse events while a button is held, so a selection can be dragged outside of it
+    grab: Option<usize>,
+    /// Whether the window has the keyboard
+    focused: bool,
+}
+
+impl Terminal {
+    /// Open the window with a first tab running `command`, recording its output to `record` if given
+    pub fn new(config: Config, command: Vec<String>, width: u32, height: u32, record: Option<&str>) -> Result<Terminal, String> {
+        let window = Window::new_flags(-1, -1, width, height, "Terminal", &[WindowFlag::Async, WindowFlag::Resizable])
+            .ok_or_else(|| "failed to open window".to_string())?;
+
+        let mut pane = Pane::spawn(&config, &command, None, width, height)?;
+        if let Some(path) = record {
+            pane.recorder = Some(Recorder::create(path, pane.console.state.console.w, pane.console.state.console.h, &term(&config))?);
+        }
+
+        let mut terminal = Terminal {
+            window: window,
+            config: config,
+            command: command,
+            panes: BTreeMap::new(),
+            next_id: 1,
+            tabs: vec![Tab { layout: Layout::Pane(0), focus: 0 }],
+            active: 0,
+            places: Vec::new(),
+            titles: Vec::new(),
+            ctrl: false,
+            shift: false,
+            mouse_x: 0,
+            mouse_y: 0,
+            grab: None,
+            focused: true,
+        };
+        terminal.panes.insert(0, pane);
+        terminal.layout();
+        Ok(terminal)
+    }
+
+    /// Whether every tab has been closed
+    pub fn is_empty(&self) -> bool {
+        self.tabs.is_empty()
+    }
+
+    /// Master PTY of every pane, to wait on for output
+    #[cfg(not(target_os = "redox"))]
+    pub fn master_fds(&self) -> Vec<RawFd> {
+        self.panes.values().map(|pane| pane.pty.master_fd).collect()
+    }
+
+    /// Time until a pane on the screen has something to draw, or input the PTY had no room for is to be tried again, or
+    /// None while nothing is due
+    #[cfg(not(target_os = "redox"))]
+    pub fn next_tick(&self) -> Option<Duration> {
+        let mut due = None;
+        for &(id, _) in self.places.iter() {
+            if let Some(pane) = self.panes.get(&id) {
+                if let Some(pane_due) = pane.console.next_tick() {
+                    due = Some(due.map_or(pane_due, |due| cmp::min(due, pane_due)));
+                }
+            }
+        }
+        let waiting = self.panes.values().any(|pane| ! pane.console.state.input.is_empty() || pane.console.state.end_of_input);
+        if waiting {
+            let retry = Duration::from_millis(16);
+            due = Some(due.map_or(retry, |due| cmp::min(due, retry)));
+        }
+        due
+    }
+
+    /// Whether any pane has output that is not drawn yet
+    #[cfg(target_os = "redox")]
+    pub fn pending(&self) -> bool {
+        self.panes.values().any(|pane| pane.console.pending)
+    }
+
+    fn focus(&self) -> Option<usize> {
+        self.tabs.get(self.active).map(|tab| tab.focus)
+    }
+
+    fn place(&self, id: usize) -> Option<Rect> {
+        self.places.iter().find(|&&(pane, _)| pane == id).map(|&(_, rect)| rect)
+    }
+
+    fn pane_at(&self, x: i32, y: i32) -> Option<usize> {
+        self.places.iter().find(|&&(_, rect)| rect.contains(x, y)).map(|&(id, _)| id)
+    }
+
+    fn bar_height(&self) -> u32 {
+        if self.tabs.len() > 1 { TAB_BAR_HEIGHT } else { 0 }
+    }
+
+    /// Area below the tab bar shared by the panes of a tab
+    fn area(&self) -> Rect {
+        let bar_height = self.bar_height();
+        Rect {
+            x: 0,
+            y: bar_height as i32,
+            w: self.window.width(),
+            h: self.window.height().saturating_sub(bar_height),
+        }
+    }
+
+    /// Fit the panes of the active tab to the window and draw everything again
+    fn layout(&mut self) {
+        let mut places = Vec::new();
+        if let Some(tab) = self.tabs.get(self.active) {
+            tab.layout.place(self.area(), &mut places);
+        }
+        for &(id, rect) in places.iter() {
+            if let Some(pane) = self.panes.get_mut(&id) {
+                pane.resize(rect.w, rect.h);
+            }
+        }
+        self.places = places;
+        self.grab = None;
+        self.update_focus();
+        self.draw_all();
+    }
+
+    fn draw_all(&mut self) {
+        let divider = Color { data: self.config.theme.palette[8] };
+        self.window.set(divider);
+
+        for &(id, rect) in self.places.iter() {
+            if let Some(pane) = self.panes.get_mut(&id) {
+                pane.console.redraw();
+                pane.console.changed.clear();
+                let rows = pane.console.image.height() as usize;
+                blit(&mut self.window, &pane.console.image, rect, 0, rows);
+            }
+        }
+
+        self.titles.clear();
+        self.draw_tab_bar();
+        self.set_title();
+        self.window.sync();
+    }
+
+    fn tab_title(&self, tab: &Tab) -> String {
+        self.panes.get(&tab.focus).map(|pane| pane.console.state.title.clone()).unwrap_or(String::new())
+    }
+
+    /// Draw the tab bar if any title changed since it was last drawn, returning whether it did
+    fn draw_tab_bar(&mut self) -> bool {
+        let titles: Vec<String> = self.tabs.iter().map(|tab| self.tab_title(tab)).collect();
+        if titles == self.titles {
+            return false;
+        }
+        self.titles = titles;
+
+        let bar_height = self.bar_height();
+        if bar_height == 0 {
+            return true;
+        }
+
+        let theme = self.config.theme;
+        let width = self.window.width();
+        self.window.rect(0, 0, width, bar_height, Color { data: theme.palette[8] });
+
+        let tab_w = cmp::min(TAB_WIDTH, width / self.titles.len() as u32);
+        for (i, title) in self.titles.iter().enumerate() {
+            let x = i as i32 * tab_w as i32;
+            let bg = if i == self.active { theme.background } else { theme.palette[0] };
+            self.window.rect(x + 1, 0, tab_w.saturating_sub(2), bar_height, Color { data: bg });
+
+            let chars = (tab_w as usize / 8).saturating_sub(2);
+            let y = (bar_height as i32 - 16) / 2;
+            for (j, c) in title.chars().take(chars).enumerate() {
+                self.window.char(x + 8 + j as i32 * 8, y, c, Color { data: theme.foreground });
+            }
+        }
+        true
+    }
+
+    fn set_title(&mut self) {
+        let title = match self.tabs.get(self.active) {
+            Some(tab) => self.tab_title(tab),
+            None => return
+        };
+        if title.is_empty() {
+            self.window.set_title("Terminal");
+        } else {
+            self.window.set_title(&title);
+        }
+    }
+
+    fn select_tab(&mut self, i: usize) {
+        if i < self.tabs.len() && i != self.active {
+            self.active = i;
+            self.layout();
+        }
+    }
+
+    fn set_focus(&mut self, id: usize) {
+        if let Some(tab) = self.tabs.get_mut(self.active) {
+            if tab.focus == id {
+                return;
+            }
+            tab.focus = id;
+        }
+        self.update_focus();
+        self.set_title();
+        self.draw_tab_bar();
+        self.window.sync();
+    }
+
+    /// Draw the cursor hollow in every pane but the focused one, and in that one too while the window is not focused
+    fn update_focus(&mut self) {
+        let focus = self.focus();
+        let focused = self.focused;
+        for (&id, pane) in self.panes.iter_mut() {
+            pane.console.set_focused(focused && Some(id) == focus);
+        }
+    }
+
+    fn spawn(&mut self, width: u32, height: u32) -> Option<usize> {
+        // New tabs and splits start where the shell in the focused pane is, unless that is on another machine
+        let cwd = self.focus().and_then(|focus| self.panes.get(&focus)).and_then(|pane| pane.cwd())
+            .and_then(|cwd| if cwd.is_dir() { Some(cwd) } else { None });
+        match Pane::spawn(&self.config, &self.command, cwd.as_ref().map(|cwd| cwd.as_path()), cmp::max(1, width), cmp::max(1, height)) {
+            Ok(pane) => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.panes.insert(id, pane);
+                Some(id)
+            },
+            Err(err) => {
+                let term_stderr = io::stderr();
+                let mut term_stderr = term_stderr.lock();
+                let _ = term_stderr.write(b"terminal: ");
+                let _ = term_stderr.write(err.as_bytes());
+                let _ = term_stderr.write(b"\n");
+                None
+            }
+        }
+    }
+
+    fn new_tab(&mut self) {
+        let area = self.area();
+        if let Some(id) = self.spawn(area.w, area.h) {
+            self.tabs.push(Tab { layout: Layout::Pane(id), focus: id });
+            self.active = self.tabs.len() - 1;
+            self.layout();
+        }
+    }
+
+    fn split(&mut self, split: Split) {
+        let focus = match self.focus() {
+            Some(focus) => focus,
+            None => return
+        };
+        let rect = self.place(focus).unwrap_or(self.area());
+        let (width, height) = match split {
+            Split::Columns => (rect.w.saturating_sub(DIVIDER) / 2, rect.h),
+            Split::Rows => (rect.w, rect.h.saturating_sub(DIVIDER) / 2),
+        };
+        if let Some(id) = self.spawn(width, height) {
+            let tab = &mut self.tabs[self.active];
+            tab.layout.split(focus, id, split);
+            tab.focus = id;
+        }
+        self.layout();
+    }
+
+    /// Close a pane, and its tab with it if it was the last one there
+    pub fn close(&mut self, id: usize) {
+        self.panes.remove(&id);
+
+        let mut closed_tab = None;
+        for (i, tab) in self.tabs.iter_mut().enumerate() {
+            let mut ids = Vec::new();
+            tab.layout.panes(&mut ids);
+            if ! ids.contains(&id) {
+                continue;
+            }
+
+            let layout = mem::replace(&mut tab.layout, Layout::Pane(id));
+            match layout.remove(id) {
+                Some(layout) => {
+                    if tab.focus == id {
+                        let mut ids = Vec::new();
+                        layout.panes(&mut ids);
+                        tab.focus = ids[0];
+                    }
+                    tab.layout = layout;
+                },
+                None => closed_tab = Some(i)
+            }
+            break;
+        }
+
+        if let Some(i) = closed_tab {
+            self.tabs.remove(i);
+            if self.active > i || self.active >= self.tabs.len() {
+                self.active = self.active.saturating_sub(1);
+            }
+        }
+
+        if ! self.tabs.is_empty() {
+            self.layout();
+        }
+    }
+
+    /// Move the keyboard focus to the nearest pane in the direction of `dx`, `dy`
+    fn move_focus(&mut self, dx: i32, dy: i32) {
+        let focus = match self.focus() {
+            Some(focus) => focus,
+            None => return
+        };
+        let rect = match self.place(focus) {
+            Some(rect) => rect,
+            None => return
+        };
+        let center = |rect: &Rect| (rect.x + rect.w as i32 / 2, rect.y + rect.h as i32 / 2);
+        let (cx, cy) = center(&rect);
+
+        let mut best: Option<(i32, usize)> = None;
+        for &(id, other) in self.places.iter() {
+            let beyond = (dx > 0 && other.x >= rect.x + rect.w as i32)
+                || (dx < 0 && other.x + other.w as i32 <= rect.x)
+                || (dy > 0 && other.y >= rect.y + rect.h as i32)
+                || (dy < 0 && other.y + other.h as i32 <= rect.y);
+            if id == focus || ! beyond {
+                continue;
+            }
+            let (ox, oy) = center(&other);
+            let distance = (ox - cx).abs() + (oy - cy).abs();
+            if best.map_or(true, |(best_distance, _)| distance < best_distance) {
+                best = Some((distance, id));
+            }
+        }
+
+        if let Some((_, id)) = best {
+            self.set_focus(id);
+        }
+    }
+
+    /// Handle the key bindings of the window itself, returning whether the key was one of them.
+    ///
+    /// Arrows, Page Up and Page Down with modifiers are sent to the program as xterm encodes them, so the bindings are
+    /// on letters and Tab, which Shift does not change the encoding of.
+    fn shortcut(&mut self, scancode: u8) -> bool {
+        if self.ctrl && self.shift {
+            match scancode {
+                // Ctrl+Shift+T
+                0x14 => self.new_tab(),
+                // Ctrl+Shift+W
+                0x11 => if let Some(focus) = self.focus() {
+                    self.close(focus);
+                },
+                // Ctrl+Shift+E
+                0x12 => self.split(Split::Columns),
+                // Ctrl+Shift+O
+                0x18 => self.split(Split::Rows),
+                // Ctrl+Shift+C
+                0x2E => {
+                    let text = match self.focus().and_then(|focus| self.panes.get(&focus)) {
+                        Some(pane) => pane.console.selected_text(),
+                        None => String::new()
+                    };
+                    if ! text.is_empty() {
+                        self.window.set_clipboard(&text);
+                    }
+                },
+                // Ctrl+Shift+V
+                0x2F => {
+                    let text = self.window.clipboard();
+                    if let Some(pane) = self.focus().and_then(|focus| self.panes.get_mut(&focus)) {
+                        pane.console.paste(&text);
+                    }
+                },
+                // Ctrl+Shift+F
+                0x21 => if let Some(pane) = self.focus().and_then(|focus| self.panes.get_mut(&focus)) {
+                    pane.console.toggle_search();
+                },
+                // Ctrl+Shift+H, J, K and L
+                0x23 => self.move_focus(-1, 0),
+                0x24 => self.move_focus(0, 1),
+                0x25 => self.move_focus(0, -1),
+                0x26 => self.move_focus(1, 0),
+                // Ctrl+Shift+Tab
+                0x0F => {
+                    let i = (self.active + self.tabs.len() - 1) % self.tabs.len();
+                    self.select_tab(i);
+                },
+                _ => return false
+            }
+            true
+        } else if self.ctrl {
+            match scancode {
+                // Ctrl+Tab
+                0x0F => {
+                    let i = (self.active + 1) % self.tabs.len();
+                    self.select_tab(i);
+                },
+                _ => return false
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn pane_input(&mut self, id: usize, event_option: EventOption) {
+        if let Some(pane) = self.panes.get_mut(&id) {
+            // Only the focused pane sees the modifier keys, but a Ctrl+click or Shift+click can go to any of them
+            if let EventOption::Button(_) = event_option {
+                pane.console.state.ctrl = self.ctrl;
+                pane.console.state.shift = self.shift;
+            }
+
+            pane.console.input(event_option);
+
+            if let Some(target) = pane.console.open.take() {
+                let cwd = pane.cwd();
+                links::open(&target, cwd.as_ref().map(|cwd| cwd.as_path()));
+            }
+        }
+    }
+
+    /// Handle one window event, returning false when the window is closed
+    pub fn event(&mut self, event_option: EventOption) -> bool {
+        match event_option {
+            EventOption::Key(key_event) => {
+                if key_event.scancode == 0x1D {
+                    self.ctrl = key_event.pressed;
+                } else if key_event.scancode == 0x2A || key_event.scancode == 0x36 {
+                    self.shift = key_event.pressed;
+                }
+
+                if key_event.pressed && self.shortcut(key_event.scancode) {
+                    return true;
+                }
+
+                if let Some(focus) = self.focus() {
+                    self.pane_input(focus, event_option);
+                }
+            },
+            EventOption::Mouse(mouse_event) => {
+                self.mouse_x = mouse_event.x;
+                self.mouse_y = mouse_event.y;
+
+                if let Some(id) = self.grab.or_else(|| self.pane_at(mouse_event.x, mouse_event.y)) {
+                    if let Some(rect) = self.place(id) {
+                        self.pane_input(id, EventOption::Mouse(MouseEvent {
+                            x: mouse_event.x - rect.x,
+                            y: mouse_event.y - rect.y,
+                        }));
+                    }
+                }
+            },
+            EventOption::Button(button_event) => {
+                let pressed = button_event.left || button_event.middle || button_event.right;
+                if pressed && self.grab.is_none() {
+                    if self.mouse_y < self.bar_height() as i32 {
+                        let tab_w = cmp::min(TAB_WIDTH, self.window.width() / self.tabs.len() as u32);
+                        let i = self.mouse_x as usize / cmp::max(1, tab_w) as usize;
+                        self.select_tab(i);
+                        return true;
+                    }
+
+                    self.grab = self.pane_at(self.mouse_x, self.mouse_y);
+                    if let Some(id) = self.grab {
+                        self.set_focus(id);
+                    }
+                }
+
+                if let Some(id) = self.grab {
+                    self.pane_input(id, event_option);
+                }
+
+                if ! pressed {
+                    self.grab = None;
+                }
+            },
+            EventOption::Scroll(_) => if let Some(id) = self.pane_at(self.mouse_x, self.mouse_y) {
+                self.pane_input(id, event_option);
+            },
+            EventOption::Resize(_) => self.layout(),
+            EventOption::Focus(focus_event) => {
+                self.focused = focus_event.focused;
+                self.update_focus();
+            },
+            EventOption::Quit(_) => return false,
+            _ => ()
+        }
+        true
+    }
+
+    /// Take in output from the pane with the master PTY `fd`, closing it once its shell has gone
+    pub fn read(&mut self, fd: RawFd, event_count: usize) {
+        let id = match self.panes.iter().find(|&(_, pane)| pane.pty.master_fd == fd) {
+            Some((&id, _)) => id,
+            None => {
+                println!("Unknown event {}", fd);
+                return;
+            }
+        };
+
+        let open = match self.panes.get_mut(&id) {
+            Some(pane) => pane.read(event_count),
+            None => true
+        };
+        if ! open {
+            self.close(id);
+        }
+    }
+
+    /// Carry out what the programs asked for with OSC sequences, answers going out with the rest of their input
+    fn requests(&mut self) {
+        for pane in self.panes.values_mut() {
+            for request in mem::replace(&mut pane.console.state.requests, Vec::new()) {
+                match request {
+                    Request::SetClipboard(text) => self.window.set_clipboard(&text),
+                    Request::ReadClipboard(selections) => {
+                        let text = self.window.clipboard();
+                        let reply = format!("\x1B]52;{};{}\x1B\\", selections, kitty::base64_encode(text.as_bytes()));
+                        pane.console.state.input.extend_from_slice(reply.as_bytes());
+                    },
+                    Request::Notify(title, body) => notify(&self.config, &title, &body)
+                }
+            }
+        }
+    }
+
+    /// Send input to the shells, close the panes of those that exited and copy what changed to the window.
+    ///
+    /// Output is drawn at most once a frame unless `tick` is set, which also drives blinking.
+    pub fn update(&mut self, tick: bool) {
+        self.requests();
+
+        let mut closed = Vec::new();
+        for (&id, pane) in self.panes.iter_mut() {
+            if ! pane.flush() || pane.exited() {
+                closed.push(id);
+            }
+        }
+        for id in closed {
+            self.close(id);
+        }
+        if self.tabs.is_empty() {
+            return;
+        }
+
+        let mut changed = false;
+        for &(id, rect) in self.places.iter() {
+            if let Some(pane) = self.panes.get_mut(&id) {
+                if tick {
+                    pane.console.tick();
+                } else if pane.console.redraw_time.elapsed() >= Duration::from_millis(16) {
+                    pane.console.redraw();
+                }
+
+                let cell_h = pane.console.fonts.cell_h;
+                for &row in pane.console.changed.iter() {
+                    blit(&mut self.window, &pane.console.image, rect, row * cell_h, (row + 1) * cell_h);
+                    changed = true;
+                }
+                pane.console.changed.clear();
+            }
+        }
+
+        if self.draw_tab_bar() {
+            self.set_title();
+            changed = true;
+        }
+
+        if changed {
+            self.window.sync();
+        }
+    }
+}
+
+/// Copy pixel rows `start..end` of a pane's image into its place in the window
+pub fn blit(window: &mut Window, image: &Image, rect: Rect, start: usize, end: usize) {
+    let window_w = window.width() as usize;
+    let window_h = window.height() as usize;
+    let image_w = image.width() as usize;
+    let image_h = image.height() as usize;
+    let (x, y) = (rect.x as usize, rect.y as usize);
+    if x >= window_w {
+        return;
+    }
+    let w = cmp::min(cmp::min(image_w, rect.w as usize), window_w - x);
+    let end = cmp::min(cmp::min(end, image_h), cmp::min(rect.h as usize, window_h.saturating_sub(y)));
+
+    let src = image.data();
+    let dst = window.data_mut();
+    for row in start..end {
+        let to = (y + row) * window_w + x;
+        let from = row * image_w;
+        dst[to..to + w].copy_from_slice(&src[from..from + w]);
+    }
+}
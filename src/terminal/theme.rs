@@ -0,0 +1,122 @@
+/// Colours used to draw the terminal, as `0xAARRGGBB` like the cells in the grid
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub foreground: u32,
+    pub background: u32,
+    pub cursor: u32,
+    /// The 16 standard colours: black, red, green, yellow, blue, magenta, cyan, white, then their bright forms
+    pub palette: [u32; 16],
+}
+
+/// The VGA colours ransid uses by default
+const DEFAULT_PALETTE: [u32; 16] = [
+    0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0xC0C0C0,
+    0x808080, 0xFF0000, 0x00FF00, 0xFFFF00, 0x0000FF, 0xFF00FF, 0x00FFFF, 0xFFFFFF,
+];
+
+const TANGO_PALETTE: [u32; 16] = [
+    0x2E3436, 0xCC0000, 0x4E9A06, 0xC4A000, 0x3465A4, 0x75507B, 0x06989A, 0xD3D7CF,
+    0x555753, 0xEF2929, 0x8AE234, 0xFCE94F, 0x729FCF, 0xAD7FA8, 0x34E2E2, 0xEEEEEC,
+];
+
+const SOLARIZED_PALETTE: [u32; 16] = [
+    0x073642, 0xDC322F, 0x859900, 0xB58900, 0x268BD2, 0xD33682, 0x2AA198, 0xEEE8D5,
+    0x002B36, 0xCB4B16, 0x586E75, 0x657B83, 0x839496, 0x6C71C4, 0x93A1A1, 0xFDF6E3,
+];
+
+fn opaque(palette: [u32; 16]) -> [u32; 16] {
+    let mut colors = palette;
+    for color in colors.iter_mut() {
+        *color |= 0xFF000000;
+    }
+    colors
+}
+
+impl Theme {
+    pub fn names() -> &'static [&'static str] {
+        &["default", "tango", "solarized-dark", "solarized-light"]
+    }
+
+    pub fn named(name: &str) -> Option<Theme> {
+        let (foreground, background, cursor, palette) = match name {
+            "default" => (0xC0C0C0, 0x000000, 0xFFFFFF, DEFAULT_PALETTE),
+            "tango" => (0xD3D7CF, 0x2E3436, 0xEEEEEC, TANGO_PALETTE),
+            "solarized-dark" => (0x839496, 0x002B36, 0x93A1A1, SOLARIZED_PALETTE),
+            "solarized-light" => (0x657B83, 0xFDF6E3, 0x586E75, SOLARIZED_PALETTE),
+            _ => return None
+        };
+
+        Some(Theme {
+            foreground: foreground | 0xFF000000,
+            background: background | 0xFF000000,
+            cursor: cursor | 0xFF000000,
+            palette: opaque(palette),
+        })
+    }
+}
+
+/// Parse a colour written as `#RRGGBB`
+pub fn parse_color(value: &str) -> Result<u32, String> {
+    let hex = value.trim_left_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("expected #RRGGBB, found {}", value));
+    }
+    u32::from_str_radix(hex, 16).map(|rgb| rgb | 0xFF000000).map_err(|err| format!("{}: {}", value, err))
+}
+
+/// Parse a colour as programs give it in OSC 4, 10 and 11: `rgb:R/G/B` with one to four hex digits for each
+/// component, or `#RGB` with one to four for each
+pub fn parse_xcolor(spec: &str) -> Option<u32> {
+    // Anything but hex digits and separators is refused before slicing, as the program may send any UTF-8
+    let hex = |b: u8| match b { b'0' ... b'9' | b'a' ... b'f' | b'A' ... b'F' => true, _ => false };
+    let components: Vec<&str> = if spec.starts_with("rgb:") {
+        if ! spec[4..].bytes().all(|b| hex(b) || b == b'/') {
+            return None;
+        }
+        spec[4..].split('/').collect()
+    } else if spec.starts_with('#') && spec.len() > 1 && (spec.len() - 1) % 3 == 0 && spec[1..].bytes().all(|b| hex(b)) {
+        let digits = (spec.len() - 1) / 3;
+        (0..3).map(|i| &spec[1 + i * digits..1 + (i + 1) * digits]).collect()
+    } else {
+        return None;
+    };
+    if components.len() != 3 {
+        return None;
+    }
+
+    let mut color = 0xFF000000;
+    for (i, component) in components.iter().enumerate() {
+        if component.is_empty() || component.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(component, 16).ok()?;
+        // Scaled to a byte, so `f`, `ff` and `ffff` are all full
+        let max = (1 << (component.len() * 4)) - 1;
+        color |= ((value * 0xFF + max / 2) / max) << (16 - i * 8);
+    }
+    Some(color)
+}
+
+/// Write a colour the way xterm answers a query for it, `rgb:RRRR/GGGG/BBBB`
+pub fn format_xcolor(color: u32) -> String {
+    let (r, g, b) = ((color >> 16) & 0xFF, (color >> 8) & 0xFF, color & 0xFF);
+    format!("rgb:{:04x}/{:04x}/{:04x}", r * 0x101, g * 0x101, b * 0x101)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_xcolor, parse_xcolor};
+
+    #[test]
+    fn xcolor() {
+        assert_eq!(parse_xcolor("rgb:ff/80/0"), Some(0xFFFF8000));
+        assert_eq!(parse_xcolor("rgb:ffff/8080/0000"), Some(0xFFFF8000));
+        assert_eq!(parse_xcolor("#f80"), Some(0xFFFF8800));
+        assert_eq!(format_xcolor(0xFFFF8000), "rgb:ffff/8080/0000");
+
+        assert_eq!(parse_xcolor("#aé"), None);
+        assert_eq!(parse_xcolor("rgb:é/0/0"), None);
+        assert_eq!(parse_xcolor("rgb:+f/0/0"), None);
+        assert_eq!(parse_xcolor("red"), None);
+    }
+}